@@ -0,0 +1,472 @@
+//! HTTP REST API over the inventory, scanning and GATT operations. Every endpoint answers
+//! with JSON; errors are `{"error": "..."}` with a matching status code.
+//!
+//! - `GET /devices`, `GET /devices/{device}`: inventory entries
+//! - `POST /scan`: starts a background scan, `GET /scan/{job}` reports its state
+//! - `GET /devices/{device}/rssi`: recent RSSI samples, smoothed values and estimated distance
+//! - `GET /devices/{device}/services`: discovered services and characteristics
+//! - `GET /devices/{device}/gatt?read_values=true`: full GATT table, optionally with values
+//! - `GET|PUT /devices/{device}/characteristics/{service}/{characteristic}`: read or write a value
+//! - `GET /devices/{device}/mj-ht-v1/readings`: temperature and humidity notifications
+//!
+//! `{device}` is an internal ID or a MAC address.
+
+use crate::assigned_numbers;
+use crate::bluetooth_manager::BluetoothManager;
+use crate::device_info::{format_hex, BluetoothDevice, ServiceInfo};
+use crate::device_storage::DeviceStorage;
+use crate::error::BluetoothError;
+use crate::gatt_decode::DecodedValue;
+use crate::gatt_export::GattProfile;
+use crate::mj_ht_v1::MjHtV1Reading;
+use crate::rssi::{RssiSample, SignalSummary};
+use crate::value_format::ValueFormat;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use btleplug::api::WriteType;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+#[derive(Clone)]
+pub struct ApiState {
+    pub manager: BluetoothManager,
+    pub storage: Arc<Mutex<DeviceStorage>>,
+    jobs: Arc<Mutex<BTreeMap<u32, ScanJob>>>,
+}
+
+impl ApiState {
+    pub fn new(manager: BluetoothManager, storage: Arc<Mutex<DeviceStorage>>) -> Self {
+        ApiState { manager, storage, jobs: Arc::new(Mutex::new(BTreeMap::new())) }
+    }
+}
+
+pub fn router(state: ApiState) -> Router {
+    Router::new()
+        .route("/devices", get(list_devices))
+        .route("/devices/:device", get(get_device))
+        .route("/devices/:device/rssi", get(rssi_history))
+        .route("/devices/:device/services", get(discover_services))
+        .route("/devices/:device/gatt", get(export_gatt))
+        .route(
+            "/devices/:device/characteristics/:service/:characteristic",
+            get(read_characteristic).put(write_characteristic),
+        )
+        .route("/devices/:device/mj-ht-v1/readings", get(mj_ht_v1_readings))
+        .route("/scan", post(start_scan))
+        .route("/scan/:job", get(scan_status))
+        .with_state(state)
+}
+
+/// Error answered as `{"error": message}`, with a `"hint"` on what to do about it when
+/// there is one.
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+    hint: Option<String>,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError { status, message: message.into(), hint: None }
+    }
+}
+
+impl From<BluetoothError> for ApiError {
+    fn from(error: BluetoothError) -> Self {
+        let status = match &error {
+            BluetoothError::DeviceNotFound(_) | BluetoothError::NotSeen { .. } | BluetoothError::CharacteristicNotFound { .. } => {
+                StatusCode::NOT_FOUND
+            }
+            BluetoothError::NotifyNotSupported { .. } | BluetoothError::WriteNotSupported { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            BluetoothError::NoAdapter(_) => StatusCode::SERVICE_UNAVAILABLE,
+            BluetoothError::Timeout { .. } | BluetoothError::Elapsed { .. } => StatusCode::GATEWAY_TIMEOUT,
+            _ => StatusCode::BAD_GATEWAY,
+        };
+        ApiError { status, message: error.to_string(), hint: error.hint() }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut body = json!({ "error": self.message });
+        if let Some(hint) = self.hint {
+            body["hint"] = json!(hint);
+        }
+        (self.status, Json(body)).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+#[derive(Serialize)]
+struct DeviceEntry {
+    id: u32,
+    #[serde(flatten)]
+    device: BluetoothDevice,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signal: Option<SignalSummary>,
+    /// Whether presence detection counts the device as around
+    present: bool,
+}
+
+async fn list_devices(State(state): State<ApiState>) -> ApiResult<Vec<DeviceEntry>> {
+    let storage = state.storage.lock().await;
+    let devices = storage
+        .list_devices()
+        .into_iter()
+        .map(|(id, device)| DeviceEntry { id, device: device.clone(), signal: storage.signal(id), present: storage.is_present(id) })
+        .collect();
+    Ok(Json(devices))
+}
+
+async fn get_device(State(state): State<ApiState>, Path(selector): Path<String>) -> ApiResult<DeviceEntry> {
+    let (id, device) = resolve(&state, &selector).await?;
+    let storage = state.storage.lock().await;
+    Ok(Json(DeviceEntry { id, device, signal: storage.signal(id), present: storage.is_present(id) }))
+}
+
+#[derive(Serialize)]
+struct RssiHistoryEntry {
+    samples: Vec<RssiSample>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signal: Option<SignalSummary>,
+}
+
+async fn rssi_history(State(state): State<ApiState>, Path(selector): Path<String>) -> ApiResult<RssiHistoryEntry> {
+    let (id, _) = resolve(&state, &selector).await?;
+    let storage = state.storage.lock().await;
+    let samples = storage.rssi_history(id).map(|history| history.samples().copied().collect()).unwrap_or_default();
+    Ok(Json(RssiHistoryEntry { samples, signal: storage.signal(id) }))
+}
+
+async fn discover_services(State(state): State<ApiState>, Path(selector): Path<String>) -> ApiResult<Vec<ServiceInfo>> {
+    let (_, device) = resolve(&state, &selector).await?;
+    let services = state.manager.with_connection(Arc::new(device), |device| async move { device.fetch_services().await }).await?;
+    Ok(Json(services))
+}
+
+#[derive(Deserialize)]
+struct GattQuery {
+    #[serde(default)]
+    read_values: bool,
+}
+
+async fn export_gatt(
+    State(state): State<ApiState>,
+    Path(selector): Path<String>,
+    Query(query): Query<GattQuery>,
+) -> ApiResult<GattProfile> {
+    let (_, device) = resolve(&state, &selector).await?;
+    let profile = state
+        .manager
+        .with_connection(Arc::new(device), |device| async move { device.gatt_profile(query.read_values).await })
+        .await?;
+    Ok(Json(profile))
+}
+
+#[derive(Serialize)]
+struct CharacteristicValue {
+    service: String,
+    characteristic: String,
+    /// Value as lowercase hex
+    hex: String,
+    /// Value as text, when it is valid UTF-8
+    utf8: Option<String>,
+    /// Value of a standard characteristic, decoded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decoded: Option<DecodedValue>,
+}
+
+async fn read_characteristic(
+    State(state): State<ApiState>,
+    Path((selector, service, characteristic)): Path<(String, String, String)>,
+) -> ApiResult<CharacteristicValue> {
+    let (_, device) = resolve(&state, &selector).await?;
+    let (service, characteristic) = parse_uuids(&service, &characteristic)?;
+    let manager = &state.manager;
+    let (value, decoded) = manager
+        .with_connection(Arc::new(device), |device| {
+            let (service, characteristic) = (service.clone(), characteristic.clone());
+            async move {
+                let value = device.read_characteristic(&service, &characteristic).await?;
+                let decoded = manager.decode_value(&device, &service, &characteristic, &value).await?;
+                Ok((value, decoded))
+            }
+        })
+        .await?;
+    Ok(Json(CharacteristicValue {
+        hex: format_hex(&value),
+        decoded,
+        utf8: String::from_utf8(value).ok(),
+        service,
+        characteristic,
+    }))
+}
+
+/// Service and characteristic given by UUID, short UUID or name, as lowercase hyphenated UUIDs.
+fn parse_uuids(service: &str, characteristic: &str) -> Result<(String, String), ApiError> {
+    let service = assigned_numbers::parse_service_uuid(service).map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e))?;
+    let characteristic = assigned_numbers::parse_characteristic_uuid(characteristic).map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e))?;
+    Ok((service.to_string(), characteristic.to_string()))
+}
+
+#[derive(Deserialize)]
+struct WriteRequest {
+    value: String,
+    /// How `value` is encoded, hex by default
+    #[serde(default)]
+    format: ValueFormat,
+    #[serde(default = "default_with_response")]
+    with_response: bool,
+}
+
+fn default_with_response() -> bool {
+    true
+}
+
+async fn write_characteristic(
+    State(state): State<ApiState>,
+    Path((selector, service, characteristic)): Path<(String, String, String)>,
+    Json(request): Json<WriteRequest>,
+) -> Result<StatusCode, ApiError> {
+    let value = request.format.encode(&request.value).map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e))?;
+    let write_type = if request.with_response { WriteType::WithResponse } else { WriteType::WithoutResponse };
+
+    let (_, device) = resolve(&state, &selector).await?;
+    let (service, characteristic) = parse_uuids(&service, &characteristic)?;
+    state
+        .manager
+        .with_connection(Arc::new(device), |device| async move {
+            device.write_characteristic(&service, &characteristic, &value, write_type).await
+        })
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct ReadingsQuery {
+    /// Number of readings to wait for
+    #[serde(default = "default_count")]
+    count: usize,
+    /// Seconds to wait at most; the readings received so far are returned
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+}
+
+fn default_count() -> usize {
+    1
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+async fn mj_ht_v1_readings(
+    State(state): State<ApiState>,
+    Path(selector): Path<String>,
+    Query(query): Query<ReadingsQuery>,
+) -> ApiResult<Vec<MjHtV1Reading>> {
+    let (id, device) = resolve(&state, &selector).await?;
+    let readings = state
+        .manager
+        .with_connection(Arc::new(device), |device| async move {
+            let stream = device.mj_ht_v1_readings().await?;
+            let timeout = tokio::time::sleep(Duration::from_secs(query.timeout));
+            let readings: Vec<MjHtV1Reading> = stream.take(query.count).take_until(timeout).collect().await;
+            if readings.len() < query.count {
+                warn!("Timed out waiting for readings from {}, got {} of {}", device.mac_address, readings.len(), query.count);
+            }
+            device.unsubscribe_from_mj_ht_v1_notifications().await?;
+            Ok(readings)
+        })
+        .await?;
+    if let Some(reading) = readings.last() {
+        state.storage.lock().await.record_mj_ht_v1_reading(id, reading);
+    }
+    Ok(Json(readings))
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum JobState {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ScanJob {
+    id: u32,
+    state: JobState,
+    started: DateTime<Utc>,
+    finished: Option<DateTime<Utc>>,
+    /// Devices in the inventory once the scan finished
+    devices: Option<usize>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ScanRequest {
+    #[serde(default = "default_duration")]
+    duration: u8,
+    #[serde(default = "default_attempts")]
+    attempts: u8,
+}
+
+fn default_duration() -> u8 {
+    5
+}
+
+fn default_attempts() -> u8 {
+    1
+}
+
+/// Starts a scan in the background. Only one scan runs at a time.
+async fn start_scan(State(state): State<ApiState>, request: Option<Json<ScanRequest>>) -> Result<(StatusCode, Json<ScanJob>), ApiError> {
+    let request = request.map(|Json(request)| request).unwrap_or(ScanRequest { duration: default_duration(), attempts: default_attempts() });
+
+    let mut jobs = state.jobs.lock().await;
+    if let Some(running) = jobs.values().find(|job| matches!(job.state, JobState::Running)) {
+        return Err(ApiError::new(StatusCode::CONFLICT, format!("Scan {} is still running", running.id)));
+    }
+    let id = jobs.keys().next_back().map_or(1, |id| id + 1);
+    let job = ScanJob { id, state: JobState::Running, started: Utc::now(), finished: None, devices: None, error: None };
+    jobs.insert(id, job.clone());
+    drop(jobs);
+
+    info!("Starting scan job {} ({} x {} seconds)", id, request.attempts, request.duration);
+    tokio::spawn(async move {
+        let result = run_scan(&state, request.duration, request.attempts).await;
+        let devices = state.storage.lock().await.list_devices().len();
+        if let Some(job) = state.jobs.lock().await.get_mut(&id) {
+            job.finished = Some(Utc::now());
+            match result {
+                Ok(()) => {
+                    job.state = JobState::Completed;
+                    job.devices = Some(devices);
+                }
+                Err(message) => {
+                    warn!("Scan job {} failed: {}", id, message);
+                    job.state = JobState::Failed;
+                    job.error = Some(message);
+                }
+            }
+        }
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// Scans with `BluetoothManager::scan`, which only locks the inventory to store a device.
+async fn run_scan(state: &ApiState, duration: u8, attempts: u8) -> Result<(), String> {
+    let mut storage = state.storage.clone();
+    state.manager.scan(&mut storage, duration, attempts).await.map_err(|e| e.to_string())?;
+    if let Err(e) = storage.lock().await.save() {
+        warn!("Failed to save the inventory: {}", e);
+    }
+    Ok(())
+}
+
+async fn scan_status(State(state): State<ApiState>, Path(id): Path<u32>) -> ApiResult<ScanJob> {
+    let jobs = state.jobs.lock().await;
+    let job = jobs.get(&id).ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, format!("Scan {} not found", id)))?;
+    Ok(Json(job.clone()))
+}
+
+/// Looks up a device by ID or MAC address and returns a copy, so the inventory is not
+/// locked during GATT operations.
+async fn resolve(state: &ApiState, selector: &str) -> Result<(u32, BluetoothDevice), ApiError> {
+    let storage = state.storage.lock().await;
+    storage
+        .find_device(selector)
+        .and_then(|id| Some((id, storage.get_device(id)?.clone())))
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, format!("Device '{}' not found", selector)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::simulated::{SimulatedAdapter, SimulatedBackend, SimulatedPeripheral};
+    use crate::bluetooth_manager::AdapterSelection;
+    use axum::body::Body;
+    use axum::http::Request;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    const MAC: &str = "4C:65:A8:D0:00:01";
+
+    async fn app() -> Router {
+        let backend = SimulatedBackend::new().with_adapter(SimulatedAdapter::new("hci0").with_peripheral(SimulatedPeripheral::new(MAC).with_name("MJ_HT_V1")));
+        let manager = BluetoothManager::with_backend(&backend, AdapterSelection::First).await.unwrap();
+        router(ApiState::new(manager, Arc::new(Mutex::new(DeviceStorage::new()))))
+    }
+
+    async fn send(app: &Router, method: &str, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+        let request = Request::builder().method(method).uri(uri);
+        let request = match body {
+            Some(body) => request.header("content-type", "application/json").body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        };
+        let response = app.clone().oneshot(request.unwrap()).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn unknown_devices_are_not_found() {
+        let app = app().await;
+        let (status, body) = send(&app, "GET", "/devices/AA:BB:CC:DD:EE:FF", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "Device 'AA:BB:CC:DD:EE:FF' not found");
+    }
+
+    #[tokio::test]
+    async fn bad_values_are_rejected() {
+        let app = app().await;
+        let uri = format!("/devices/{}/characteristics/180f/2a19", MAC);
+        let (status, body) = send(&app, "PUT", &uri, Some(json!({ "value": "0xzz" }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].is_string(), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn one_scan_runs_at_a_time() {
+        let app = app().await;
+        let (status, job) = send(&app, "POST", "/scan", Some(json!({ "duration": 1 }))).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(job["state"], "running");
+        let (status, body) = send(&app, "POST", "/scan", None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"], "Scan 1 is still running");
+
+        let uri = format!("/scan/{}", job["id"]);
+        assert_eq!(send(&app, "GET", &uri, None).await.1["state"], "running");
+        let mut job = Value::Null;
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            job = send(&app, "GET", &uri, None).await.1;
+            if job["state"] != "running" {
+                break;
+            }
+        }
+        assert_eq!(job["state"], "completed", "{}", job);
+        assert_eq!(job["devices"], 1);
+        let (status, device) = send(&app, "GET", &format!("/devices/{}", MAC), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(device["id"], 1);
+
+        let (status, _) = send(&app, "GET", "/scan/7", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
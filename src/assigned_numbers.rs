@@ -0,0 +1,683 @@
+//! Bluetooth SIG assigned numbers: names of the standard services, characteristics and
+//! descriptors, of common company identifiers, of appearance values and of units.
+//!
+//! The tables are embedded so names resolve offline. They cover the GATT based
+//! specifications and the companies and member UUIDs seen most often; anything else is
+//! shown as its raw number.
+//!
+//! Wherever a UUID is typed in, `parse_uuid` also accepts the 16 or 32-bit short form
+//! (`2a19`, `0x2A19`) or the name (`Battery Level`, `battery_level`).
+
+use std::fmt;
+use uuid::Uuid;
+
+/// The Bluetooth base UUID, `0000xxxx-0000-1000-8000-00805f9b34fb`, that short UUIDs expand into.
+const BASE_UUID: u128 = 0x00000000_0000_1000_8000_00805f9b34fb;
+
+/// Which table a UUID belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UuidKind {
+    Service,
+    Characteristic,
+    Descriptor,
+}
+
+impl fmt::Display for UuidKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UuidKind::Service => write!(f, "service"),
+            UuidKind::Characteristic => write!(f, "characteristic"),
+            UuidKind::Descriptor => write!(f, "descriptor"),
+        }
+    }
+}
+
+const SERVICES: &[(u32, &str)] = &[
+    (0x1800, "Generic Access"),
+    (0x1801, "Generic Attribute"),
+    (0x1802, "Immediate Alert"),
+    (0x1803, "Link Loss"),
+    (0x1804, "Tx Power"),
+    (0x1805, "Current Time Service"),
+    (0x1806, "Reference Time Update Service"),
+    (0x1807, "Next DST Change Service"),
+    (0x1808, "Glucose"),
+    (0x1809, "Health Thermometer"),
+    (0x180a, "Device Information"),
+    (0x180d, "Heart Rate"),
+    (0x180e, "Phone Alert Status Service"),
+    (0x180f, "Battery Service"),
+    (0x1810, "Blood Pressure"),
+    (0x1811, "Alert Notification Service"),
+    (0x1812, "Human Interface Device"),
+    (0x1813, "Scan Parameters"),
+    (0x1814, "Running Speed and Cadence"),
+    (0x1815, "Automation IO"),
+    (0x1816, "Cycling Speed and Cadence"),
+    (0x1818, "Cycling Power"),
+    (0x1819, "Location and Navigation"),
+    (0x181a, "Environmental Sensing"),
+    (0x181b, "Body Composition"),
+    (0x181c, "User Data"),
+    (0x181d, "Weight Scale"),
+    (0x181e, "Bond Management Service"),
+    (0x181f, "Continuous Glucose Monitoring"),
+    (0x1820, "Internet Protocol Support Service"),
+    (0x1821, "Indoor Positioning"),
+    (0x1822, "Pulse Oximeter Service"),
+    (0x1823, "HTTP Proxy"),
+    (0x1824, "Transport Discovery"),
+    (0x1825, "Object Transfer Service"),
+    (0x1826, "Fitness Machine"),
+    (0x1827, "Mesh Provisioning Service"),
+    (0x1828, "Mesh Proxy Service"),
+    (0x1829, "Reconnection Configuration"),
+    (0x183a, "Insulin Delivery"),
+    (0x183b, "Binary Sensor"),
+    (0x183c, "Emergency Configuration"),
+    (0x183e, "Physical Activity Monitor"),
+    (0x1843, "Audio Input Control"),
+    (0x1844, "Volume Control"),
+    (0x1845, "Volume Offset Control"),
+    (0x1846, "Coordinated Set Identification"),
+    (0x1847, "Device Time"),
+    (0x1848, "Media Control Service"),
+    (0x1849, "Generic Media Control Service"),
+    (0x184a, "Constant Tone Extension"),
+    (0x184b, "Telephone Bearer Service"),
+    (0x184c, "Generic Telephone Bearer Service"),
+    (0x184d, "Microphone Control"),
+    (0x184e, "Audio Stream Control Service"),
+    (0x184f, "Broadcast Audio Scan Service"),
+    (0x1850, "Published Audio Capabilities Service"),
+    (0x1851, "Basic Audio Announcement Service"),
+    (0x1852, "Broadcast Audio Announcement Service"),
+    (0x1853, "Common Audio"),
+    (0x1854, "Hearing Access"),
+    (0x1855, "Telephony and Media Audio"),
+    (0x1856, "Public Broadcast Announcement"),
+    // 16-bit UUIDs assigned to members, used for advertised service data
+    (0xfcd2, "BTHome"),
+    (0xfd6f, "Exposure Notification"),
+    (0xfe95, "Xiaomi MiBeacon"),
+    (0xfeaa, "Eddystone"),
+];
+
+const CHARACTERISTICS: &[(u32, &str)] = &[
+    (0x2a00, "Device Name"),
+    (0x2a01, "Appearance"),
+    (0x2a02, "Peripheral Privacy Flag"),
+    (0x2a03, "Reconnection Address"),
+    (0x2a04, "Peripheral Preferred Connection Parameters"),
+    (0x2a05, "Service Changed"),
+    (0x2a06, "Alert Level"),
+    (0x2a07, "Tx Power Level"),
+    (0x2a08, "Date Time"),
+    (0x2a09, "Day of Week"),
+    (0x2a0a, "Day Date Time"),
+    (0x2a0c, "Exact Time 256"),
+    (0x2a0d, "DST Offset"),
+    (0x2a0e, "Time Zone"),
+    (0x2a0f, "Local Time Information"),
+    (0x2a11, "Time with DST"),
+    (0x2a12, "Time Accuracy"),
+    (0x2a13, "Time Source"),
+    (0x2a14, "Reference Time Information"),
+    (0x2a16, "Time Update Control Point"),
+    (0x2a17, "Time Update State"),
+    (0x2a18, "Glucose Measurement"),
+    (0x2a19, "Battery Level"),
+    (0x2a1c, "Temperature Measurement"),
+    (0x2a1d, "Temperature Type"),
+    (0x2a1e, "Intermediate Temperature"),
+    (0x2a21, "Measurement Interval"),
+    (0x2a22, "Boot Keyboard Input Report"),
+    (0x2a23, "System ID"),
+    (0x2a24, "Model Number String"),
+    (0x2a25, "Serial Number String"),
+    (0x2a26, "Firmware Revision String"),
+    (0x2a27, "Hardware Revision String"),
+    (0x2a28, "Software Revision String"),
+    (0x2a29, "Manufacturer Name String"),
+    (0x2a2a, "IEEE 11073-20601 Regulatory Certification Data List"),
+    (0x2a2b, "Current Time"),
+    (0x2a31, "Scan Refresh"),
+    (0x2a32, "Boot Keyboard Output Report"),
+    (0x2a33, "Boot Mouse Input Report"),
+    (0x2a34, "Glucose Measurement Context"),
+    (0x2a35, "Blood Pressure Measurement"),
+    (0x2a36, "Intermediate Cuff Pressure"),
+    (0x2a37, "Heart Rate Measurement"),
+    (0x2a38, "Body Sensor Location"),
+    (0x2a39, "Heart Rate Control Point"),
+    (0x2a3f, "Alert Status"),
+    (0x2a40, "Ringer Control Point"),
+    (0x2a41, "Ringer Setting"),
+    (0x2a42, "Alert Category ID Bit Mask"),
+    (0x2a43, "Alert Category ID"),
+    (0x2a44, "Alert Notification Control Point"),
+    (0x2a45, "Unread Alert Status"),
+    (0x2a46, "New Alert"),
+    (0x2a47, "Supported New Alert Category"),
+    (0x2a48, "Supported Unread Alert Category"),
+    (0x2a49, "Blood Pressure Feature"),
+    (0x2a4a, "HID Information"),
+    (0x2a4b, "Report Map"),
+    (0x2a4c, "HID Control Point"),
+    (0x2a4d, "Report"),
+    (0x2a4e, "Protocol Mode"),
+    (0x2a4f, "Scan Interval Window"),
+    (0x2a50, "PnP ID"),
+    (0x2a51, "Glucose Feature"),
+    (0x2a52, "Record Access Control Point"),
+    (0x2a53, "RSC Measurement"),
+    (0x2a54, "RSC Feature"),
+    (0x2a55, "SC Control Point"),
+    (0x2a56, "Digital"),
+    (0x2a58, "Analog"),
+    (0x2a5a, "Aggregate"),
+    (0x2a5b, "CSC Measurement"),
+    (0x2a5c, "CSC Feature"),
+    (0x2a5d, "Sensor Location"),
+    (0x2a5e, "PLX Spot-Check Measurement"),
+    (0x2a5f, "PLX Continuous Measurement"),
+    (0x2a60, "PLX Features"),
+    (0x2a63, "Cycling Power Measurement"),
+    (0x2a64, "Cycling Power Vector"),
+    (0x2a65, "Cycling Power Feature"),
+    (0x2a66, "Cycling Power Control Point"),
+    (0x2a67, "Location and Speed"),
+    (0x2a68, "Navigation"),
+    (0x2a69, "Position Quality"),
+    (0x2a6a, "LN Feature"),
+    (0x2a6b, "LN Control Point"),
+    (0x2a6c, "Elevation"),
+    (0x2a6d, "Pressure"),
+    (0x2a6e, "Temperature"),
+    (0x2a6f, "Humidity"),
+    (0x2a70, "True Wind Speed"),
+    (0x2a71, "True Wind Direction"),
+    (0x2a72, "Apparent Wind Speed"),
+    (0x2a73, "Apparent Wind Direction"),
+    (0x2a74, "Gust Factor"),
+    (0x2a75, "Pollen Concentration"),
+    (0x2a76, "UV Index"),
+    (0x2a77, "Irradiance"),
+    (0x2a78, "Rainfall"),
+    (0x2a79, "Wind Chill"),
+    (0x2a7a, "Heat Index"),
+    (0x2a7b, "Dew Point"),
+    (0x2a7d, "Descriptor Value Changed"),
+    (0x2a7e, "Aerobic Heart Rate Lower Limit"),
+    (0x2a80, "Age"),
+    (0x2a85, "Date of Birth"),
+    (0x2a8a, "First Name"),
+    (0x2a8c, "Gender"),
+    (0x2a8e, "Height"),
+    (0x2a90, "Last Name"),
+    (0x2a98, "Weight"),
+    (0x2a99, "Database Change Increment"),
+    (0x2a9a, "User Index"),
+    (0x2a9b, "Body Composition Feature"),
+    (0x2a9c, "Body Composition Measurement"),
+    (0x2a9d, "Weight Measurement"),
+    (0x2a9e, "Weight Scale Feature"),
+    (0x2a9f, "User Control Point"),
+    (0x2aa0, "Magnetic Flux Density - 2D"),
+    (0x2aa1, "Magnetic Flux Density - 3D"),
+    (0x2aa2, "Language"),
+    (0x2aa3, "Barometric Pressure Trend"),
+    (0x2aa6, "Central Address Resolution"),
+    (0x2aa7, "CGM Measurement"),
+    (0x2aa8, "CGM Feature"),
+    (0x2aa9, "CGM Status"),
+    (0x2aaa, "CGM Session Start Time"),
+    (0x2aab, "CGM Session Run Time"),
+    (0x2aac, "CGM Specific Ops Control Point"),
+    (0x2ac9, "Resolvable Private Address Only"),
+    (0x2acc, "Fitness Machine Feature"),
+    (0x2acd, "Treadmill Data"),
+    (0x2ad2, "Indoor Bike Data"),
+    (0x2ad9, "Fitness Machine Control Point"),
+    (0x2ada, "Fitness Machine Status"),
+    (0x2b29, "Client Supported Features"),
+    (0x2b2a, "Database Hash"),
+    (0x2b3a, "Server Supported Features"),
+];
+
+const DESCRIPTORS: &[(u32, &str)] = &[
+    (0x2900, "Characteristic Extended Properties"),
+    (0x2901, "Characteristic User Description"),
+    (0x2902, "Client Characteristic Configuration"),
+    (0x2903, "Server Characteristic Configuration"),
+    (0x2904, "Characteristic Presentation Format"),
+    (0x2905, "Characteristic Aggregate Format"),
+    (0x2906, "Valid Range"),
+    (0x2907, "External Report Reference"),
+    (0x2908, "Report Reference"),
+    (0x2909, "Number of Digitals"),
+    (0x290a, "Value Trigger Setting"),
+    (0x290b, "Environmental Sensing Configuration"),
+    (0x290c, "Environmental Sensing Measurement"),
+    (0x290d, "Environmental Sensing Trigger Setting"),
+    (0x290e, "Time Trigger Setting"),
+    (0x290f, "Complete BR-EDR Transport Block Data"),
+];
+
+const COMPANIES: &[(u16, &str)] = &[
+    (0x0000, "Ericsson AB"),
+    (0x0001, "Nokia Mobile Phones"),
+    (0x0002, "Intel Corp."),
+    (0x0003, "IBM Corp."),
+    (0x0004, "Toshiba Corp."),
+    (0x0006, "Microsoft"),
+    (0x0008, "Motorola"),
+    (0x000a, "Qualcomm Technologies International, Ltd. (QTIL)"),
+    (0x000d, "Texas Instruments Inc."),
+    (0x000f, "Broadcom Corporation"),
+    (0x001d, "Qualcomm"),
+    (0x0025, "NXP Semiconductors"),
+    (0x0030, "ST Microelectronics"),
+    (0x0046, "MediaTek, Inc."),
+    (0x004c, "Apple, Inc."),
+    (0x0059, "Nordic Semiconductor ASA"),
+    (0x0065, "HP, Inc."),
+    (0x0075, "Samsung Electronics Co. Ltd."),
+    (0x0078, "Nike, Inc."),
+    (0x0087, "Garmin International, Inc."),
+    (0x00c4, "LG Electronics"),
+    (0x00e0, "Google"),
+    (0x012d, "Sony Corporation"),
+    (0x0131, "Cypress Semiconductor"),
+    (0x0157, "Anhui Huami Information Technology Co., Ltd."),
+    (0x0171, "Amazon.com Services LLC"),
+    (0x027d, "HUAWEI Technologies Co., Ltd."),
+    (0x02e5, "Espressif Systems (Shanghai) Co., Ltd."),
+    (0x038f, "Xiaomi Inc."),
+    (0x0499, "Ruuvi Innovations Ltd."),
+];
+
+/// Symbols of the units used by the Characteristic Presentation Format descriptor.
+const UNITS: &[(u16, &str)] = &[
+    (0x2701, "m"),
+    (0x2702, "kg"),
+    (0x2703, "s"),
+    (0x2704, "A"),
+    (0x2705, "K"),
+    (0x2706, "mol"),
+    (0x2707, "cd"),
+    (0x2710, "m²"),
+    (0x2711, "m³"),
+    (0x2712, "m/s"),
+    (0x2713, "m/s²"),
+    (0x2715, "kg/m³"),
+    (0x2716, "kg/m²"),
+    (0x2717, "m³/kg"),
+    (0x2718, "A/m²"),
+    (0x2719, "A/m"),
+    (0x271a, "mol/m³"),
+    (0x271b, "kg/m³"),
+    (0x271c, "cd/m²"),
+    (0x2720, "rad"),
+    (0x2721, "sr"),
+    (0x2722, "Hz"),
+    (0x2723, "N"),
+    (0x2724, "Pa"),
+    (0x2725, "J"),
+    (0x2726, "W"),
+    (0x2727, "C"),
+    (0x2728, "V"),
+    (0x2729, "F"),
+    (0x272a, "Ω"),
+    (0x272b, "S"),
+    (0x272c, "Wb"),
+    (0x272d, "T"),
+    (0x272e, "H"),
+    (0x272f, "°C"),
+    (0x2730, "lm"),
+    (0x2731, "lx"),
+    (0x2732, "Bq"),
+    (0x2733, "Gy"),
+    (0x2734, "Sv"),
+    (0x2735, "kat"),
+    (0x2760, "min"),
+    (0x2761, "h"),
+    (0x2762, "d"),
+    (0x2763, "°"),
+    (0x2766, "ha"),
+    (0x2767, "l"),
+    (0x2768, "t"),
+    (0x2780, "bar"),
+    (0x2781, "mmHg"),
+    (0x2785, "kn"),
+    (0x27a2, "in"),
+    (0x27a3, "ft"),
+    (0x27a4, "mi"),
+    (0x27a5, "psi"),
+    (0x27a6, "km/h"),
+    (0x27a7, "mph"),
+    (0x27a8, "rpm"),
+    (0x27a9, "cal"),
+    (0x27aa, "kcal"),
+    (0x27ab, "kWh"),
+    (0x27ac, "°F"),
+    (0x27ad, "%"),
+    (0x27ae, "‰"),
+    (0x27af, "bpm"),
+    (0x27b0, "Ah"),
+    (0x27b1, "mg/dL"),
+    (0x27b2, "mmol/L"),
+    (0x27b6, "W/m²"),
+    (0x27b8, "lb"),
+    (0x27c3, "dB"),
+    (0x27c4, "ppm"),
+    (0x27c5, "ppb"),
+];
+
+/// Appearance categories, the upper 10 bits of the value.
+const APPEARANCE_CATEGORIES: &[(u16, &str)] = &[
+    (0, "Unknown"),
+    (1, "Phone"),
+    (2, "Computer"),
+    (3, "Watch"),
+    (4, "Clock"),
+    (5, "Display"),
+    (6, "Remote Control"),
+    (7, "Eye-glasses"),
+    (8, "Tag"),
+    (9, "Keyring"),
+    (10, "Media Player"),
+    (11, "Barcode Scanner"),
+    (12, "Thermometer"),
+    (13, "Heart Rate Sensor"),
+    (14, "Blood Pressure"),
+    (15, "Human Interface Device"),
+    (16, "Glucose Meter"),
+    (17, "Running Walking Sensor"),
+    (18, "Cycling"),
+    (19, "Control Device"),
+    (20, "Network Device"),
+    (21, "Sensor"),
+    (22, "Light Fixtures"),
+    (23, "Fan"),
+    (24, "HVAC"),
+    (25, "Air Conditioning"),
+    (26, "Humidifier"),
+    (27, "Heating"),
+    (28, "Access Control"),
+    (29, "Motorized Device"),
+    (30, "Power Device"),
+    (31, "Light Source"),
+    (32, "Window Covering"),
+    (33, "Audio Sink"),
+    (34, "Audio Source"),
+    (35, "Motorized Vehicle"),
+    (36, "Domestic Appliance"),
+    (37, "Wearable Audio Device"),
+    (38, "Aircraft"),
+    (39, "AV Equipment"),
+    (40, "Display Equipment"),
+    (41, "Hearing aid"),
+    (42, "Gaming"),
+    (43, "Signage"),
+    (49, "Pulse Oximeter"),
+    (50, "Weight Scale"),
+    (51, "Personal Mobility Device"),
+    (52, "Continuous Glucose Monitor"),
+    (53, "Insulin Pump"),
+    (54, "Medication Delivery"),
+    (55, "Spirometer"),
+    (81, "Outdoor Sports Activity"),
+];
+
+/// Appearance subcategories, by full value.
+const APPEARANCE_SUBCATEGORIES: &[(u16, &str)] = &[
+    (0x00c1, "Sports Watch"),
+    (0x0301, "Ear Thermometer"),
+    (0x0341, "Heart Rate Belt"),
+    (0x03c1, "Keyboard"),
+    (0x03c2, "Mouse"),
+    (0x03c3, "Joystick"),
+    (0x03c4, "Gamepad"),
+    (0x0481, "Cycling Computer"),
+    (0x0482, "Speed Sensor"),
+    (0x0483, "Cadence Sensor"),
+    (0x0484, "Power Sensor"),
+    (0x0485, "Speed and Cadence Sensor"),
+    (0x0541, "Motion Sensor"),
+    (0x0542, "Air quality Sensor"),
+    (0x0543, "Temperature Sensor"),
+    (0x0544, "Humidity Sensor"),
+    (0x0545, "Leak Sensor"),
+    (0x0546, "Smoke Sensor"),
+    (0x0547, "Occupancy Sensor"),
+    (0x0548, "Contact Sensor"),
+    (0x0549, "Carbon Monoxide Sensor"),
+    (0x054a, "Carbon Dioxide Sensor"),
+    (0x054b, "Ambient Light Sensor"),
+    (0x054c, "Energy Sensor"),
+    (0x054d, "Color Light Sensor"),
+    (0x054e, "Rain Sensor"),
+    (0x054f, "Fire Sensor"),
+    (0x0550, "Wind Sensor"),
+    (0x0551, "Proximity Sensor"),
+    (0x0552, "Multi-Sensor"),
+];
+
+fn table(kind: UuidKind) -> &'static [(u32, &'static str)] {
+    match kind {
+        UuidKind::Service => SERVICES,
+        UuidKind::Characteristic => CHARACTERISTICS,
+        UuidKind::Descriptor => DESCRIPTORS,
+    }
+}
+
+/// Expands a 16 or 32-bit short UUID with the Bluetooth base UUID.
+pub fn uuid_from_short(short: u32) -> Uuid {
+    Uuid::from_u128(BASE_UUID | (short as u128) << 96)
+}
+
+/// The 16 or 32-bit short form of a UUID derived from the Bluetooth base UUID.
+pub fn short_uuid(uuid: &Uuid) -> Option<u32> {
+    let value = uuid.as_u128();
+    (value & ((1 << 96) - 1) == BASE_UUID).then_some((value >> 96) as u32)
+}
+
+/// The assigned name of a service, characteristic or descriptor UUID.
+pub fn name(uuid: &Uuid, kind: UuidKind) -> Option<&'static str> {
+    let short = short_uuid(uuid)?;
+    table(kind).iter().find(|(number, _)| *number == short).map(|(_, name)| *name)
+}
+
+/// The name followed by the short UUID, e.g. `Battery Level (0x2a19)`, or the full UUID
+/// if it has no assigned name.
+pub fn describe(uuid: &Uuid, kind: UuidKind) -> String {
+    match (name(uuid, kind), short_uuid(uuid)) {
+        (Some(name), Some(short)) => format!("{} ({:#06x})", name, short),
+        _ => uuid.to_string(),
+    }
+}
+
+/// Like `describe`, for a UUID kept as a string.
+pub fn describe_str(uuid: &str, kind: UuidKind) -> String {
+    match Uuid::parse_str(uuid) {
+        Ok(uuid) => describe(&uuid, kind),
+        Err(_) => uuid.to_string(),
+    }
+}
+
+/// Parses a full UUID, a 16 or 32-bit short UUID in hex (optionally `0x` prefixed) or an
+/// assigned name. Names are matched ignoring case, spaces, dashes and underscores, and for
+/// services without a trailing "Service".
+pub fn parse_uuid(input: &str, kind: UuidKind) -> Result<Uuid, String> {
+    let input = input.trim();
+    if let Ok(uuid) = Uuid::parse_str(input) {
+        return Ok(uuid);
+    }
+    let hex = input.strip_prefix("0x").or_else(|| input.strip_prefix("0X")).unwrap_or(input);
+    if matches!(hex.len(), 4 | 8) {
+        if let Ok(short) = u32::from_str_radix(hex, 16) {
+            return Ok(uuid_from_short(short));
+        }
+    }
+    let wanted = normalize_name(input, kind);
+    table(kind)
+        .iter()
+        .find(|(_, name)| normalize_name(name, kind) == wanted)
+        .map(|(number, _)| uuid_from_short(*number))
+        .ok_or_else(|| format!("'{}' is neither a UUID nor the name of a {}", input, kind))
+}
+
+fn normalize_name(name: &str, kind: UuidKind) -> String {
+    let name = normalize(name);
+    match kind {
+        UuidKind::Service => name.strip_suffix("service").map(str::to_string).unwrap_or(name),
+        _ => name,
+    }
+}
+
+/// The name in lowercase without spaces and punctuation.
+fn normalize(name: &str) -> String {
+    name.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_lowercase()).collect()
+}
+
+pub fn company_name(id: u16) -> Option<&'static str> {
+    COMPANIES.iter().find(|(number, _)| *number == id).map(|(_, name)| *name)
+}
+
+/// The ID of the company called `name`, or of the only company whose name starts with it.
+/// Case, spaces and punctuation are ignored, so `apple` finds "Apple, Inc.".
+pub fn company_id(name: &str) -> Result<u16, String> {
+    let wanted = normalize(name);
+    if wanted.is_empty() {
+        return Err("No company name given".to_string());
+    }
+    if let Some((id, _)) = COMPANIES.iter().find(|(_, company)| normalize(company) == wanted) {
+        return Ok(*id);
+    }
+    let matches: Vec<&(u16, &str)> = COMPANIES.iter().filter(|(_, company)| normalize(company).starts_with(&wanted)).collect();
+    match matches.as_slice() {
+        [(id, _)] => Ok(*id),
+        [] => Err(format!("No company is called '{}'", name.trim())),
+        _ => {
+            let names: Vec<&str> = matches.iter().map(|(_, company)| *company).collect();
+            Err(format!("'{}' matches several companies: {}", name.trim(), names.join(", ")))
+        }
+    }
+}
+
+/// The symbol of a unit, e.g. `°C` for 0x272f; `None` for unitless and unknown units.
+pub fn unit_symbol(unit: u16) -> Option<&'static str> {
+    UNITS.iter().find(|(number, _)| *number == unit).map(|(_, symbol)| *symbol)
+}
+
+/// The subcategory name of an appearance value, or its category name, e.g. `Temperature Sensor`.
+pub fn appearance_name(value: u16) -> String {
+    if let Some((_, name)) = APPEARANCE_SUBCATEGORIES.iter().find(|(number, _)| *number == value) {
+        return name.to_string();
+    }
+    match APPEARANCE_CATEGORIES.iter().find(|(category, _)| *category == value >> 6) {
+        Some((_, name)) if value & 0x3f == 0 => name.to_string(),
+        Some((_, name)) => format!("{} (subcategory {})", name, value & 0x3f),
+        None => format!("Unknown ({:#06x})", value),
+    }
+}
+
+/// `parse_uuid` for services, usable as a clap value parser.
+pub fn parse_service_uuid(input: &str) -> Result<Uuid, String> {
+    parse_uuid(input, UuidKind::Service)
+}
+
+/// `parse_uuid` for characteristics, usable as a clap value parser.
+pub fn parse_characteristic_uuid(input: &str) -> Result<Uuid, String> {
+    parse_uuid(input, UuidKind::Characteristic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_uuids() {
+        let battery_service = Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb);
+        let battery_level = Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb);
+        let cases = [
+            ("0000180f-0000-1000-8000-00805f9b34fb", UuidKind::Service, Ok(battery_service)),
+            ("0000180F00001000800000805F9B34FB", UuidKind::Service, Ok(battery_service)),
+            ("180f", UuidKind::Service, Ok(battery_service)),
+            (" 0x180F ", UuidKind::Service, Ok(battery_service)),
+            ("0000180f", UuidKind::Service, Ok(battery_service)),
+            ("Battery Service", UuidKind::Service, Ok(battery_service)),
+            ("battery", UuidKind::Service, Ok(battery_service)),
+            ("Battery Level", UuidKind::Characteristic, Ok(battery_level)),
+            ("battery_level", UuidKind::Characteristic, Ok(battery_level)),
+            ("2a19", UuidKind::Characteristic, Ok(battery_level)),
+            // Names are looked up in the table of their kind
+            ("Battery Level", UuidKind::Service, Err(())),
+            ("18f", UuidKind::Service, Err(())),
+            ("0x180g", UuidKind::Service, Err(())),
+            ("", UuidKind::Service, Err(())),
+        ];
+        for (input, kind, expected) in cases {
+            assert_eq!(parse_uuid(input, kind).map_err(|_| ()), expected, "{:?} as {}", input, kind);
+        }
+    }
+
+    #[test]
+    fn short_uuids() {
+        let cases = [
+            (Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb), Some(0x2a19)),
+            (Uuid::from_u128(0x12345678_0000_1000_8000_00805f9b34fb), Some(0x1234_5678)),
+            (Uuid::from_u128(0x226c0000_6476_4566_7562_66734470666d), None),
+            (Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fc), None),
+        ];
+        for (uuid, expected) in cases {
+            assert_eq!(short_uuid(&uuid), expected, "{}", uuid);
+            if let Some(short) = expected {
+                assert_eq!(uuid_from_short(short), uuid);
+            }
+        }
+    }
+
+    #[test]
+    fn appearance_names() {
+        let cases = [
+            (0x0000, "Unknown"),
+            (0x00c0, "Watch"),
+            (0x00c1, "Sports Watch"),
+            (0x0300, "Thermometer"),
+            (0x0301, "Ear Thermometer"),
+            (0x0305, "Thermometer (subcategory 5)"),
+            (0xffc0, "Unknown (0xffc0)"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(appearance_name(value), expected, "{:#06x}", value);
+        }
+    }
+
+    #[test]
+    fn company_ids() {
+        let cases = [
+            ("Apple, Inc.", Ok(0x004c)),
+            ("apple inc", Ok(0x004c)),
+            ("APPLE", Ok(0x004c)),
+            ("Microsoft", Ok(0x0006)),
+            ("xiaomi", Ok(0x038f)),
+            ("", Err(())),
+            ("  ", Err(())),
+            (",", Err(())),
+            ("No Such Company", Err(())),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(company_id(name).map_err(|_| ()), expected, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn ambiguous_company_prefixes_are_rejected() {
+        let prefix = "a";
+        assert!(COMPANIES.iter().filter(|(_, company)| normalize(company).starts_with(prefix)).count() > 1);
+        let error = company_id(prefix).unwrap_err();
+        assert!(error.contains("Apple, Inc."), "{}", error);
+    }
+}
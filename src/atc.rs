@@ -0,0 +1,135 @@
+//! Advertisements of the ATC1441 and pvvx custom thermometer firmwares (service data for
+//! UUID 0x181A, Environmental Sensing).
+//!
+//! ATC format (13 bytes, big endian): MAC (6), temperature (i16, 0.1 °C), humidity (u8, %),
+//! battery (u8, %), battery voltage (u16, mV), frame counter (u8).
+//!
+//! pvvx custom format (15 bytes, little endian): MAC (6, reversed), temperature (i16,
+//! 0.01 °C), humidity (u16, 0.01 %), battery voltage (u16, mV), battery (u8, %), frame
+//! counter (u8), flags (u8).
+
+use crate::sensor::{Measurement, ReadingSource};
+use uuid::Uuid;
+
+pub const SERVICE_UUID: Uuid = Uuid::from_u128(0x0000181a_0000_1000_8000_00805f9b34fb);
+
+const ATC_LENGTH: usize = 13;
+const PVVX_LENGTH: usize = 15;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AtcFrame {
+    /// `ReadingSource::Atc` or `ReadingSource::Pvvx`
+    pub format: ReadingSource,
+    /// MAC address announced in the frame, formatted like `A4:C1:38:00:00:01`.
+    pub mac_address: String,
+    pub frame_counter: u8,
+    pub measurements: Vec<Measurement>,
+}
+
+/// Decodes the service data of either firmware, telling them apart by length.
+pub fn parse(data: &[u8]) -> Option<AtcFrame> {
+    match data.len() {
+        ATC_LENGTH => Some(parse_atc(data)),
+        PVVX_LENGTH => Some(parse_pvvx(data)),
+        _ => None,
+    }
+}
+
+fn parse_atc(data: &[u8]) -> AtcFrame {
+    let temperature = i16::from_be_bytes([data[6], data[7]]);
+    let voltage = u16::from_be_bytes([data[10], data[11]]);
+
+    AtcFrame {
+        format: ReadingSource::Atc,
+        mac_address: format_mac(data[..6].iter()),
+        frame_counter: data[12],
+        measurements: vec![
+            Measurement::Temperature(temperature as f32 / 10.0),
+            Measurement::Humidity(data[8] as f32),
+            Measurement::Battery(data[9]),
+            Measurement::BatteryVoltage(voltage),
+        ],
+    }
+}
+
+fn parse_pvvx(data: &[u8]) -> AtcFrame {
+    let temperature = i16::from_le_bytes([data[6], data[7]]);
+    let humidity = u16::from_le_bytes([data[8], data[9]]);
+    let voltage = u16::from_le_bytes([data[10], data[11]]);
+
+    AtcFrame {
+        format: ReadingSource::Pvvx,
+        mac_address: format_mac(data[..6].iter().rev()),
+        frame_counter: data[13],
+        measurements: vec![
+            Measurement::Temperature(temperature as f32 / 100.0),
+            Measurement::Humidity(humidity as f32 / 100.0),
+            Measurement::Battery(data[12]),
+            Measurement::BatteryVoltage(voltage),
+        ],
+    }
+}
+
+fn format_mac<'a>(bytes: impl Iterator<Item = &'a u8>) -> String {
+    bytes.map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_both_formats() {
+        let cases: Vec<(&str, &[u8], _)> = vec![
+            (
+                "ATC",
+                &[0xa4, 0xc1, 0x38, 0x00, 0x00, 0x02, 0x00, 0xd7, 0x34, 0x58, 0x0b, 0xa4, 0x07],
+                Some((ReadingSource::Atc, "A4:C1:38:00:00:02", 7, vec![
+                    Measurement::Temperature(21.5),
+                    Measurement::Humidity(52.0),
+                    Measurement::Battery(88),
+                    Measurement::BatteryVoltage(2980),
+                ])),
+            ),
+            (
+                "ATC below zero",
+                &[0xa4, 0xc1, 0x38, 0x00, 0x00, 0x02, 0xff, 0xc9, 0x5f, 0x0a, 0x0a, 0x8c, 0xff],
+                Some((ReadingSource::Atc, "A4:C1:38:00:00:02", 255, vec![
+                    Measurement::Temperature(-5.5),
+                    Measurement::Humidity(95.0),
+                    Measurement::Battery(10),
+                    Measurement::BatteryVoltage(2700),
+                ])),
+            ),
+            (
+                "pvvx",
+                &[0x02, 0x00, 0x00, 0x38, 0xc1, 0xa4, 0x69, 0x08, 0x5a, 0x14, 0xa4, 0x0b, 0x58, 0x07, 0x04],
+                Some((ReadingSource::Pvvx, "A4:C1:38:00:00:02", 7, vec![
+                    Measurement::Temperature(21.53),
+                    Measurement::Humidity(52.1),
+                    Measurement::Battery(88),
+                    Measurement::BatteryVoltage(2980),
+                ])),
+            ),
+            (
+                "pvvx below zero",
+                &[0x02, 0x00, 0x00, 0x38, 0xc1, 0xa4, 0x2e, 0xfb, 0x10, 0x27, 0x8c, 0x0a, 0x0a, 0x00, 0x00],
+                Some((ReadingSource::Pvvx, "A4:C1:38:00:00:02", 0, vec![
+                    Measurement::Temperature(-12.34),
+                    Measurement::Humidity(100.0),
+                    Measurement::Battery(10),
+                    Measurement::BatteryVoltage(2700),
+                ])),
+            ),
+            ("empty", &[], None),
+            ("too short for ATC", &[0xa4, 0xc1, 0x38, 0x00, 0x00, 0x02, 0x00, 0xd7, 0x34, 0x58, 0x0b, 0xa4], None),
+            ("between the formats", &[0; 14], None),
+            ("too long for pvvx", &[0; 16], None),
+        ];
+        for (name, data, expected) in cases {
+            let parsed = parse(data).map(|frame| (frame.format, frame.mac_address, frame.frame_counter, frame.measurements));
+            let expected = expected.map(|(format, mac, counter, measurements)| (format, mac.to_string(), counter, measurements));
+            assert_eq!(parsed, expected, "{}", name);
+        }
+    }
+}
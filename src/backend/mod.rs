@@ -0,0 +1,77 @@
+//! Bluetooth LE backends.
+//!
+//! `BluetoothManager` and `BluetoothDevice` only talk to the traits defined here, so the
+//! same scan, connect and sensor logic runs against the real radio (`platform`, backed by
+//! btleplug) or against the scriptable in-process adapter in `simulated`.
+
+pub mod platform;
+pub mod simulated;
+
+use async_trait::async_trait;
+use btleplug::api::{Characteristic, Descriptor, PeripheralProperties, ScanFilter, Service, ValueNotification, WriteType};
+use btleplug::Result;
+use futures::stream::Stream;
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Stream of value notifications coming from a connected peripheral.
+pub type NotificationStream = Pin<Box<dyn Stream<Item = ValueNotification> + Send>>;
+
+/// Stream of discovery events coming from an adapter.
+pub type AdapterEventStream = Pin<Box<dyn Stream<Item = AdapterEvent> + Send>>;
+
+/// What happened to a peripheral. Advertised data is already reflected in its properties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdapterEventKind {
+    Discovered,
+    Updated,
+    Connected,
+    Disconnected,
+    ManufacturerData,
+    ServiceData,
+}
+
+#[derive(Debug, Clone)]
+pub struct AdapterEvent {
+    pub kind: AdapterEventKind,
+    pub peripheral: Arc<dyn BlePeripheral>,
+}
+
+/// Entry point of a backend: enumerates the adapters it can drive.
+#[async_trait]
+pub trait BleBackend: Send + Sync {
+    async fn adapters(&self) -> Result<Vec<Arc<dyn BleAdapter>>>;
+}
+
+/// A local Bluetooth adapter (the "central").
+#[async_trait]
+pub trait BleAdapter: Debug + Send + Sync {
+    async fn adapter_info(&self) -> Result<String>;
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()>;
+    async fn stop_scan(&self) -> Result<()>;
+    /// Events for peripherals seen from now on; subscribe before starting a scan.
+    async fn events(&self) -> Result<AdapterEventStream>;
+    async fn peripherals(&self) -> Result<Vec<Arc<dyn BlePeripheral>>>;
+}
+
+/// A remote device seen by an adapter.
+#[async_trait]
+pub trait BlePeripheral: Debug + Send + Sync {
+    /// Identifier of the peripheral, the MAC address on most platforms.
+    fn id(&self) -> String;
+    async fn properties(&self) -> Result<Option<PeripheralProperties>>;
+    async fn is_connected(&self) -> Result<bool>;
+    async fn connect(&self) -> Result<()>;
+    async fn disconnect(&self) -> Result<()>;
+    async fn discover_services(&self) -> Result<()>;
+    /// Services found by the last call to `discover_services`.
+    fn services(&self) -> BTreeSet<Service>;
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>>;
+    async fn write(&self, characteristic: &Characteristic, data: &[u8], write_type: WriteType) -> Result<()>;
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>>;
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()>;
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()>;
+    async fn notifications(&self) -> Result<NotificationStream>;
+}
@@ -0,0 +1,123 @@
+//! Backend for the host Bluetooth stack, implemented on top of btleplug.
+
+use super::{AdapterEvent, AdapterEventKind, AdapterEventStream, BleAdapter, BleBackend, BlePeripheral, NotificationStream};
+use async_trait::async_trait;
+use btleplug::api::{
+    Central, CentralEvent, Characteristic, Descriptor, Peripheral as PeripheralTrait, PeripheralProperties, ScanFilter,
+    Service, WriteType,
+};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use btleplug::Result;
+use futures::StreamExt;
+use log::debug;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+#[async_trait]
+impl BleBackend for Manager {
+    async fn adapters(&self) -> Result<Vec<Arc<dyn BleAdapter>>> {
+        let adapters = btleplug::api::Manager::adapters(self).await?;
+        Ok(adapters.into_iter().map(|adapter| Arc::new(adapter) as Arc<dyn BleAdapter>).collect())
+    }
+}
+
+#[async_trait]
+impl BleAdapter for Adapter {
+    async fn adapter_info(&self) -> Result<String> {
+        Central::adapter_info(self).await
+    }
+
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        Central::start_scan(self, filter).await
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        Central::stop_scan(self).await
+    }
+
+    async fn events(&self) -> Result<AdapterEventStream> {
+        let adapter = self.clone();
+        let events = Central::events(self).await?.filter_map(move |event| {
+            let adapter = adapter.clone();
+            async move {
+                let (kind, id) = match event {
+                    CentralEvent::DeviceDiscovered(id) => (AdapterEventKind::Discovered, id),
+                    CentralEvent::DeviceUpdated(id) => (AdapterEventKind::Updated, id),
+                    CentralEvent::DeviceConnected(id) => (AdapterEventKind::Connected, id),
+                    CentralEvent::DeviceDisconnected(id) => (AdapterEventKind::Disconnected, id),
+                    CentralEvent::ManufacturerDataAdvertisement { id, .. } => (AdapterEventKind::ManufacturerData, id),
+                    CentralEvent::ServiceDataAdvertisement { id, .. } => (AdapterEventKind::ServiceData, id),
+                    _ => return None,
+                };
+                match Central::peripheral(&adapter, &id).await {
+                    Ok(peripheral) => Some(AdapterEvent { kind, peripheral: Arc::new(peripheral) as Arc<dyn BlePeripheral> }),
+                    Err(e) => {
+                        debug!("Dropping {:?} event of unknown peripheral {:?}: {}", kind, id, e);
+                        None
+                    }
+                }
+            }
+        });
+        Ok(Box::pin(events))
+    }
+
+    async fn peripherals(&self) -> Result<Vec<Arc<dyn BlePeripheral>>> {
+        let peripherals = Central::peripherals(self).await?;
+        Ok(peripherals.into_iter().map(|peripheral| Arc::new(peripheral) as Arc<dyn BlePeripheral>).collect())
+    }
+}
+
+#[async_trait]
+impl BlePeripheral for Peripheral {
+    fn id(&self) -> String {
+        PeripheralTrait::id(self).to_string()
+    }
+
+    async fn properties(&self) -> Result<Option<PeripheralProperties>> {
+        PeripheralTrait::properties(self).await
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        PeripheralTrait::is_connected(self).await
+    }
+
+    async fn connect(&self) -> Result<()> {
+        PeripheralTrait::connect(self).await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        PeripheralTrait::disconnect(self).await
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        PeripheralTrait::discover_services(self).await
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        PeripheralTrait::services(self)
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        PeripheralTrait::read(self, characteristic).await
+    }
+
+    async fn write(&self, characteristic: &Characteristic, data: &[u8], write_type: WriteType) -> Result<()> {
+        PeripheralTrait::write(self, characteristic, data, write_type).await
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        PeripheralTrait::read_descriptor(self, descriptor).await
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        PeripheralTrait::subscribe(self, characteristic).await
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        PeripheralTrait::unsubscribe(self, characteristic).await
+    }
+
+    async fn notifications(&self) -> Result<NotificationStream> {
+        PeripheralTrait::notifications(self).await
+    }
+}
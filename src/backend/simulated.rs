@@ -0,0 +1,704 @@
+//! In-process simulated adapter.
+//!
+//! Peripherals, their GATT tables and advertisement data are scripted through builder
+//! methods, and every operation can be made to fail on demand, so the manager logic can be
+//! exercised without a radio.
+
+use super::{AdapterEvent, AdapterEventKind, AdapterEventStream, BleAdapter, BleBackend, BlePeripheral, NotificationStream};
+use async_trait::async_trait;
+use btleplug::api::{CharPropFlags, Characteristic, Descriptor, PeripheralProperties, ScanFilter, Service, ValueNotification, WriteType};
+use btleplug::{Error, Result};
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use log::debug;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const USER_DESCRIPTION_UUID: Uuid = Uuid::from_u128(0x00002901_0000_1000_8000_00805f9b34fb);
+const CLIENT_CONFIGURATION_UUID: Uuid = Uuid::from_u128(0x00002902_0000_1000_8000_00805f9b34fb);
+const PRESENTATION_FORMAT_UUID: Uuid = Uuid::from_u128(0x00002904_0000_1000_8000_00805f9b34fb);
+
+/// Operations that can be scripted to fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SimulatedOperation {
+    StartScan,
+    StopScan,
+    Connect,
+    Disconnect,
+    DiscoverServices,
+    Read,
+    Write,
+    Subscribe,
+    Unsubscribe,
+}
+
+/// Pending injected failures, consumed one per matching operation.
+#[derive(Debug, Default)]
+struct FailureScript {
+    pending: Mutex<HashMap<SimulatedOperation, u32>>,
+}
+
+impl FailureScript {
+    #[cfg(test)]
+    fn inject(&self, operation: SimulatedOperation, times: u32) {
+        *self.pending.lock().unwrap().entry(operation).or_insert(0) += times;
+    }
+
+    fn check(&self, operation: SimulatedOperation) -> Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.get_mut(&operation) {
+            Some(remaining) if *remaining > 0 => {
+                *remaining -= 1;
+                debug!("Injecting simulated failure for {:?}", operation);
+                Err(Error::RuntimeError(format!("Simulated failure: {:?}", operation)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SimulatedBackend {
+    adapters: Vec<Arc<SimulatedAdapter>>,
+}
+
+impl SimulatedBackend {
+    pub fn new() -> Self {
+        SimulatedBackend::default()
+    }
+
+    pub fn with_adapter(mut self, adapter: SimulatedAdapter) -> Self {
+        self.adapters.push(Arc::new(adapter));
+        self
+    }
+
+    #[cfg(test)]
+    pub fn adapter(&self, index: usize) -> Arc<SimulatedAdapter> {
+        self.adapters[index].clone()
+    }
+
+    /// An adapter seeing an MJ_HT_V1 sensor, an ATC thermometer, a BTHome motion sensor, an
+    /// anonymous device and a tag coming and going every 20 seconds, and a second adapter
+    /// hearing the thermometer better.
+    pub fn demo() -> Self {
+        let gap = Uuid::from_u128(0x00001800_0000_1000_8000_00805f9b34fb);
+        let device_information = Uuid::from_u128(0x0000180a_0000_1000_8000_00805f9b34fb);
+        let battery = Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb);
+        let mj_ht_v1 = Uuid::from_u128(0x226c0000_6476_4566_7562_66734470666d);
+        let read = CharPropFlags::READ;
+
+        let sensor = SimulatedPeripheral::new("4C:65:A8:D0:00:01")
+            .with_name("MJ_HT_V1")
+            .with_rssi(-62)
+            .with_service_data(
+                Uuid::from_u128(0x0000fe95_0000_1000_8000_00805f9b34fb),
+                // MiBeacon v2, product 0x01aa, temperature 23.4 °C and humidity 45.6 %
+                &[0x50, 0x20, 0xaa, 0x01, 0x17, 0x01, 0x00, 0xd0, 0xa8, 0x65, 0x4c, 0x0d, 0x10, 0x04, 0xea, 0x00, 0xc8, 0x01],
+            )
+            .with_characteristic(gap, Uuid::from_u128(0x00002a00_0000_1000_8000_00805f9b34fb), read | CharPropFlags::WRITE, b"MJ_HT_V1")
+            .with_characteristic(gap, Uuid::from_u128(0x00002a01_0000_1000_8000_00805f9b34fb), read, &[0x00, 0x00])
+            .with_characteristic(gap, Uuid::from_u128(0x00002a04_0000_1000_8000_00805f9b34fb), read, &[0x18, 0x00, 0x28, 0x00, 0x00, 0x00, 0xe8, 0x03])
+            .with_characteristic(device_information, Uuid::from_u128(0x00002a26_0000_1000_8000_00805f9b34fb), read, b"00.00.66")
+            .with_characteristic(device_information, Uuid::from_u128(0x00002a29_0000_1000_8000_00805f9b34fb), read, b"Cleargrass Inc")
+            .with_characteristic(device_information, Uuid::from_u128(0x00002a50_0000_1000_8000_00805f9b34fb), read, &[0x01, 0x8f, 0x03, 0xaa, 0x01, 0x66, 0x00])
+            .with_characteristic(battery, Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb), read | CharPropFlags::NOTIFY, &[87])
+            .with_characteristic(mj_ht_v1, Uuid::from_u128(0x226caa55_6476_4566_7562_66734470666d), CharPropFlags::NOTIFY, &[])
+            .with_characteristic(mj_ht_v1, Uuid::from_u128(0x226cbb55_6476_4566_7562_66734470666d), CharPropFlags::NOTIFY, &[])
+            .with_descriptor(Uuid::from_u128(0x226caa55_6476_4566_7562_66734470666d), USER_DESCRIPTION_UUID, b"Temperature and Humidity")
+            .with_descriptor(Uuid::from_u128(0x226cbb55_6476_4566_7562_66734470666d), USER_DESCRIPTION_UUID, b"Humidity")
+            // Vendor characteristic that only a Presentation Format explains: sint16, exponent -2, °C
+            .with_characteristic(mj_ht_v1, Uuid::from_u128(0x226ccc55_6476_4566_7562_66734470666d), read, &[0x24, 0x09])
+            .with_descriptor(Uuid::from_u128(0x226ccc55_6476_4566_7562_66734470666d), USER_DESCRIPTION_UUID, b"Temperature")
+            .with_descriptor(
+                Uuid::from_u128(0x226ccc55_6476_4566_7562_66734470666d),
+                PRESENTATION_FORMAT_UUID,
+                &[0x0e, 0xfe, 0x2f, 0x27, 0x01, 0x00, 0x00],
+            )
+            .with_notification(Uuid::from_u128(0x226caa55_6476_4566_7562_66734470666d), b"T=23.4 H=45.6\0", Duration::from_secs(2));
+
+        let thermometer = |rssi| {
+            SimulatedPeripheral::new("A4:C1:38:00:00:02").with_name("ATC_000002").with_rssi(rssi).with_service_data(
+                Uuid::from_u128(0x0000181a_0000_1000_8000_00805f9b34fb),
+                // pvvx custom format, 21.53 °C, 52.10 %, 2980 mV, battery 88 %, counter 7
+                &[0x02, 0x00, 0x00, 0x38, 0xc1, 0xa4, 0x69, 0x08, 0x5a, 0x14, 0xa4, 0x0b, 0x58, 0x07, 0x04],
+            )
+        };
+
+        let motion_sensor = SimulatedPeripheral::new("3C:2E:F5:00:00:03")
+            .with_name("SBMO-003Z")
+            .with_rssi(-75)
+            .with_tx_power(-20)
+            .with_service_data(
+                Uuid::from_u128(0x0000fcd2_0000_1000_8000_00805f9b34fb),
+                // BTHome v2, packet 9, battery 97 %, illuminance 123.45 lx, motion detected
+                &[0x40, 0x00, 0x09, 0x01, 0x61, 0x05, 0x39, 0x30, 0x00, 0x21, 0x01],
+            );
+
+        let anonymous = SimulatedPeripheral::new("F0:11:22:33:44:55").with_rssi(-81).with_manufacturer_data(0x004c, &[0x12, 0x02, 0x00, 0x00]);
+
+        let tag = SimulatedPeripheral::new("D4:F5:13:00:00:06")
+            .with_name("Tag")
+            .with_rssi(-66)
+            .with_presence_cycle(Duration::from_secs(20), Duration::from_secs(20));
+
+        SimulatedBackend::new()
+            .with_adapter(
+                SimulatedAdapter::new("hci0 (simulated)")
+                    .with_peripheral(sensor)
+                    .with_peripheral(thermometer(-70))
+                    .with_peripheral(motion_sensor)
+                    .with_peripheral(anonymous)
+                    .with_peripheral(tag),
+            )
+            .with_adapter(SimulatedAdapter::new("hci1 (simulated USB dongle)").with_peripheral(thermometer(-58)))
+    }
+}
+
+#[async_trait]
+impl BleBackend for SimulatedBackend {
+    async fn adapters(&self) -> Result<Vec<Arc<dyn BleAdapter>>> {
+        Ok(self.adapters.iter().map(|adapter| adapter.clone() as Arc<dyn BleAdapter>).collect())
+    }
+}
+
+/// How often scanned peripherals advertise.
+const ADVERTISING_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct SimulatedAdapter {
+    name: String,
+    peripherals: Arc<Mutex<Vec<Arc<SimulatedPeripheral>>>>,
+    scanning: AtomicBool,
+    scanned: AtomicBool,
+    /// Incremented on every start and stop, so a stale advertising task knows to exit.
+    scan_generation: Arc<AtomicU64>,
+    listeners: Arc<Mutex<Vec<UnboundedSender<AdapterEvent>>>>,
+    failures: FailureScript,
+}
+
+impl SimulatedAdapter {
+    pub fn new(name: &str) -> Self {
+        SimulatedAdapter {
+            name: name.to_string(),
+            peripherals: Arc::new(Mutex::new(Vec::new())),
+            scanning: AtomicBool::new(false),
+            scanned: AtomicBool::new(false),
+            scan_generation: Arc::new(AtomicU64::new(0)),
+            listeners: Arc::new(Mutex::new(Vec::new())),
+            failures: FailureScript::default(),
+        }
+    }
+
+    pub fn with_peripheral(self, peripheral: SimulatedPeripheral) -> Self {
+        self.add_peripheral(peripheral);
+        self
+    }
+
+    /// Adds a peripheral at runtime, returning a handle to keep scripting it.
+    pub fn add_peripheral(&self, peripheral: SimulatedPeripheral) -> Arc<SimulatedPeripheral> {
+        let peripheral = Arc::new(peripheral);
+        self.peripherals.lock().unwrap().push(peripheral.clone());
+        peripheral
+    }
+
+    /// Makes the next `times` calls of `operation` on this adapter fail.
+    #[cfg(test)]
+    pub fn fail_next(&self, operation: SimulatedOperation, times: u32) {
+        self.failures.inject(operation, times);
+    }
+
+    #[cfg(test)]
+    pub fn is_scanning(&self) -> bool {
+        self.scanning.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl BleAdapter for SimulatedAdapter {
+    async fn adapter_info(&self) -> Result<String> {
+        Ok(self.name.clone())
+    }
+
+    async fn start_scan(&self, _filter: ScanFilter) -> Result<()> {
+        self.failures.check(SimulatedOperation::StartScan)?;
+        self.scanned.store(true, Ordering::SeqCst);
+        if self.scanning.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        // Every peripheral advertises periodically while the scan runs: first seen as
+        // discovered, then as service data (or a plain update, e.g. of the RSSI).
+        let generation = self.scan_generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let current_generation = self.scan_generation.clone();
+        let peripherals = self.peripherals.clone();
+        let listeners = self.listeners.clone();
+        tokio::spawn(async move {
+            let mut seen = HashSet::new();
+            while current_generation.load(Ordering::SeqCst) == generation {
+                let peripherals = peripherals.lock().unwrap().clone();
+                for peripheral in peripherals.into_iter().filter(|peripheral| peripheral.in_range()) {
+                    let kind = if seen.insert(peripheral.id.clone()) {
+                        AdapterEventKind::Discovered
+                    } else if !peripheral.properties.lock().unwrap().service_data.is_empty() {
+                        AdapterEventKind::ServiceData
+                    } else {
+                        AdapterEventKind::Updated
+                    };
+                    let event = AdapterEvent { kind, peripheral: peripheral as Arc<dyn BlePeripheral> };
+                    listeners.lock().unwrap().retain(|listener| listener.unbounded_send(event.clone()).is_ok());
+                }
+                tokio::time::sleep(ADVERTISING_INTERVAL).await;
+            }
+        });
+        Ok(())
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        self.failures.check(SimulatedOperation::StopScan)?;
+        if self.scanning.swap(false, Ordering::SeqCst) {
+            self.scan_generation.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    async fn events(&self) -> Result<AdapterEventStream> {
+        let (sender, receiver) = unbounded();
+        self.listeners.lock().unwrap().push(sender);
+        Ok(Box::pin(receiver))
+    }
+
+    async fn peripherals(&self) -> Result<Vec<Arc<dyn BlePeripheral>>> {
+        // Like a real adapter, nothing is known until a scan has been started.
+        if !self.scanned.load(Ordering::SeqCst) {
+            return Ok(Vec::new());
+        }
+        Ok(self.peripherals.lock().unwrap().iter().map(|p| p.clone() as Arc<dyn BlePeripheral>).collect())
+    }
+}
+
+#[derive(Debug)]
+pub struct SimulatedPeripheral {
+    id: String,
+    properties: Mutex<PeripheralProperties>,
+    gatt: BTreeMap<Uuid, BTreeSet<Characteristic>>,
+    values: Mutex<HashMap<Uuid, Vec<u8>>>,
+    /// Descriptor values by characteristic and descriptor UUID.
+    descriptor_values: HashMap<(Uuid, Uuid), Vec<u8>>,
+    scripted_notifications: HashMap<Uuid, (Vec<u8>, Duration)>,
+    connected: AtomicBool,
+    discovered: AtomicBool,
+    subscriptions: Arc<Mutex<HashSet<Uuid>>>,
+    listeners: Arc<Mutex<Vec<UnboundedSender<ValueNotification>>>>,
+    /// Time in range and out of range, repeating from creation; always in range when `None`.
+    presence_cycle: Option<(Duration, Duration)>,
+    created: Instant,
+    failures: FailureScript,
+}
+
+impl SimulatedPeripheral {
+    pub fn new(address: &str) -> Self {
+        let properties = PeripheralProperties {
+            address: address.parse().unwrap_or_default(),
+            ..Default::default()
+        };
+        SimulatedPeripheral {
+            id: address.to_string(),
+            properties: Mutex::new(properties),
+            gatt: BTreeMap::new(),
+            values: Mutex::new(HashMap::new()),
+            descriptor_values: HashMap::new(),
+            scripted_notifications: HashMap::new(),
+            connected: AtomicBool::new(false),
+            discovered: AtomicBool::new(false),
+            subscriptions: Arc::new(Mutex::new(HashSet::new())),
+            listeners: Arc::new(Mutex::new(Vec::new())),
+            presence_cycle: None,
+            created: Instant::now(),
+            failures: FailureScript::default(),
+        }
+    }
+
+    /// Advertises for `present`, then goes silent for `away`, over and over, like a tag
+    /// carried in and out of the room.
+    pub fn with_presence_cycle(mut self, present: Duration, away: Duration) -> Self {
+        self.presence_cycle = Some((present, away));
+        self
+    }
+
+    fn in_range(&self) -> bool {
+        match self.presence_cycle {
+            Some((present, away)) => {
+                let period = (present + away).as_millis().max(1);
+                self.created.elapsed().as_millis() % period < present.as_millis()
+            }
+            None => true,
+        }
+    }
+
+    pub fn with_name(self, name: &str) -> Self {
+        self.properties.lock().unwrap().local_name = Some(name.to_string());
+        self
+    }
+
+    pub fn with_rssi(self, rssi: i16) -> Self {
+        self.set_rssi(rssi);
+        self
+    }
+
+    pub fn with_tx_power(self, tx_power: i16) -> Self {
+        self.properties.lock().unwrap().tx_power_level = Some(tx_power);
+        self
+    }
+
+    pub fn with_service_data(self, service: Uuid, data: &[u8]) -> Self {
+        self.set_service_data(service, data);
+        self
+    }
+
+    pub fn with_manufacturer_data(self, company_id: u16, data: &[u8]) -> Self {
+        self.properties.lock().unwrap().manufacturer_data.insert(company_id, data.to_vec());
+        self
+    }
+
+    /// Adds a characteristic (and its service, if new) to the GATT table. Characteristics
+    /// that notify or indicate get a Client Characteristic Configuration descriptor.
+    pub fn with_characteristic(mut self, service: Uuid, characteristic: Uuid, properties: CharPropFlags, value: &[u8]) -> Self {
+        let mut descriptors = BTreeSet::new();
+        if properties.intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE) {
+            descriptors.insert(Descriptor { uuid: CLIENT_CONFIGURATION_UUID, service_uuid: service, characteristic_uuid: characteristic });
+        }
+        self.gatt.entry(service).or_default().insert(Characteristic { uuid: characteristic, service_uuid: service, properties, descriptors });
+        self.values.lock().unwrap().insert(characteristic, value.to_vec());
+        self
+    }
+
+    /// Adds a descriptor to a characteristic added before.
+    pub fn with_descriptor(mut self, characteristic: Uuid, descriptor: Uuid, value: &[u8]) -> Self {
+        for characteristics in self.gatt.values_mut() {
+            if let Some(mut existing) = characteristics.iter().find(|c| c.uuid == characteristic).cloned() {
+                characteristics.remove(&existing);
+                existing.descriptors.insert(Descriptor { uuid: descriptor, service_uuid: existing.service_uuid, characteristic_uuid: characteristic });
+                characteristics.insert(existing);
+            }
+        }
+        self.descriptor_values.insert((characteristic, descriptor), value.to_vec());
+        self
+    }
+
+    /// Emits `value` on `characteristic` every `interval` while it is subscribed.
+    pub fn with_notification(mut self, characteristic: Uuid, value: &[u8], interval: Duration) -> Self {
+        self.scripted_notifications.insert(characteristic, (value.to_vec(), interval));
+        self
+    }
+
+    pub fn set_rssi(&self, rssi: i16) {
+        self.properties.lock().unwrap().rssi = Some(rssi);
+    }
+
+    pub fn set_service_data(&self, service: Uuid, data: &[u8]) {
+        let mut properties = self.properties.lock().unwrap();
+        properties.service_data.insert(service, data.to_vec());
+        if !properties.services.contains(&service) {
+            properties.services.push(service);
+        }
+    }
+
+    pub fn set_value(&self, characteristic: Uuid, value: &[u8]) {
+        self.values.lock().unwrap().insert(characteristic, value.to_vec());
+    }
+
+    pub fn value(&self, characteristic: Uuid) -> Option<Vec<u8>> {
+        self.values.lock().unwrap().get(&characteristic).cloned()
+    }
+
+    /// Sends a notification to every listener if `characteristic` is subscribed.
+    #[cfg(test)]
+    pub fn notify(&self, characteristic: Uuid, value: &[u8]) {
+        send_notification(&self.subscriptions, &self.listeners, characteristic, value);
+    }
+
+    #[cfg(test)]
+    pub fn is_subscribed(&self, characteristic: Uuid) -> bool {
+        self.subscriptions.lock().unwrap().contains(&characteristic)
+    }
+
+    /// Makes the next `times` calls of `operation` on this peripheral fail.
+    #[cfg(test)]
+    pub fn fail_next(&self, operation: SimulatedOperation, times: u32) {
+        self.failures.inject(operation, times);
+    }
+
+    fn ensure_connected(&self) -> Result<()> {
+        if self.connected.load(Ordering::SeqCst) {
+            Ok(())
+        } else {
+            Err(Error::NotConnected)
+        }
+    }
+
+    fn ensure_known(&self, characteristic: &Characteristic) -> Result<()> {
+        let known = self
+            .gatt
+            .get(&characteristic.service_uuid)
+            .is_some_and(|characteristics| characteristics.iter().any(|c| c.uuid == characteristic.uuid));
+        if known {
+            Ok(())
+        } else {
+            Err(Error::NoSuchCharacteristic)
+        }
+    }
+}
+
+fn send_notification(
+    subscriptions: &Mutex<HashSet<Uuid>>,
+    listeners: &Mutex<Vec<UnboundedSender<ValueNotification>>>,
+    characteristic: Uuid,
+    value: &[u8],
+) -> bool {
+    if !subscriptions.lock().unwrap().contains(&characteristic) {
+        return false;
+    }
+    let notification = ValueNotification { uuid: characteristic, value: value.to_vec() };
+    listeners.lock().unwrap().retain(|listener| listener.unbounded_send(notification.clone()).is_ok());
+    true
+}
+
+#[async_trait]
+impl BlePeripheral for SimulatedPeripheral {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn properties(&self) -> Result<Option<PeripheralProperties>> {
+        Ok(Some(self.properties.lock().unwrap().clone()))
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        Ok(self.connected.load(Ordering::SeqCst))
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.failures.check(SimulatedOperation::Connect)?;
+        self.connected.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.failures.check(SimulatedOperation::Disconnect)?;
+        self.connected.store(false, Ordering::SeqCst);
+        self.subscriptions.lock().unwrap().clear();
+        Ok(())
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        self.ensure_connected()?;
+        self.failures.check(SimulatedOperation::DiscoverServices)?;
+        self.discovered.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        if !self.discovered.load(Ordering::SeqCst) {
+            return BTreeSet::new();
+        }
+        self.gatt
+            .iter()
+            .map(|(uuid, characteristics)| Service {
+                uuid: *uuid,
+                primary: true,
+                characteristics: characteristics.clone(),
+            })
+            .collect()
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.ensure_connected()?;
+        self.ensure_known(characteristic)?;
+        self.failures.check(SimulatedOperation::Read)?;
+        if !characteristic.properties.contains(CharPropFlags::READ) {
+            return Err(Error::NotSupported("Characteristic is not readable".to_string()));
+        }
+        Ok(self.value(characteristic.uuid).unwrap_or_default())
+    }
+
+    async fn write(&self, characteristic: &Characteristic, data: &[u8], _write_type: WriteType) -> Result<()> {
+        self.ensure_connected()?;
+        self.ensure_known(characteristic)?;
+        self.failures.check(SimulatedOperation::Write)?;
+        self.set_value(characteristic.uuid, data);
+        Ok(())
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        self.ensure_connected()?;
+        self.failures.check(SimulatedOperation::Read)?;
+        // The configuration follows the subscriptions, like on a real device
+        if descriptor.uuid == CLIENT_CONFIGURATION_UUID {
+            let subscribed = self.subscriptions.lock().unwrap().contains(&descriptor.characteristic_uuid);
+            return Ok(vec![subscribed as u8, 0]);
+        }
+        self.descriptor_values
+            .get(&(descriptor.characteristic_uuid, descriptor.uuid))
+            .cloned()
+            .ok_or(Error::NotSupported("No such descriptor".to_string()))
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.ensure_connected()?;
+        self.ensure_known(characteristic)?;
+        self.failures.check(SimulatedOperation::Subscribe)?;
+        if !self.subscriptions.lock().unwrap().insert(characteristic.uuid) {
+            return Ok(());
+        }
+
+        if let Some((value, interval)) = self.scripted_notifications.get(&characteristic.uuid).cloned() {
+            let uuid = characteristic.uuid;
+            let subscriptions = self.subscriptions.clone();
+            let listeners = self.listeners.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(interval).await;
+                    if !send_notification(&subscriptions, &listeners, uuid, &value) {
+                        break;
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.ensure_connected()?;
+        self.failures.check(SimulatedOperation::Unsubscribe)?;
+        self.subscriptions.lock().unwrap().remove(&characteristic.uuid);
+        Ok(())
+    }
+
+    async fn notifications(&self) -> Result<NotificationStream> {
+        let (sender, receiver) = unbounded();
+        self.listeners.lock().unwrap().push(sender);
+        Ok(Box::pin(receiver))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    const SERVICE: Uuid = Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb);
+    const CHARACTERISTIC: Uuid = Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb);
+
+    fn battery() -> SimulatedPeripheral {
+        SimulatedPeripheral::new("AA:BB:CC:DD:EE:01")
+            .with_name("Battery")
+            .with_rssi(-60)
+            .with_characteristic(SERVICE, CHARACTERISTIC, CharPropFlags::READ | CharPropFlags::NOTIFY, &[87])
+    }
+
+    async fn characteristic(peripheral: &SimulatedPeripheral) -> Characteristic {
+        peripheral.connect().await.unwrap();
+        peripheral.discover_services().await.unwrap();
+        let services = peripheral.services();
+        services.into_iter().flat_map(|service| service.characteristics).find(|c| c.uuid == CHARACTERISTIC).unwrap()
+    }
+
+    #[tokio::test]
+    async fn backend_lists_its_adapters() {
+        let backend = SimulatedBackend::new().with_adapter(SimulatedAdapter::new("hci0")).with_adapter(SimulatedAdapter::new("hci1"));
+        let adapters = backend.adapters().await.unwrap();
+        let mut names = Vec::new();
+        for adapter in adapters {
+            names.push(adapter.adapter_info().await.unwrap());
+        }
+        assert_eq!(names, ["hci0", "hci1"]);
+    }
+
+    #[tokio::test]
+    async fn peripherals_are_known_once_scanned() {
+        let adapter = SimulatedAdapter::new("hci0").with_peripheral(battery());
+        assert!(adapter.peripherals().await.unwrap().is_empty());
+
+        adapter.start_scan(ScanFilter::default()).await.unwrap();
+        assert!(adapter.is_scanning());
+        let peripherals = adapter.peripherals().await.unwrap();
+        assert_eq!(peripherals.len(), 1);
+        let properties = peripherals[0].properties().await.unwrap().unwrap();
+        assert_eq!(properties.local_name.as_deref(), Some("Battery"));
+        assert_eq!(properties.rssi, Some(-60));
+
+        adapter.stop_scan().await.unwrap();
+        assert!(!adapter.is_scanning());
+    }
+
+    #[tokio::test]
+    async fn scan_emits_discovered_then_updated_events() {
+        let adapter = SimulatedAdapter::new("hci0").with_peripheral(battery());
+        let mut events = adapter.events().await.unwrap();
+        adapter.start_scan(ScanFilter::default()).await.unwrap();
+
+        let first = tokio::time::timeout(Duration::from_secs(3), events.next()).await.unwrap().unwrap();
+        assert_eq!(first.kind, AdapterEventKind::Discovered);
+        assert_eq!(first.peripheral.id(), "AA:BB:CC:DD:EE:01");
+        let second = tokio::time::timeout(Duration::from_secs(3), events.next()).await.unwrap().unwrap();
+        assert_eq!(second.kind, AdapterEventKind::Updated);
+        adapter.stop_scan().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn gatt_operations_need_a_connection() {
+        let peripheral = battery();
+        let battery_level = characteristic(&peripheral).await;
+        assert_eq!(peripheral.read(&battery_level).await.unwrap(), [87]);
+
+        peripheral.write(&battery_level, &[42], WriteType::WithResponse).await.unwrap();
+        assert_eq!(peripheral.read(&battery_level).await.unwrap(), [42]);
+
+        peripheral.disconnect().await.unwrap();
+        assert!(matches!(peripheral.read(&battery_level).await, Err(Error::NotConnected)));
+    }
+
+    #[tokio::test]
+    async fn unknown_characteristics_are_rejected() {
+        let peripheral = battery();
+        let mut unknown = characteristic(&peripheral).await;
+        unknown.uuid = Uuid::from_u128(0x00002a00_0000_1000_8000_00805f9b34fb);
+        assert!(matches!(peripheral.read(&unknown).await, Err(Error::NoSuchCharacteristic)));
+    }
+
+    #[tokio::test]
+    async fn notifications_follow_subscriptions() {
+        let peripheral = battery();
+        let battery_level = characteristic(&peripheral).await;
+        let notifications = peripheral.notifications().await.unwrap();
+
+        peripheral.notify(CHARACTERISTIC, &[1]);
+        peripheral.subscribe(&battery_level).await.unwrap();
+        peripheral.notify(CHARACTERISTIC, &[2]);
+        peripheral.unsubscribe(&battery_level).await.unwrap();
+        peripheral.notify(CHARACTERISTIC, &[3]);
+        drop(peripheral);
+
+        let received: Vec<ValueNotification> = notifications.collect().await;
+        assert_eq!(received, [ValueNotification { uuid: CHARACTERISTIC, value: vec![2] }]);
+    }
+
+    #[tokio::test]
+    async fn injected_failures_are_consumed_one_per_call() {
+        let peripheral = battery();
+        peripheral.fail_next(SimulatedOperation::Connect, 2);
+        assert!(matches!(peripheral.connect().await, Err(Error::RuntimeError(_))));
+        assert!(peripheral.connect().await.is_err());
+        peripheral.connect().await.unwrap();
+        assert!(peripheral.is_connected().await.unwrap());
+
+        let adapter = SimulatedAdapter::new("hci0");
+        adapter.fail_next(SimulatedOperation::StartScan, 1);
+        assert!(adapter.start_scan(ScanFilter::default()).await.is_err());
+        assert!(!adapter.is_scanning());
+        adapter.start_scan(ScanFilter::default()).await.unwrap();
+        adapter.stop_scan().await.unwrap();
+    }
+}
@@ -1,78 +1,341 @@
-use btleplug::api::{Central, Manager as _, Peripheral as PeripheralTrait, ScanFilter};
-use btleplug::platform::Adapter;
-use std::error::Error;
+use btleplug::api::{PeripheralProperties, WriteType};
 use std::sync::Arc;
-use crate::device_storage::DeviceStorage;
-use crate::device_info::BluetoothDevice;
-use log::{info, debug};
+use uuid::Uuid;
+use crate::assigned_numbers::{self, UuidKind};
+use crate::device_storage::{DeviceStorage, Inventory};
+use crate::device_info::{format_hex, BluetoothDevice};
+use crate::error::BluetoothError;
+use crate::gatt_decode::{self, DecodedValue};
+use crate::gatt_export::GattProfile;
+use crate::backend::{AdapterEvent, AdapterEventKind, BleAdapter, BleBackend, BlePeripheral};
+use crate::atc;
+use crate::bthome::{self, BthomeError};
+use crate::connection_manager::{ConnectionInfo, ConnectionManager};
+use crate::metrics;
+use crate::mibeacon::{self, MiBeaconError};
+use crate::mj_ht_v1::MjHtV1Reading;
+use crate::presence::PresenceEvent;
+use crate::scan_filter::DeviceFilter;
+use crate::sensor::ReadingSource;
+use futures::StreamExt;
+use log::{info, debug, warn};
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
 
+/// How often devices not heard for the absence timeout are departed while scanning.
+const PRESENCE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Replayed MiBeacon frames in a row after which rejecting them is logged as a warning.
+const REPLAY_WARNING_COUNT: u32 = 10;
+
+/// How long a connection stays open after its last use by default.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Which of the backend's adapters a manager scans with.
+#[derive(Debug, Clone, Default)]
+pub enum AdapterSelection {
+    #[default]
+    First,
+    /// An adapter given by index or identifier.
+    One(String),
+    All,
+}
+
+/// An adapter of the backend and the identifier devices record their sightings under.
+#[derive(Debug, Clone)]
+pub struct AdapterEntry {
+    pub index: usize,
+    /// First word of the adapter info, e.g. `hci0`.
+    pub id: String,
+    pub info: String,
+    pub selected: bool,
+    adapter: Arc<dyn BleAdapter>,
+}
+
+/// A peripheral an adapter knows of, with the properties it last advertised.
+struct Sighting {
+    adapter: String,
+    peripheral: Arc<dyn BlePeripheral>,
+    properties: Option<PeripheralProperties>,
+}
+
+/// Outcome of polling one sensor with `poll_mj_ht_v1`.
+#[derive(Debug)]
+pub struct PollResult {
+    pub id: u32,
+    pub mac_address: String,
+    pub name: String,
+    pub result: Result<MjHtV1Reading, BluetoothError>,
+    /// Time from starting to connect until the reading arrived or the poll failed.
+    pub elapsed: Duration,
+}
+
+#[derive(Clone)]
 pub struct BluetoothManager {
-    adapter: Adapter,
+    adapters: Vec<AdapterEntry>,
+    filter: DeviceFilter,
+    connections: Arc<ConnectionManager>,
+    /// Scans running, so overlapping scans share the adapters' scan and only the last one
+    /// to finish stops it.
+    scans: Arc<tokio::sync::Mutex<usize>>,
+    /// Older MiBeacon frames rejected in a row per MAC address, to warn about devices stuck
+    /// behind their saved packet ID.
+    replays: Arc<std::sync::Mutex<HashMap<String, u32>>>,
 }
 
 impl BluetoothManager {
-    pub async fn new() -> Result<Self, Box<dyn Error>> {
+    pub async fn new(selection: AdapterSelection) -> Result<Self, BluetoothError> {
         info!("Creating new BluetoothManager instance...");
-        let manager = btleplug::platform::Manager::new().await?;
-        let adapters = manager.adapters().await?;
-        let adapter = adapters.into_iter().next().ok_or("No Bluetooth adapter found")?;
-        info!("Bluetooth adapter found: {:?}", adapter.adapter_info().await?);
-        Ok(BluetoothManager { adapter })
+        let manager = btleplug::platform::Manager::new().await.map_err(|e| BluetoothError::adapter("open the Bluetooth stack", e))?;
+        Self::with_backend(&manager, selection).await
     }
 
-    pub async fn scan(&self, storage: &mut DeviceStorage, duration: u8, attempts: u8) -> Result<(), Box<dyn Error>> {
+    /// Creates a manager scanning with the selected adapters of the given backend.
+    pub async fn with_backend(backend: &dyn BleBackend, selection: AdapterSelection) -> Result<Self, BluetoothError> {
+        let mut adapters = Vec::new();
+        let available = backend.adapters().await.map_err(|e| BluetoothError::adapter("list adapters", e))?;
+        for (index, adapter) in available.into_iter().enumerate() {
+            let info = adapter.adapter_info().await.map_err(|e| BluetoothError::adapter("query the adapter", e))?;
+            let id = info.split_whitespace().next().map_or_else(|| format!("adapter{}", index), str::to_string);
+            adapters.push(AdapterEntry { index, id, info, selected: false, adapter });
+        }
+        if adapters.is_empty() {
+            return Err(BluetoothError::NoAdapter("No Bluetooth adapter found".to_string()));
+        }
+
+        match &selection {
+            AdapterSelection::First => adapters[0].selected = true,
+            AdapterSelection::All => adapters.iter_mut().for_each(|entry| entry.selected = true),
+            AdapterSelection::One(selector) => {
+                let available = adapters.iter().map(|entry| format!("{} ({})", entry.index, entry.id)).collect::<Vec<_>>().join(", ");
+                let entry = adapters
+                    .iter_mut()
+                    .find(|entry| {
+                        selector.parse() == Ok(entry.index) || entry.id.eq_ignore_ascii_case(selector) || entry.info.eq_ignore_ascii_case(selector)
+                    })
+                    .ok_or_else(|| BluetoothError::NoAdapter(format!("No Bluetooth adapter '{}', available: {}", selector, available)))?;
+                entry.selected = true;
+            }
+        }
+        for entry in adapters.iter().filter(|entry| entry.selected) {
+            info!("Using Bluetooth adapter {}: {:?}", entry.index, entry.info);
+        }
+        Ok(BluetoothManager {
+            adapters,
+            filter: DeviceFilter::default(),
+            connections: Arc::new(ConnectionManager::new(DEFAULT_IDLE_TIMEOUT)),
+            scans: Arc::new(tokio::sync::Mutex::new(0)),
+            replays: Arc::new(std::sync::Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Every adapter of the backend, selected or not.
+    pub fn adapters(&self) -> &[AdapterEntry] {
+        &self.adapters
+    }
+
+    fn selected_adapters(&self) -> impl Iterator<Item = &AdapterEntry> {
+        self.adapters.iter().filter(|entry| entry.selected)
+    }
+
+    /// Connections unused for `idle_timeout` are closed, unless opened with `connect_device`.
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.connections = Arc::new(ConnectionManager::new(idle_timeout));
+        self
+    }
+
+    /// Only peripherals matching `filter` are added to the inventory.
+    pub fn with_filter(mut self, filter: DeviceFilter) -> Self {
+        if !filter.is_empty() {
+            info!("Using scan filter: {:?}", filter);
+        }
+        self.filter = filter;
+        self
+    }
+
+    pub async fn scan<I: Inventory>(&self, inventory: &mut I, duration: u8, attempts: u8) -> Result<(), BluetoothError> {
         info!("Starting scans of {} seconds with {} attempt(s)...", duration, attempts);
         for attempt in 1..=attempts {
             info!("Scan attempt {}/{}", attempt, attempts);
-            self.adapter.start_scan(ScanFilter::default()).await?;
-            tokio::time::sleep(std::time::Duration::from_secs(duration as u64)).await;
-            let peripherals = self.adapter.peripherals().await?;
+            self.discover(inventory, tokio::time::sleep(Duration::from_secs(duration as u64)), |_| true, |_| false).await?;
+        }
+        info!("Scan completed.");
+        Ok(())
+    }
 
-            for peripheral in peripherals {
-                if let Some(device) = self.create_bluetooth_device(peripheral).await {
-                    storage.add_or_update_device(device);
+    /// Scans until `stop` completes, storing devices as they advertise. A shared inventory
+    /// is only locked while an advertisement is stored.
+    pub async fn scan_until<I, S>(&self, inventory: &mut I, stop: S) -> Result<(), BluetoothError>
+    where
+        I: Inventory,
+        S: Future<Output = ()>,
+    {
+        info!("Starting continuous scan...");
+        self.discover(inventory, stop, |_| true, |_| false).await
+    }
+
+    /// Scans until `stop` completes, passing arrivals and departures to `on_event`.
+    pub async fn watch_presence<S, F>(&self, storage: &mut DeviceStorage, stop: S, mut on_event: F) -> Result<(), BluetoothError>
+    where
+        S: Future<Output = ()>,
+        F: FnMut(&PresenceEvent),
+    {
+        info!("Watching arrivals and departures...");
+        let mut events = storage.presence_events();
+        let forward = async {
+            loop {
+                match events.recv().await {
+                    Ok(event) => on_event(&event),
+                    Err(RecvError::Lagged(missed)) => warn!("Missed {} presence events", missed),
+                    // The storage holds the sender, so this doesn't happen while scanning
+                    Err(RecvError::Closed) => break,
                 }
             }
+        };
+        tokio::select! {
+            result = self.discover(storage, stop, |_| true, |_| false) => result,
+            _ = forward => Ok(()),
         }
-        info!("Scan completed.");
-        Ok(())
     }
 
+    /// Scans until the inventory holds `max_devices` MJ_HT_V1 sensors.
     pub async fn scan_for_mj_ht_v1_devices(
         &self,
         storage: &mut DeviceStorage,
         max_devices: u8,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), BluetoothError> {
         info!("Starting scan for up to {} MJ_HT_V1 devices...", max_devices);
-    
-        // Run scan until the max number of devices is found
-        while storage.count_devices_by_name("MJ_HT_V1") < max_devices as usize {
-            info!("Scanning for MJ_HT_V1 devices...");
-            self.adapter.start_scan(ScanFilter::default()).await?;
-            tokio::time::sleep(std::time::Duration::from_secs(5)).await; // Scan for 5 seconds each iteration
-    
-            let peripherals = self.adapter.peripherals().await?;
-            for peripheral in peripherals {
-                if let Some(device) = self.create_bluetooth_device(peripheral).await {
-                    if device.name == "MJ_HT_V1" {
-                        storage.add_or_update_device(device);
-    
-                        // Check if we reached the maximum number of devices
-                        if storage.count_devices_by_name("MJ_HT_V1") >= max_devices as usize {
-                            info!("Found {} MJ_HT_V1 devices, stopping scan.", max_devices);
-                            return Ok(());
+        let enough = |storage: &DeviceStorage| storage.count_devices_by_name("MJ_HT_V1") >= max_devices as usize;
+        self.discover(storage, std::future::pending(), |device| device.name == "MJ_HT_V1", enough).await?;
+        info!("Scan completed with {} MJ_HT_V1 devices found.", storage.count_devices_by_name("MJ_HT_V1"));
+        Ok(())
+    }
+
+    /// Scans until `done` holds or `until` completes, storing each device `accept`s as soon
+    /// as it advertises. The scan is stopped before returning.
+    async fn discover<I, U, A, D>(&self, inventory: &mut I, until: U, accept: A, done: D) -> Result<(), BluetoothError>
+    where
+        I: Inventory,
+        U: Future<Output = ()>,
+        A: Fn(&BluetoothDevice) -> bool,
+        D: Fn(&DeviceStorage) -> bool,
+    {
+        if inventory.update(|storage| done(storage)).await {
+            return Ok(());
+        }
+        // Events of all adapters, tagged with the identifier of the adapter they came from
+        let mut streams = Vec::new();
+        for entry in self.selected_adapters() {
+            let id = entry.id.clone();
+            let events = entry.adapter.events().await.map_err(|e| BluetoothError::adapter("listen to adapter events", e))?.map(move |event| (id.clone(), event));
+            streams.push(Box::pin(events));
+        }
+        let mut events = futures::stream::select_all(streams);
+        // Devices seen shortly before start up are still around, before any sighting counts
+        inventory.update(|storage| storage.check_presence()).await;
+        self.start_scan().await?;
+        let result = async {
+            metrics::record_scan();
+
+            // Peripherals known from earlier scans aren't necessarily announced again
+            for entry in self.selected_adapters() {
+                for peripheral in entry.adapter.peripherals().await.map_err(|e| BluetoothError::adapter("list peripherals", e))? {
+                    self.store_peripheral(peripheral, &entry.id, inventory, &accept).await;
+                }
+            }
+
+            tokio::pin!(until);
+            let mut presence_check = tokio::time::interval(PRESENCE_CHECK_INTERVAL);
+            while !inventory.update(|storage| done(storage)).await {
+                tokio::select! {
+                    event = events.next() => match event {
+                        Some((adapter, AdapterEvent { kind: kind @ (AdapterEventKind::Connected | AdapterEventKind::Disconnected), peripheral })) => {
+                            debug!("{:?} event from {} on {}", kind, peripheral.id(), adapter);
+                            self.connections.connection_changed(&peripheral.id(), kind == AdapterEventKind::Connected);
+                        }
+                        Some((adapter, AdapterEvent { kind, peripheral })) => {
+                            debug!("{:?} event from {} on {}", kind, peripheral.id(), adapter);
+                            let mac_address = peripheral.id().to_string();
+                            if self.store_peripheral(peripheral, &adapter, inventory, &accept).await {
+                                inventory.update(|storage| storage.record_sighting(&mac_address, &adapter)).await;
+                            }
                         }
+                        None => {
+                            warn!("Adapters stopped sending events");
+                            break;
+                        }
+                    },
+                    _ = presence_check.tick() => inventory.update(|storage| storage.check_presence()).await,
+                    _ = &mut until => break,
+                }
+            }
+            Ok::<_, BluetoothError>(())
+        }
+        .await;
+
+        self.stop_scan().await;
+        result
+    }
+
+    /// Starts the adapters' scan unless another scan is running already.
+    async fn start_scan(&self) -> Result<(), BluetoothError> {
+        let mut scans = self.scans.lock().await;
+        if *scans == 0 {
+            let mut started = Vec::new();
+            for entry in self.selected_adapters() {
+                if let Err(e) = entry.adapter.start_scan(self.filter.scan_filter()).await {
+                    for entry in started {
+                        Self::stop_adapter_scan(entry).await;
                     }
+                    return Err(BluetoothError::adapter("start scanning", e));
                 }
+                started.push(entry);
             }
-            info!("Scan iteration completed.");
         }
-    
-        info!("Scan completed with {} MJ_HT_V1 devices found.", storage.count_devices_by_name("MJ_HT_V1"));
+        *scans += 1;
         Ok(())
     }
 
-    pub async fn retrieve_device_info(&self, device_id: u32, storage: &DeviceStorage) -> Result<(), Box<dyn std::error::Error>> {
+    /// Stops the adapters' scan when no other scan is running.
+    async fn stop_scan(&self) {
+        let mut scans = self.scans.lock().await;
+        *scans = scans.saturating_sub(1);
+        if *scans == 0 {
+            for entry in self.selected_adapters() {
+                Self::stop_adapter_scan(entry).await;
+            }
+        }
+    }
+
+    async fn stop_adapter_scan(entry: &AdapterEntry) {
+        if let Err(e) = entry.adapter.stop_scan().await {
+            warn!("Failed to stop scan on {}: {}", entry.id, e);
+        }
+    }
+
+    /// Whether the peripheral was stored.
+    async fn store_peripheral<I, A>(&self, peripheral: Arc<dyn BlePeripheral>, adapter: &str, inventory: &mut I, accept: &A) -> bool
+    where
+        I: Inventory,
+        A: Fn(&BluetoothDevice) -> bool,
+    {
+        let Some(sighting) = Self::sighting(peripheral, adapter).await else {
+            return false;
+        };
+        inventory
+            .update(|storage| match self.create_bluetooth_device(sighting, storage) {
+                Some(device) if accept(&device) => {
+                    storage.add_or_update_device(device);
+                    true
+                }
+                _ => false,
+            })
+            .await
+    }
+
+    pub async fn retrieve_device_info(&self, device_id: u32, storage: &DeviceStorage) -> Result<(), BluetoothError> {
         self.with_device(device_id, storage, |device| async move {
             info!("Retrieving detailed information...");
             device.retrieve_additional_info().await?;
@@ -80,7 +343,7 @@ impl BluetoothManager {
         }).await
     }
     
-    pub async fn list_available_info(&self, device_id: u32, storage: &DeviceStorage) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn list_available_info(&self, device_id: u32, storage: &DeviceStorage) -> Result<(), BluetoothError> {
         self.with_device(device_id, storage, |device| async move {
             info!("Listing available information...");
             device.list_available_info().await?;
@@ -88,15 +351,43 @@ impl BluetoothManager {
         }).await
     }
 
-    pub async fn retrieve_temperature_and_humidity(&self, device_id: u32, storage: &DeviceStorage) -> Result<(), Box<dyn std::error::Error>> {
-        self.with_device(device_id, storage, |device| async move {
+    /// Streams MJ_HT_V1 readings to `on_reading` until `stop` completes or the device goes away.
+    /// Each reading becomes the device's latest sensor values.
+    pub async fn retrieve_temperature_and_humidity<S, F>(&self, device_id: u32, storage: &mut DeviceStorage, stop: S, mut on_reading: F) -> Result<(), BluetoothError>
+    where
+        S: Future<Output = ()>,
+        F: FnMut(&MjHtV1Reading),
+    {
+        let device = storage.get_device(device_id).ok_or_else(|| BluetoothError::DeviceNotFound(device_id.to_string()))?;
+        self.with_connection(Arc::new(device.clone()), |device| async move {
             info!("Subscribing to temperature and humidity notifications...");
-            device.subscribe_to_mj_ht_v1_notifications().await?;
+            let mut readings = device.mj_ht_v1_readings().await?;
+            tokio::pin!(stop);
+            loop {
+                tokio::select! {
+                    reading = readings.next() => match reading {
+                        Some(reading) => {
+                            storage.record_mj_ht_v1_reading(device_id, &reading);
+                            on_reading(&reading);
+                        }
+                        None => {
+                            warn!("Notifications from device {} stopped", device.mac_address);
+                            break;
+                        }
+                    },
+                    _ = &mut stop => {
+                        info!("Stopping temperature and humidity notifications");
+                        break;
+                    }
+                }
+            }
+            drop(readings);
+            device.unsubscribe_from_mj_ht_v1_notifications().await?;
             Ok(())
         }).await
     }
 
-    pub async fn read_mj_ht_v1_information(&self, device_id: u32, storage: &DeviceStorage) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn read_mj_ht_v1_information(&self, device_id: u32, storage: &DeviceStorage) -> Result<(), BluetoothError> {
         self.with_device(device_id, storage, |device| async move {
             info!("Printing all MJ_HT_V1 characteristics...");
             device.read_mj_ht_v1_information().await?;
@@ -104,35 +395,80 @@ impl BluetoothManager {
         }).await
     }
 
-    // Connect with a device
-    pub async fn connect_device(&self, device_id: u32, storage: &DeviceStorage) -> Result<(), Box<dyn std::error::Error>> {
+    // Connect with a device and keep the connection open until it is disconnected
+    pub async fn connect_device(&self, device_id: u32, storage: &DeviceStorage) -> Result<(), BluetoothError> {
+        let device = storage.get_device(device_id).ok_or_else(|| BluetoothError::DeviceNotFound(device_id.to_string()))?;
+        info!("Connecting to device...");
+        self.connections.open(device, true).await?;
+        println!("Connected to {}, the connection stays open until you disconnect", device.mac_address);
+        Ok(())
+    }
+
+    // Disconnect from a device
+    pub async fn disconnect_device(&self, device_id: u32, storage: &DeviceStorage) -> Result<(), BluetoothError> {
+        let device = storage.get_device(device_id).ok_or_else(|| BluetoothError::DeviceNotFound(device_id.to_string()))?;
+        info!("Disconnecting from device...");
+        self.connections.close(device).await
+    }
+
+    /// Connections opened so far and their state.
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        self.connections.list()
+    }
+
+    /// Disconnects every device still connected.
+    pub async fn close_connections(&self) {
+        self.connections.close_all().await;
+    }
+
+    // Read characteristic value
+    pub async fn read_characteristic(&self, device_id: u32, storage: &DeviceStorage, service_uuid: &str, characteristic_uuid: &str) -> Result<(), BluetoothError> {
         self.with_device(device_id, storage, |device| async move {
-            info!("Connecting to device...");
-            device.connect().await?;
+            info!("Reading characteristic value...");
+            let value = device.read_characteristic(service_uuid, characteristic_uuid).await?;
+            let decoded = self.decode_value(&device, service_uuid, characteristic_uuid, &value).await?;
+            println!("{}: {}", assigned_numbers::describe_str(characteristic_uuid, UuidKind::Characteristic), gatt_decode::display(decoded.as_ref(), &value));
             Ok(())
         }).await
     }
 
-    // Disconnect from a device
-    pub async fn disconnect_device(&self, device_id: u32, storage: &DeviceStorage) -> Result<(), Box<dyn std::error::Error>> {
+    /// Decodes a value read from the device, with the characteristic's Presentation Format if
+    /// it is not a standard characteristic. The descriptors are only read on the first read
+    /// over a connection.
+    pub async fn decode_value(&self, device: &BluetoothDevice, service_uuid: &str, characteristic_uuid: &str, value: &[u8]) -> Result<Option<DecodedValue>, BluetoothError> {
+        let descriptors = match self.connections.cached_descriptors(&device.mac_address, service_uuid, characteristic_uuid) {
+            Some(descriptors) => descriptors,
+            None => {
+                let descriptors = device.characteristic_descriptors(service_uuid, characteristic_uuid).await?;
+                self.connections.cache_descriptors(&device.mac_address, service_uuid, characteristic_uuid, descriptors.clone());
+                descriptors
+            }
+        };
+        let Ok(uuid) = Uuid::parse_str(characteristic_uuid) else { return Ok(None) };
+        Ok(gatt_decode::decode(&uuid, descriptors.presentation_format.as_ref(), value))
+    }
+
+    // Write characteristic value
+    pub async fn write_characteristic(&self, device_id: u32, storage: &DeviceStorage, service_uuid: &str, characteristic_uuid: &str, value: &[u8], write_type: WriteType) -> Result<(), BluetoothError> {
         self.with_device(device_id, storage, |device| async move {
-            info!("Disconnecting from device...");
-            device.disconnect().await?;
+            info!("Writing characteristic value...");
+            device.write_characteristic(service_uuid, characteristic_uuid, value, write_type).await?;
+            println!("{}: wrote {}", assigned_numbers::describe_str(characteristic_uuid, UuidKind::Characteristic), format_hex(value));
             Ok(())
         }).await
     }
 
-    // Read characteristic value
-    pub async fn read_characteristic(&self, device_id: u32, storage: &DeviceStorage, service_uuid: &str, characteristic_uuid: &str) -> Result<(), Box<dyn std::error::Error>> {
+    // Print notifications of a characteristic
+    pub async fn subscribe_characteristic(&self, device_id: u32, storage: &DeviceStorage, service_uuid: &str, characteristic_uuid: &str, count: usize) -> Result<(), BluetoothError> {
         self.with_device(device_id, storage, |device| async move {
-            info!("Reading characteristic value...");
-            device.read_characteristic(service_uuid, characteristic_uuid).await?;
+            info!("Subscribing to characteristic notifications...");
+            device.watch_characteristic(service_uuid, characteristic_uuid, count).await?;
             Ok(())
         }).await
     }
 
     // Discover services and characteristics
-    pub async fn discover_services (&self, device_id: u32, storage: &DeviceStorage) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn discover_services (&self, device_id: u32, storage: &DeviceStorage) -> Result<(), BluetoothError> {
         self.with_device(device_id, storage, |device| async move {
             info!("Discovering services and characteristics...");
             device.discover_services().await?;
@@ -140,8 +476,15 @@ impl BluetoothManager {
         }).await
     }
 
+    /// Dumps the GATT table of a device, see `BluetoothDevice::gatt_profile`.
+    pub async fn export_gatt(&self, device_id: u32, storage: &DeviceStorage, read_values: bool) -> Result<GattProfile, BluetoothError> {
+        let device = storage.get_device(device_id).ok_or_else(|| BluetoothError::DeviceNotFound(device_id.to_string()))?;
+        info!("Exporting the GATT table...");
+        self.with_connection(Arc::new(device.clone()), |device| async move { device.gatt_profile(read_values).await }).await
+    }
+
     // Read MJ_HT_V1 sensor data
-    pub async fn read_mj_ht_v1(&self, device_id: u32, storage: &DeviceStorage) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn read_mj_ht_v1(&self, device_id: u32, storage: &DeviceStorage) -> Result<(), BluetoothError> {
         self.with_device(device_id, storage, |device| async move {
             info!("Reading MJ_HT_V1 sensor data...");
             device.read_mj_ht_v1().await?;
@@ -149,33 +492,488 @@ impl BluetoothManager {
         }).await
     }
 
+    /// Takes one temperature and humidity reading from each of the given MJ_HT_V1 sensors,
+    /// connecting to at most `max_connections` of them at a time. Each sensor gets `timeout`
+    /// to connect and deliver a reading, which becomes its latest sensor values. Results are in
+    /// the order of `device_ids`.
+    pub async fn poll_mj_ht_v1(&self, device_ids: &[u32], storage: &mut DeviceStorage, max_connections: usize, timeout: Duration) -> Vec<PollResult> {
+        info!("Polling {} MJ_HT_V1 sensors, {} at a time...", device_ids.len(), max_connections);
+        let devices: Vec<(u32, Option<BluetoothDevice>)> = device_ids.iter().map(|&id| (id, storage.get_device(id).cloned())).collect();
+        let polls = devices.into_iter().map(|(id, device)| {
+            async move {
+                let started = std::time::Instant::now();
+                let Some(device) = device else {
+                    return PollResult {
+                        id,
+                        mac_address: String::new(),
+                        name: String::new(),
+                        result: Err(BluetoothError::DeviceNotFound(id.to_string())),
+                        elapsed: started.elapsed(),
+                    };
+                };
+                let result = match tokio::time::timeout(timeout, self.poll_one_mj_ht_v1(Arc::new(device.clone()))).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        // The poll may have been cut off while subscribed; a pinned connection
+                        // stays open, so its notifications would keep coming
+                        if let Err(e) = device.unsubscribe_from_mj_ht_v1_notifications().await {
+                            debug!("Failed to unsubscribe from {} after the timeout: {}", device.mac_address, e);
+                        }
+                        Err(BluetoothError::Elapsed { mac: device.mac_address.clone(), operation: "poll a reading", timeout })
+                    }
+                };
+                // Free the slot for the next sensor, unless the connection was opened on purpose
+                if let Err(e) = self.connections.close_unpinned(&device).await {
+                    debug!("Failed to close the connection to {}: {}", device.mac_address, e);
+                }
+                match &result {
+                    Ok(reading) => debug!("Sensor {} read {:.1} °C, {:.1} %", device.mac_address, reading.temperature, reading.humidity),
+                    Err(e) => warn!("Failed to poll sensor {}: {}", device.mac_address, e),
+                }
+                PollResult { id, mac_address: device.mac_address.clone(), name: device.display_name().to_string(), result, elapsed: started.elapsed() }
+            }
+        });
+        let results: Vec<PollResult> = futures::stream::iter(polls).buffered(max_connections.max(1)).collect().await;
+        for poll in &results {
+            if let Ok(reading) = &poll.result {
+                storage.record_mj_ht_v1_reading(poll.id, reading);
+            }
+        }
+        results
+    }
+
+    async fn poll_one_mj_ht_v1(&self, device: Arc<BluetoothDevice>) -> Result<MjHtV1Reading, BluetoothError> {
+        self.with_connection(device, |device| async move {
+            let mut readings = device.mj_ht_v1_readings().await?;
+            let reading = readings.next().await;
+            drop(readings);
+            device.unsubscribe_from_mj_ht_v1_notifications().await?;
+            reading.ok_or_else(|| BluetoothError::NotConnected { mac: device.mac_address.clone() })
+        }).await
+    }
+
     /// Helper method to reduce code duplication when working with devices.
     async fn with_device<F, Fut>(
         &self,
         device_id: u32,
         storage: &DeviceStorage,
         f: F,
-    ) -> Result<(), Box<dyn std::error::Error>>
+    ) -> Result<(), BluetoothError>
     where
         F: FnOnce(Arc<BluetoothDevice>) -> Fut,
-        Fut: std::future::Future<Output = Result<(), Box<dyn std::error::Error>>>,
+        Fut: std::future::Future<Output = Result<(), BluetoothError>>,
     {
         if let Some(device) = storage.get_device(device_id).map(|d| Arc::new(d.clone())) {
-            f(device).await
+            self.with_connection(device, f).await
         } else {
-            Err("Device not found".into())
+            Err(BluetoothError::DeviceNotFound(device_id.to_string()))
         }
     }
 
-    /// Helper method to create a BluetoothDevice from a peripheral.
-    async fn create_bluetooth_device(&self, peripheral: btleplug::platform::Peripheral) -> Option<BluetoothDevice> {
+    /// Runs `f` on the device once it is connected with its services discovered. The
+    /// connection is left open for the next operation.
+    pub async fn with_connection<F, Fut, T>(&self, device: Arc<BluetoothDevice>, f: F) -> Result<T, BluetoothError>
+    where
+        F: FnOnce(Arc<BluetoothDevice>) -> Fut,
+        Fut: std::future::Future<Output = Result<T, BluetoothError>>,
+    {
+        let _lease = self.connections.open(&device, false).await?;
+        f(device).await
+    }
+
+    async fn sighting(peripheral: Arc<dyn BlePeripheral>, adapter: &str) -> Option<Sighting> {
         let properties = peripheral.properties().await.ok()?;
+        Some(Sighting { adapter: adapter.to_string(), peripheral, properties })
+    }
+
+    /// Helper method to create a BluetoothDevice from a peripheral.
+    fn create_bluetooth_device(&self, sighting: Sighting, storage: &DeviceStorage) -> Option<BluetoothDevice> {
+        let Sighting { adapter, peripheral, properties } = sighting;
         let name = properties.as_ref().and_then(|props| props.local_name.clone()).unwrap_or("Unknown Device".to_string());
         let rssi = properties.as_ref().and_then(|props| props.rssi).unwrap_or(0);
         let mac_address = peripheral.id().to_string();
 
+        if !self.filter.matches(&mac_address, properties.as_ref()) {
+            debug!("Device filtered out: MAC={}, Name={}, RSSI={}", mac_address, name, rssi);
+            return None;
+        }
         debug!("Device found: MAC={}, Name={}, RSSI={}", mac_address, name, rssi);
 
-        Some(BluetoothDevice::new(mac_address, name, rssi, Arc::new(peripheral)))
+        let mut device = BluetoothDevice::new(mac_address, name, rssi, &adapter, peripheral);
+        device.tx_power = properties.as_ref().and_then(|props| props.tx_power_level);
+        device.manufacturer_ids = properties.iter().flat_map(|props| props.manufacturer_data.keys().copied()).collect();
+        if let Some(properties) = &properties {
+            self.decode_service_data(&mut device, &properties.service_data, storage);
+        }
+        Some(device)
+    }
+
+    /// Decodes sensor values broadcast in the advertisement's service data. Bind keys and
+    /// replay state come from the inventory entry of the same MAC address.
+    fn decode_service_data(&self, device: &mut BluetoothDevice, service_data: &HashMap<Uuid, Vec<u8>>, storage: &DeviceStorage) {
+        if let Some(data) = service_data.get(&mibeacon::SERVICE_UUID) {
+            let known = storage.get_device_by_mac(&device.mac_address);
+            let bind_key = match known.and_then(|d| d.bind_key.as_deref()).map(mibeacon::parse_bind_key) {
+                Some(Ok(key)) => Some(key),
+                Some(Err(e)) => {
+                    warn!("Ignoring bind key of {}: {}", device.mac_address, e);
+                    None
+                }
+                None => None,
+            };
+
+            let last_packet_id = known.and_then(|d| d.mibeacon_packet_id);
+            match mibeacon::parse(data, &device.mac_address, bind_key.as_ref()) {
+                Ok(frame) if !frame.is_newer_than(last_packet_id) && !frame.is_restart_after(last_packet_id) => {
+                    self.reject_replay(&device.mac_address, frame.packet_id, last_packet_id);
+                }
+                Ok(frame) => {
+                    if frame.is_restart_after(last_packet_id) {
+                        warn!(
+                            "MiBeacon packet ID of {} went back from {} to {}, assuming the device restarted",
+                            device.mac_address, last_packet_id.unwrap_or_default(), frame.packet_id
+                        );
+                    }
+                    self.replays.lock().unwrap().remove(&device.mac_address);
+                    debug!("MiBeacon frame from {}: product {:#06x}, packet {}, {:?}", device.mac_address, frame.product_id, frame.packet_id, frame.measurements);
+                    if frame.encrypted {
+                        device.mibeacon_packet_id = Some(frame.packet_id);
+                    }
+                    device.apply_measurements(ReadingSource::MiBeacon, &frame.measurements);
+                }
+                Err(MiBeaconError::AuthenticationFailed) => {
+                    warn!("Rejecting MiBeacon frame from {}: authentication failed, check the bind key", device.mac_address);
+                }
+                Err(MiBeaconError::NotEncrypted) => {
+                    warn!("Rejecting unencrypted MiBeacon frame from {}, which has a bind key", device.mac_address);
+                }
+                Err(e) => debug!("Ignoring MiBeacon frame from {}: {}", device.mac_address, e),
+            }
+        }
+
+        if let Some(data) = service_data.get(&atc::SERVICE_UUID) {
+            match atc::parse(data) {
+                Some(frame) => {
+                    debug!("{} frame from {}: counter {}, {:?}", frame.format, device.mac_address, frame.frame_counter, frame.measurements);
+                    device.apply_measurements(frame.format, &frame.measurements);
+                }
+                None => debug!("Ignoring 0x181A service data of unknown length {} from {}", data.len(), device.mac_address),
+            }
+        }
+
+        if let Some(data) = service_data.get(&bthome::SERVICE_UUID) {
+            let known = storage.get_device_by_mac(&device.mac_address);
+            let bind_key = known.and_then(|d| d.bind_key.as_deref()).and_then(|key| mibeacon::parse_bind_key(key).ok());
+
+            match bthome::parse(data, &device.mac_address, bind_key.as_ref()) {
+                Ok(frame) if !frame.is_newer_than(known.and_then(|d| d.bthome_counter)) => {
+                    debug!("Rejecting replayed BTHome frame {:?} from {}", frame.counter, device.mac_address);
+                }
+                Ok(frame) => {
+                    debug!("BTHome frame from {}: packet {:?}, {:?}", device.mac_address, frame.packet_id, frame.measurements);
+                    if frame.counter.is_some() {
+                        device.bthome_counter = frame.counter;
+                    }
+                    device.apply_measurements(ReadingSource::BTHome, &frame.measurements);
+                }
+                Err(BthomeError::AuthenticationFailed) => {
+                    warn!("Rejecting BTHome frame from {}: authentication failed, check the bind key", device.mac_address);
+                }
+                Err(BthomeError::NotEncrypted) => {
+                    warn!("Rejecting unencrypted BTHome frame from {}, which has a bind key", device.mac_address);
+                }
+                Err(e) => debug!("Ignoring BTHome frame from {}: {}", device.mac_address, e),
+            }
+        }
+    }
+
+    /// Logs a rejected MiBeacon frame. Devices repeat each advertisement, so the same packet
+    /// ID again is expected; older ones are counted and warned about once they keep coming.
+    fn reject_replay(&self, mac_address: &str, packet_id: u32, last_packet_id: Option<u32>) {
+        if Some(packet_id) == last_packet_id {
+            debug!("Ignoring repeated MiBeacon frame {} from {}", packet_id, mac_address);
+            return;
+        }
+        let mut replays = self.replays.lock().unwrap();
+        let count = replays.entry(mac_address.to_string()).or_default();
+        *count += 1;
+        if count.is_multiple_of(REPLAY_WARNING_COUNT) {
+            warn!(
+                "Rejected {} replayed MiBeacon frames in a row from {}, latest packet {} after {}; set its bind key again to reset the packet ID if the device restarted",
+                count, mac_address, packet_id, last_packet_id.unwrap_or_default()
+            );
+        } else {
+            debug!("Rejecting replayed MiBeacon frame {} from {}", packet_id, mac_address);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::simulated::{SimulatedAdapter, SimulatedBackend, SimulatedOperation, SimulatedPeripheral};
+    use btleplug::api::CharPropFlags;
+
+    const MJ_HT_V1_SERVICE: Uuid = Uuid::from_u128(0x226c0000_6476_4566_7562_66734470666d);
+    const MJ_HT_V1_TEMPERATURE_HUMIDITY: Uuid = Uuid::from_u128(0x226caa55_6476_4566_7562_66734470666d);
+    const MJ_HT_V1_HUMIDITY: Uuid = Uuid::from_u128(0x226cbb55_6476_4566_7562_66734470666d);
+    const VENDOR_TEMPERATURE: Uuid = Uuid::from_u128(0x226ccc55_6476_4566_7562_66734470666d);
+    const PRESENTATION_FORMAT: Uuid = Uuid::from_u128(0x00002904_0000_1000_8000_00805f9b34fb);
+
+    /// An MJ_HT_V1 sensor, sending readings while subscribed if `notifying`.
+    fn mj_ht_v1(address: &str, notifying: bool) -> SimulatedPeripheral {
+        let sensor = SimulatedPeripheral::new(address)
+            .with_name("MJ_HT_V1")
+            .with_characteristic(MJ_HT_V1_SERVICE, MJ_HT_V1_TEMPERATURE_HUMIDITY, CharPropFlags::NOTIFY, &[])
+            .with_characteristic(MJ_HT_V1_SERVICE, MJ_HT_V1_HUMIDITY, CharPropFlags::NOTIFY, &[]);
+        if notifying {
+            sensor.with_notification(MJ_HT_V1_TEMPERATURE_HUMIDITY, b"T=23.4 H=45.6\0", Duration::from_millis(100))
+        } else {
+            sensor
+        }
+    }
+
+    /// Adds the peripherals to a new inventory, returning it and their IDs.
+    fn inventory(peripherals: &[Arc<SimulatedPeripheral>]) -> (DeviceStorage, Vec<u32>) {
+        let mut storage = DeviceStorage::new();
+        let ids = peripherals
+            .iter()
+            .map(|peripheral| {
+                let mac_address = peripheral.id();
+                storage.add_or_update_device(BluetoothDevice::new(mac_address.clone(), "MJ_HT_V1".to_string(), -60, "hci0", peripheral.clone()));
+                storage.find_device(&mac_address).unwrap()
+            })
+            .collect();
+        (storage, ids)
+    }
+
+    fn backend() -> SimulatedBackend {
+        SimulatedBackend::new().with_adapter(SimulatedAdapter::new("hci0").with_peripheral(SimulatedPeripheral::new("AA:BB:CC:DD:EE:01").with_name("Tag")))
+    }
+
+    #[tokio::test]
+    async fn overlapping_scans_share_the_adapter_scan() {
+        let backend = backend();
+        let manager = BluetoothManager::with_backend(&backend, AdapterSelection::First).await.unwrap();
+        let adapter = backend.adapter(0);
+        let (mut long, mut short) = (DeviceStorage::new(), DeviceStorage::new());
+
+        let check = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert!(adapter.is_scanning());
+            // The short scan ended, the long one keeps scanning
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            assert!(adapter.is_scanning());
+        };
+        let (long_result, short_result, ()) = tokio::join!(
+            manager.scan_until(&mut long, tokio::time::sleep(Duration::from_millis(600))),
+            manager.scan_until(&mut short, tokio::time::sleep(Duration::from_millis(200))),
+            check,
+        );
+        long_result.unwrap();
+        short_result.unwrap();
+        assert!(!adapter.is_scanning());
+        assert_eq!(long.count_devices_by_name("Tag"), 1);
+    }
+
+    #[tokio::test]
+    async fn polls_respect_the_connection_limit() {
+        let backend = backend();
+        let manager = BluetoothManager::with_backend(&backend, AdapterSelection::First).await.unwrap();
+        let sensors: Vec<_> = (1..=4).map(|n| Arc::new(mj_ht_v1(&format!("4C:65:A8:D0:00:0{}", n), true))).collect();
+        let (mut storage, ids) = inventory(&sensors);
+
+        let mut most_in_use = 0;
+        let results = {
+            let poll = manager.poll_mj_ht_v1(&ids, &mut storage, 2, Duration::from_secs(10));
+            tokio::pin!(poll);
+            loop {
+                tokio::select! {
+                    results = &mut poll => break results,
+                    _ = tokio::time::sleep(Duration::from_millis(20)) => {
+                        let in_use = manager.connections().iter().filter(|connection| connection.users > 0).count();
+                        most_in_use = most_in_use.max(in_use);
+                    }
+                }
+            }
+        };
+
+        assert_eq!(most_in_use, 2);
+        assert_eq!(results.iter().map(|poll| poll.id).collect::<Vec<_>>(), ids);
+        for poll in &results {
+            let reading = poll.result.as_ref().unwrap();
+            assert_eq!((reading.temperature, reading.humidity), (23.4, 45.6));
+            assert!(storage.get_device(poll.id).unwrap().sensor.is_some());
+        }
+        // Sensors only connected for the poll are disconnected again
+        for sensor in &sensors {
+            assert!(!sensor.is_connected().await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn silent_sensors_time_out_and_are_unsubscribed() {
+        let backend = backend();
+        let manager = BluetoothManager::with_backend(&backend, AdapterSelection::First).await.unwrap();
+        let silent = Arc::new(mj_ht_v1("4C:65:A8:D0:00:01", false));
+        let notifying = Arc::new(mj_ht_v1("4C:65:A8:D0:00:02", true));
+        let (mut storage, ids) = inventory(&[silent.clone(), notifying.clone()]);
+        manager.connect_device(ids[0], &storage).await.unwrap();
+
+        let results = manager.poll_mj_ht_v1(&ids, &mut storage, 2, Duration::from_secs(4)).await;
+
+        assert!(matches!(results[0].result, Err(BluetoothError::Elapsed { .. })), "{:?}", results[0].result);
+        assert!(results[0].elapsed < Duration::from_secs(5));
+        assert!(results[1].result.is_ok());
+        // The pinned connection stays open, without the notifications
+        assert!(silent.is_connected().await.unwrap());
+        assert!(!silent.is_subscribed(MJ_HT_V1_TEMPERATURE_HUMIDITY));
+        assert!(!silent.is_subscribed(MJ_HT_V1_HUMIDITY));
+    }
+
+    #[tokio::test]
+    async fn failed_scan_start_releases_the_scan() {
+        let backend = backend();
+        let manager = BluetoothManager::with_backend(&backend, AdapterSelection::First).await.unwrap();
+        let adapter = backend.adapter(0);
+        adapter.fail_next(SimulatedOperation::StartScan, 1);
+        let mut storage = DeviceStorage::new();
+        assert!(manager.scan_until(&mut storage, std::future::ready(())).await.is_err());
+
+        manager.scan_until(&mut storage, tokio::time::sleep(Duration::from_millis(100))).await.unwrap();
+        assert!(!adapter.is_scanning());
+    }
+
+    #[tokio::test]
+    async fn descriptors_are_read_once_per_connection() {
+        let backend = backend();
+        let manager = BluetoothManager::with_backend(&backend, AdapterSelection::First).await.unwrap();
+        let sensor = Arc::new(
+            SimulatedPeripheral::new("4C:65:A8:D0:00:01")
+                .with_characteristic(MJ_HT_V1_SERVICE, VENDOR_TEMPERATURE, CharPropFlags::READ, &[0x24, 0x09])
+                .with_descriptor(VENDOR_TEMPERATURE, PRESENTATION_FORMAT, &[0x0e, 0xfe, 0x2f, 0x27, 0x01, 0x00, 0x00]),
+        );
+        let (storage, ids) = inventory(std::slice::from_ref(&sensor));
+        let device = Arc::new(storage.get_device(ids[0]).unwrap().clone());
+        let (service, characteristic) = (MJ_HT_V1_SERVICE.to_string(), VENDOR_TEMPERATURE.to_string());
+        let temperature = Some(DecodedValue::Quantity { value: 23.4, decimals: Some(2), unit: Some("°C") });
+
+        manager.connect_device(ids[0], &storage).await.unwrap();
+        let (manager_ref, sensor_ref) = (&manager, &sensor);
+        let decoded = manager.with_connection(device.clone(), |device| async move {
+            let first = manager_ref.decode_value(&device, &service, &characteristic, &[0x24, 0x09]).await?;
+            // A failing descriptor read would leave the format out, so this one comes from the cache
+            sensor_ref.fail_next(SimulatedOperation::Read, 1);
+            let second = manager_ref.decode_value(&device, &service, &characteristic, &[0x24, 0x09]).await?;
+            Ok((first, second))
+        }).await.unwrap();
+        assert_eq!(decoded, (temperature.clone(), temperature));
+
+        // Closing the connection drops the cached descriptors
+        manager.disconnect_device(ids[0], &storage).await.unwrap();
+        manager.connect_device(ids[0], &storage).await.unwrap();
+        sensor.fail_next(SimulatedOperation::Read, 1);
+        let decoded = manager.decode_value(&device, &MJ_HT_V1_SERVICE.to_string(), &VENDOR_TEMPERATURE.to_string(), &[0x24, 0x09]).await.unwrap();
+        assert_eq!(decoded, None);
+    }
+
+    #[tokio::test]
+    async fn restarted_mibeacon_counters_are_accepted() {
+        // XMWSDJ04MMC frame with packet ID 0xa4, published in the xiaomi-ble test suite
+        let frame = hex::decode("48590312a41b776e7c96add7000000f2bf545b").unwrap();
+        let service_data = HashMap::from([(mibeacon::SERVICE_UUID, frame)]);
+        let manager = BluetoothManager::with_backend(&backend(), AdapterSelection::First).await.unwrap();
+        let sensor = Arc::new(SimulatedPeripheral::new("2C:11:65:25:70:04"));
+        let (mut storage, ids) = inventory(std::slice::from_ref(&sensor));
+        let decode = |storage: &DeviceStorage| {
+            let mut device = BluetoothDevice::new(sensor.id(), "XMWSDJ04MMC".to_string(), -60, "hci0", sensor.clone());
+            manager.decode_service_data(&mut device, &service_data, storage);
+            device
+        };
+        let set_packet_id = |storage: &mut DeviceStorage, packet_id| {
+            let device = storage.get_device_mut(ids[0]).unwrap();
+            device.bind_key = Some("b2cf9a553d53571b5657defd582d676e".to_string());
+            device.mibeacon_packet_id = Some(packet_id);
+        };
+
+        // Slightly older frames are replays, repeated ones aren't counted as such
+        set_packet_id(&mut storage, 0xa5);
+        assert_eq!(decode(&storage).mibeacon_packet_id, None);
+        assert_eq!(manager.replays.lock().unwrap().get(&sensor.id()), Some(&1));
+        set_packet_id(&mut storage, 0xa4);
+        assert_eq!(decode(&storage).mibeacon_packet_id, None);
+        assert_eq!(manager.replays.lock().unwrap().get(&sensor.id()), Some(&1));
+
+        // Far older frames come from a restarted device
+        set_packet_id(&mut storage, 0x12345);
+        let device = decode(&storage);
+        assert_eq!(device.mibeacon_packet_id, Some(0xa4));
+        assert!(device.sensor.is_some());
+        assert!(manager.replays.lock().unwrap().is_empty());
+        storage.add_or_update_device(device);
+        assert_eq!(storage.get_device(ids[0]).unwrap().mibeacon_packet_id, Some(0xa4));
+    }
+
+    /// Two adapters both hearing one thermometer, the second one better.
+    fn two_adapters() -> SimulatedBackend {
+        let thermometer = |rssi| SimulatedPeripheral::new("A4:C1:38:00:00:02").with_name("ATC_000002").with_rssi(rssi);
+        SimulatedBackend::new()
+            .with_adapter(SimulatedAdapter::new("hci0 (built-in)").with_peripheral(thermometer(-70)))
+            .with_adapter(SimulatedAdapter::new("hci1 (USB dongle)").with_peripheral(thermometer(-58)))
+    }
+
+    fn selected(manager: &BluetoothManager) -> Vec<&str> {
+        manager.selected_adapters().map(|entry| entry.id.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn adapters_are_selected_by_index_or_id() {
+        let backend = two_adapters();
+        let manager = BluetoothManager::with_backend(&backend, AdapterSelection::First).await.unwrap();
+        assert_eq!(selected(&manager), ["hci0"]);
+        let manager = BluetoothManager::with_backend(&backend, AdapterSelection::One("1".to_string())).await.unwrap();
+        assert_eq!(selected(&manager), ["hci1"]);
+        let manager = BluetoothManager::with_backend(&backend, AdapterSelection::One("HCI1".to_string())).await.unwrap();
+        assert_eq!(selected(&manager), ["hci1"]);
+        let manager = BluetoothManager::with_backend(&backend, AdapterSelection::All).await.unwrap();
+        assert_eq!(selected(&manager), ["hci0", "hci1"]);
+        assert_eq!(manager.adapters().iter().map(|entry| entry.info.as_str()).collect::<Vec<_>>(), ["hci0 (built-in)", "hci1 (USB dongle)"]);
+
+        match BluetoothManager::with_backend(&backend, AdapterSelection::One("hci2".to_string())).await {
+            Err(BluetoothError::NoAdapter(message)) => assert_eq!(message, "No Bluetooth adapter 'hci2', available: 0 (hci0), 1 (hci1)"),
+            other => panic!("expected NoAdapter, got {:?}", other.map(|_| ())),
+        }
+        assert!(matches!(
+            BluetoothManager::with_backend(&SimulatedBackend::new(), AdapterSelection::All).await,
+            Err(BluetoothError::NoAdapter(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn devices_record_every_adapter_hearing_them() {
+        let manager = BluetoothManager::with_backend(&two_adapters(), AdapterSelection::All).await.unwrap();
+        let mut storage = DeviceStorage::new();
+        manager.scan_until(&mut storage, tokio::time::sleep(Duration::from_millis(100))).await.unwrap();
+
+        let devices = storage.list_devices();
+        assert_eq!(devices.len(), 1);
+        let device = devices[0].1;
+        let sightings: Vec<_> = device.adapters.iter().map(|(adapter, sighting)| (adapter.as_str(), sighting.rssi)).collect();
+        assert_eq!(sightings, [("hci0", -70), ("hci1", -58)]);
+        // Connections go through the adapter hearing it best
+        assert_eq!((device.adapter.as_deref(), device.rssi), (Some("hci1"), -58));
+    }
+
+    #[tokio::test]
+    async fn open_connections_are_subscribed_without_delay() {
+        let backend = backend();
+        let manager = BluetoothManager::with_backend(&backend, AdapterSelection::First).await.unwrap();
+        let sensor = Arc::new(mj_ht_v1("4C:65:A8:D0:00:01", true));
+        let (mut storage, ids) = inventory(&[sensor]);
+        manager.connect_device(ids[0], &storage).await.unwrap();
+
+        let results = manager.poll_mj_ht_v1(&ids, &mut storage, 1, Duration::from_secs(10)).await;
+        assert!(results[0].result.is_ok(), "{:?}", results[0].result);
+        assert!(results[0].elapsed < Duration::from_secs(1), "{:?}", results[0].elapsed);
     }
 }
@@ -0,0 +1,324 @@
+//! BTHome v2 advertisements (service data for UUID 0xFCD2).
+//!
+//! Frame layout: device information byte (bit 0 encrypted, bit 2 trigger based, bits 5-7
+//! version) followed by objects. Every object is `id (u8) | data`, where the data length
+//! is fixed by the object ID, so parsing stops at the first unknown ID, keeping the objects
+//! before it.
+//!
+//! Encrypted frames carry the objects as AES-CCM ciphertext (4 byte MIC) using the
+//! device's 16 byte bind key, followed by a 4 byte counter and the MIC. The nonce is
+//! `MAC | UUID (d2 fc) | device information | counter`.
+
+use crate::sensor::{ButtonEvent, Measurement};
+use aes::Aes128;
+use ccm::aead::generic_array::GenericArray;
+use ccm::aead::{Aead, KeyInit};
+use ccm::consts::{U13, U4};
+use ccm::Ccm;
+use log::debug;
+use std::fmt;
+use uuid::Uuid;
+
+pub const SERVICE_UUID: Uuid = Uuid::from_u128(0x0000fcd2_0000_1000_8000_00805f9b34fb);
+
+const FLAG_ENCRYPTED: u8 = 0x01;
+const FLAG_TRIGGER_BASED: u8 = 0x04;
+const SUPPORTED_VERSION: u8 = 2;
+
+const COUNTER_LENGTH: usize = 4;
+const MIC_LENGTH: usize = 4;
+
+type BthomeCcm = Ccm<Aes128, U4, U13>;
+
+const OBJECT_PACKET_ID: u8 = 0x00;
+const OBJECT_BATTERY: u8 = 0x01;
+const OBJECT_TEMPERATURE: u8 = 0x02;
+const OBJECT_HUMIDITY: u8 = 0x03;
+const OBJECT_PRESSURE: u8 = 0x04;
+const OBJECT_ILLUMINANCE: u8 = 0x05;
+const OBJECT_VOLTAGE: u8 = 0x0c;
+const OBJECT_DOOR: u8 = 0x1a;
+const OBJECT_MOTION: u8 = 0x21;
+const OBJECT_HUMIDITY_COARSE: u8 = 0x2e;
+const OBJECT_BUTTON: u8 = 0x3a;
+const OBJECT_TEMPERATURE_COARSE: u8 = 0x45;
+const OBJECT_VOLTAGE_COARSE: u8 = 0x4a;
+const OBJECT_TEXT: u8 = 0x53;
+const OBJECT_RAW: u8 = 0x54;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BthomeFrame {
+    pub version: u8,
+    /// Whether the objects were encrypted (and authenticated) with the bind key.
+    pub encrypted: bool,
+    /// Sent on state changes (buttons, doors) instead of periodically.
+    pub trigger_based: bool,
+    /// Packet ID object, used by receivers to drop repeated advertisements.
+    pub packet_id: Option<u8>,
+    /// Counter of encrypted frames, used to detect replays.
+    pub counter: Option<u32>,
+    pub measurements: Vec<Measurement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BthomeError {
+    /// The frame ends in the middle of an object or trailer.
+    Truncated,
+    /// The payload is encrypted and no bind key is registered for the device.
+    Encrypted,
+    /// Only BTHome v2 is supported.
+    UnsupportedVersion(u8),
+    /// The MIC does not match: wrong bind key or tampered frame.
+    AuthenticationFailed,
+    /// The MAC address is needed for the nonce but is not known.
+    MissingMacAddress,
+    /// The frame carries plaintext objects although the device has a bind key, as a spoofed
+    /// frame would.
+    NotEncrypted,
+}
+
+impl fmt::Display for BthomeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BthomeError::Truncated => write!(f, "BTHome frame is truncated"),
+            BthomeError::Encrypted => write!(f, "BTHome frame is encrypted and no bind key is registered"),
+            BthomeError::UnsupportedVersion(version) => write!(f, "BTHome v{} frames are not supported", version),
+            BthomeError::AuthenticationFailed => write!(f, "BTHome frame failed authentication (wrong bind key?)"),
+            BthomeError::MissingMacAddress => write!(f, "BTHome frame needs the MAC address to build the nonce"),
+            BthomeError::NotEncrypted => write!(f, "BTHome frame is not encrypted although a bind key is registered"),
+        }
+    }
+}
+
+impl std::error::Error for BthomeError {}
+
+/// Decodes a BTHome v2 frame received from `mac_address`. Encrypted frames need the
+/// device's `bind_key`; once it is given, plaintext frames are rejected.
+pub fn parse(data: &[u8], mac_address: &str, bind_key: Option<&[u8; 16]>) -> Result<BthomeFrame, BthomeError> {
+    let (&device_information, payload) = data.split_first().ok_or(BthomeError::Truncated)?;
+    let version = device_information >> 5;
+    if version != SUPPORTED_VERSION {
+        return Err(BthomeError::UnsupportedVersion(version));
+    }
+    let encrypted = device_information & FLAG_ENCRYPTED != 0;
+
+    let (objects, counter) = if encrypted {
+        let bind_key = bind_key.ok_or(BthomeError::Encrypted)?;
+        let mac = parse_mac(mac_address).ok_or(BthomeError::MissingMacAddress)?;
+        if payload.len() < COUNTER_LENGTH + MIC_LENGTH {
+            return Err(BthomeError::Truncated);
+        }
+        let (ciphertext, trailer) = payload.split_at(payload.len() - COUNTER_LENGTH - MIC_LENGTH);
+        let (counter, mic) = trailer.split_at(COUNTER_LENGTH);
+
+        let mut nonce = Vec::with_capacity(13);
+        nonce.extend_from_slice(&mac);
+        nonce.extend_from_slice(&[0xd2, 0xfc, device_information]);
+        nonce.extend_from_slice(counter);
+
+        let plaintext = decrypt(bind_key, &nonce, ciphertext, mic)?;
+        (plaintext, Some(u32::from_le_bytes([counter[0], counter[1], counter[2], counter[3]])))
+    } else if bind_key.is_some() {
+        return Err(BthomeError::NotEncrypted);
+    } else {
+        (payload.to_vec(), None)
+    };
+
+    let (packet_id, measurements) = parse_objects(&objects)?;
+
+    Ok(BthomeFrame {
+        version,
+        encrypted,
+        trigger_based: device_information & FLAG_TRIGGER_BASED != 0,
+        packet_id,
+        counter,
+        measurements,
+    })
+}
+
+impl BthomeFrame {
+    /// Whether the frame is newer than the last accepted counter of the device. Only
+    /// encrypted frames carry a counter.
+    pub fn is_newer_than(&self, last_counter: Option<u32>) -> bool {
+        match (self.counter, last_counter) {
+            (Some(counter), Some(last)) => counter > last,
+            _ => true,
+        }
+    }
+}
+
+fn decrypt(bind_key: &[u8; 16], nonce: &[u8], ciphertext: &[u8], mic: &[u8]) -> Result<Vec<u8>, BthomeError> {
+    let cipher = BthomeCcm::new(GenericArray::from_slice(bind_key));
+    let mut message = ciphertext.to_vec();
+    message.extend_from_slice(mic);
+    cipher
+        .decrypt(GenericArray::from_slice(nonce), message.as_slice())
+        .map_err(|_| BthomeError::AuthenticationFailed)
+}
+
+fn parse_objects(data: &[u8]) -> Result<(Option<u8>, Vec<Measurement>), BthomeError> {
+    let mut rest = data;
+    let mut packet_id = None;
+    let mut measurements = Vec::new();
+
+    while let Some((&id, tail)) = rest.split_first() {
+        let length = match id {
+            OBJECT_TEXT | OBJECT_RAW => *tail.first().ok_or(BthomeError::Truncated)? as usize + 1,
+            _ => match object_length(id) {
+                Some(length) => length,
+                None => {
+                    debug!("Stopping at unknown BTHome object {:#04x}, {} bytes left", id, tail.len());
+                    break;
+                }
+            },
+        };
+        if tail.len() < length {
+            return Err(BthomeError::Truncated);
+        }
+        let (value, tail) = tail.split_at(length);
+        rest = tail;
+
+        match id {
+            OBJECT_PACKET_ID => packet_id = Some(value[0]),
+            OBJECT_BATTERY => measurements.push(Measurement::Battery(value[0])),
+            OBJECT_TEMPERATURE => measurements.push(Measurement::Temperature(signed(value) as f32 / 100.0)),
+            OBJECT_TEMPERATURE_COARSE => measurements.push(Measurement::Temperature(signed(value) as f32 / 10.0)),
+            OBJECT_HUMIDITY => measurements.push(Measurement::Humidity(unsigned(value) as f32 / 100.0)),
+            OBJECT_HUMIDITY_COARSE => measurements.push(Measurement::Humidity(unsigned(value) as f32)),
+            OBJECT_PRESSURE => measurements.push(Measurement::Pressure(unsigned(value) as f32 / 100.0)),
+            OBJECT_ILLUMINANCE => measurements.push(Measurement::Illuminance(unsigned(value) as f32 / 100.0)),
+            OBJECT_VOLTAGE => measurements.push(Measurement::Voltage(unsigned(value) as f32 / 1000.0)),
+            OBJECT_VOLTAGE_COARSE => measurements.push(Measurement::Voltage(unsigned(value) as f32 / 10.0)),
+            OBJECT_MOTION => measurements.push(Measurement::Motion(value[0] != 0)),
+            OBJECT_DOOR => measurements.push(Measurement::Door(value[0] != 0)),
+            OBJECT_BUTTON => match ButtonEvent::from_bthome(value[0]) {
+                Some(event) => measurements.push(Measurement::Button(event)),
+                None => debug!("Skipping unknown BTHome button event {:#04x}", value[0]),
+            },
+            _ => debug!("Skipping BTHome object {:#04x} ({} bytes)", id, length),
+        }
+    }
+
+    Ok((packet_id, measurements))
+}
+
+/// Data length of the fixed-size BTHome v2 objects.
+fn object_length(id: u8) -> Option<usize> {
+    let length = match id {
+        0x00 | 0x01 | 0x09 | 0x0f..=0x11 | 0x15..=0x2f | 0x3a | 0x46 => 1,
+        0x02 | 0x03 | 0x06..=0x08 | 0x0c..=0x0e | 0x12..=0x14 => 2,
+        0x3c | 0x3d | 0x3f | 0x40 | 0x41 | 0x43 | 0x44 | 0x45 | 0x47..=0x4a | 0x51 | 0x52 | 0xf0 => 2,
+        0x04 | 0x05 | 0x0a | 0x0b | 0x42 | 0x4b | 0xf2 => 3,
+        0x3e | 0x4c..=0x50 | 0x55 | 0xf1 => 4,
+        _ => return None,
+    };
+    Some(length)
+}
+
+fn unsigned(value: &[u8]) -> u32 {
+    value.iter().rev().fold(0, |acc, byte| (acc << 8) | *byte as u32)
+}
+
+fn signed(value: &[u8]) -> i32 {
+    let shift = 32 - 8 * value.len() as u32;
+    ((unsigned(value) << shift) as i32) >> shift
+}
+
+/// Turns `A4:C1:38:12:34:56` into bytes, in the order used by the nonce.
+fn parse_mac(mac_address: &str) -> Option<Vec<u8>> {
+    let bytes = mac_address
+        .split(':')
+        .map(|part| u8::from_str_radix(part, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    (bytes.len() == 6).then_some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Example of the BTHome specification: temperature 25.06 °C and humidity 50.55 %,
+    // counter 0x33221100
+    const BIND_KEY: [u8; 16] = [0x23, 0x1d, 0x39, 0xc1, 0xd7, 0xcc, 0x1a, 0xb1, 0xae, 0xe2, 0x24, 0xcd, 0x09, 0x6d, 0xb9, 0x32];
+    const MAC: &str = "54:48:E6:8F:80:A5";
+    const ENCRYPTED_FRAME: [u8; 15] = [0x41, 0xa4, 0x72, 0x66, 0xc9, 0x5f, 0x73, 0x00, 0x11, 0x22, 0x33, 0x78, 0x23, 0x72, 0x14];
+
+    fn measurements(data: &[u8]) -> Result<Vec<Measurement>, BthomeError> {
+        parse(data, MAC, None).map(|frame| frame.measurements)
+    }
+
+    #[test]
+    fn parses_plain_frame() {
+        // Packet 9, battery 97 %, illuminance 123.45 lx, motion detected
+        let frame = parse(&[0x40, 0x00, 0x09, 0x01, 0x61, 0x05, 0x39, 0x30, 0x00, 0x21, 0x01], MAC, None).unwrap();
+        assert_eq!(frame.version, 2);
+        assert!(!frame.encrypted);
+        assert!(!frame.trigger_based);
+        assert_eq!(frame.packet_id, Some(9));
+        assert_eq!(frame.counter, None);
+        assert_eq!(frame.measurements, vec![Measurement::Battery(97), Measurement::Illuminance(123.45), Measurement::Motion(true)]);
+    }
+
+    #[test]
+    fn decodes_objects() {
+        let cases: Vec<(&str, &[u8], _)> = vec![
+            ("temperature", &[0x40, 0x02, 0xca, 0x09], Ok(vec![Measurement::Temperature(25.06)])),
+            ("negative temperature", &[0x40, 0x02, 0x2e, 0xfb], Ok(vec![Measurement::Temperature(-12.34)])),
+            ("coarse temperature", &[0x40, 0x45, 0x11, 0x01], Ok(vec![Measurement::Temperature(27.3)])),
+            ("humidity", &[0x40, 0x03, 0xbf, 0x13], Ok(vec![Measurement::Humidity(50.55)])),
+            ("coarse humidity", &[0x40, 0x2e, 0x23], Ok(vec![Measurement::Humidity(35.0)])),
+            ("pressure", &[0x40, 0x04, 0x13, 0x8a, 0x01], Ok(vec![Measurement::Pressure(1008.83)])),
+            ("voltage", &[0x40, 0x0c, 0x02, 0x0c], Ok(vec![Measurement::Voltage(3.074)])),
+            ("door", &[0x40, 0x1a, 0x01], Ok(vec![Measurement::Door(true)])),
+            ("button", &[0x44, 0x3a, 0x02], Ok(vec![Measurement::Button(ButtonEvent::DoublePress)])),
+            ("unknown button event", &[0x44, 0x3a, 0x42], Ok(vec![])),
+            ("text skipped", &[0x40, 0x53, 0x02, 0x68, 0x69, 0x01, 0x50], Ok(vec![Measurement::Battery(80)])),
+            ("known object of unread type skipped", &[0x40, 0x06, 0x5e, 0x1f, 0x01, 0x50], Ok(vec![Measurement::Battery(80)])),
+            (
+                "objects before an unknown ID kept",
+                &[0x40, 0x01, 0x50, 0x02, 0xca, 0x09, 0xee, 0x01, 0x02],
+                Ok(vec![Measurement::Battery(80), Measurement::Temperature(25.06)]),
+            ),
+            ("truncated object", &[0x40, 0x01, 0x50, 0x02, 0xca], Err(BthomeError::Truncated)),
+            ("truncated text", &[0x40, 0x53, 0x05, 0x68], Err(BthomeError::Truncated)),
+            ("empty", &[], Err(BthomeError::Truncated)),
+            ("version 1", &[0x20, 0x01, 0x50], Err(BthomeError::UnsupportedVersion(1))),
+        ];
+        for (name, data, expected) in cases {
+            assert_eq!(measurements(data), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn decrypts_specification_example() {
+        let frame = parse(&ENCRYPTED_FRAME, MAC, Some(&BIND_KEY)).unwrap();
+        assert!(frame.encrypted);
+        assert_eq!(frame.counter, Some(0x33221100));
+        assert_eq!(frame.measurements, vec![Measurement::Temperature(25.06), Measurement::Humidity(50.55)]);
+    }
+
+    #[test]
+    fn encrypted_frame_needs_the_right_key_and_mac() {
+        assert_eq!(parse(&ENCRYPTED_FRAME, MAC, None), Err(BthomeError::Encrypted));
+        let mut wrong_key = BIND_KEY;
+        wrong_key[0] ^= 0x01;
+        assert_eq!(parse(&ENCRYPTED_FRAME, MAC, Some(&wrong_key)), Err(BthomeError::AuthenticationFailed));
+        assert_eq!(parse(&ENCRYPTED_FRAME, "54:48:E6:8F:80:A6", Some(&BIND_KEY)), Err(BthomeError::AuthenticationFailed));
+        assert_eq!(parse(&ENCRYPTED_FRAME, "not a MAC", Some(&BIND_KEY)), Err(BthomeError::MissingMacAddress));
+        assert_eq!(parse(&ENCRYPTED_FRAME[..8], MAC, Some(&BIND_KEY)), Err(BthomeError::Truncated));
+    }
+
+    #[test]
+    fn plain_frame_with_key_is_rejected() {
+        assert_eq!(parse(&[0x40, 0x01, 0x50], MAC, Some(&BIND_KEY)), Err(BthomeError::NotEncrypted));
+    }
+
+    #[test]
+    fn replayed_counters_are_detected() {
+        let frame = parse(&ENCRYPTED_FRAME, MAC, Some(&BIND_KEY)).unwrap();
+        assert!(frame.is_newer_than(None));
+        assert!(frame.is_newer_than(Some(0x332210ff)));
+        assert!(!frame.is_newer_than(Some(0x33221100)));
+        assert!(parse(&[0x40, 0x01, 0x50], MAC, None).unwrap().is_newer_than(Some(u32::MAX)));
+    }
+}
@@ -0,0 +1,702 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use crate::api::{self, ApiState};
+use crate::assigned_numbers;
+use crate::bluetooth_manager::{AdapterSelection, BluetoothManager};
+use crate::device_storage::DeviceStorage;
+use crate::error::BluetoothError;
+use crate::gatt_export::{ExportFormat, GattProfile};
+use crate::metrics;
+use crate::mibeacon;
+use crate::mqtt::{MqttConfig, MqttPublisher};
+use crate::presence;
+use crate::rssi;
+use crate::scan_filter::{self, DeviceFilter};
+use crate::ui::UserInterface;
+use crate::value_format::ValueFormat;
+use btleplug::api::WriteType;
+use log::{info, warn};
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+/// Command-line interface. Without a subcommand the interactive menu is started.
+#[derive(Parser, Debug)]
+#[command(name = "bluetooth", version, about = "Bluetooth LE scanner and MJ_HT_V1 sensor reader")]
+pub struct Cli {
+    /// Bluetooth backend to use
+    #[arg(long, value_enum, default_value_t = BackendKind::Platform, env = "BLUETOOTH_BACKEND", global = true)]
+    pub backend: BackendKind,
+
+    /// File the device inventory is kept in between runs
+    #[arg(long, default_value = "devices.json", env = "BLUETOOTH_STORAGE", global = true)]
+    pub storage: PathBuf,
+
+    /// Bluetooth adapter to use, by index or identifier (e.g. hci1); see `adapters`
+    #[arg(long, env = "BLUETOOTH_ADAPTER", global = true, conflicts_with = "all_adapters")]
+    pub adapter: Option<String>,
+
+    /// Scan with every adapter at once
+    #[arg(long, env = "BLUETOOTH_ALL_ADAPTERS", global = true)]
+    pub all_adapters: bool,
+
+    /// Path loss exponent for distance estimates: 2 in free space, up to 4 indoors
+    #[arg(long, default_value_t = rssi::DEFAULT_PATH_LOSS_EXPONENT, env = "BLUETOOTH_PATH_LOSS_EXPONENT", global = true, value_parser = rssi::parse_path_loss_exponent)]
+    pub path_loss_exponent: f64,
+
+    /// Seconds without advertisements after which a device counts as departed
+    #[arg(long, default_value_t = presence::DEFAULT_ABSENCE_TIMEOUT.as_secs(), env = "BLUETOOTH_ABSENCE_TIMEOUT", global = true)]
+    pub absence_timeout: u64,
+
+    /// Seconds a connection stays open after its last use
+    #[arg(long, default_value_t = 30, env = "BLUETOOTH_IDLE_TIMEOUT", global = true)]
+    pub idle_timeout: u64,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Which discovered devices are stored; all given criteria must match.
+#[derive(Args, Debug)]
+pub struct FilterArgs {
+    /// Only devices advertising this service, by UUID, short UUID or name (repeatable, any may match)
+    #[arg(long = "filter-service", global = true, value_parser = assigned_numbers::parse_service_uuid)]
+    pub services: Vec<Uuid>,
+    /// Only devices whose name matches this glob, e.g. 'MJ_HT_*'
+    #[arg(long = "filter-name", global = true, conflicts_with = "name_regex")]
+    pub name: Option<String>,
+    /// Only devices whose name matches this regular expression
+    #[arg(long = "filter-name-regex", global = true)]
+    pub name_regex: Option<String>,
+    /// Only devices received at least this strong, in dBm
+    #[arg(long = "min-rssi", global = true, allow_hyphen_values = true)]
+    pub min_rssi: Option<i16>,
+    /// Only devices advertising manufacturer data of this company, by ID or name, e.g. 0x004c or Apple (repeatable)
+    #[arg(long = "filter-manufacturer", global = true)]
+    pub manufacturers: Vec<String>,
+    /// Only these addresses; globs like 'A4:C1:38:*' allowed (repeatable)
+    #[arg(long = "allow", global = true)]
+    pub allow: Vec<String>,
+    /// Never these addresses; globs allowed (repeatable)
+    #[arg(long = "deny", global = true)]
+    pub deny: Vec<String>,
+}
+
+impl FilterArgs {
+    pub fn build(&self) -> Result<DeviceFilter, String> {
+        let name = match (&self.name, &self.name_regex) {
+            (Some(glob), _) => Some(scan_filter::glob(glob)?),
+            (None, Some(pattern)) => Some(scan_filter::regex(pattern)?),
+            (None, None) => None,
+        };
+        Ok(DeviceFilter {
+            services: self.services.clone(),
+            name,
+            min_rssi: self.min_rssi,
+            manufacturer_ids: self.manufacturers.iter().map(|id| scan_filter::parse_company_id(id)).collect::<Result<_, _>>()?,
+            allow: self.allow.iter().map(|pattern| scan_filter::glob(pattern)).collect::<Result<_, _>>()?,
+            deny: self.deny.iter().map(|pattern| scan_filter::glob(pattern)).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl Cli {
+    /// Takes the command to run, the interactive menu when none was given.
+    pub fn take_command(&mut self) -> Command {
+        self.command.take().unwrap_or(Command::Shell)
+    }
+
+    pub fn adapter_selection(&self) -> AdapterSelection {
+        match (&self.adapter, self.all_adapters) {
+            (_, true) => AdapterSelection::All,
+            (Some(adapter), false) => AdapterSelection::One(adapter.clone()),
+            (None, false) => AdapterSelection::First,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    /// The host Bluetooth stack
+    Platform,
+    /// An in-process demo adapter, no radio required
+    Simulated,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Start the interactive menu
+    Shell,
+    /// List the Bluetooth adapters and which of them are used
+    Adapters,
+    /// Scan for devices and print what was found
+    Scan {
+        #[command(flatten)]
+        scan: ScanArgs,
+    },
+    /// List the devices in the inventory
+    List {
+        /// Only list MJ_HT_V1 sensors
+        #[arg(long, conflicts_with = "thermometers")]
+        mj_ht_v1: bool,
+        /// Only list thermometers: MJ_HT_V1 sensors and ATC / pvvx firmware devices
+        #[arg(long)]
+        thermometers: bool,
+    },
+    /// Set the alias, location and tags of a device in the inventory
+    Label {
+        /// Internal ID, alias or MAC address of the device
+        #[arg(short, long)]
+        device: String,
+        /// Unique name to select the device by; an empty value clears it
+        #[arg(long)]
+        alias: Option<String>,
+        /// Where the device is; an empty value clears it
+        #[arg(long)]
+        location: Option<String>,
+        /// Tag to add, or to remove when prefixed with '-' (repeatable)
+        #[arg(long = "tag", allow_hyphen_values = true)]
+        tags: Vec<String>,
+    },
+    /// Attach a metadata entry to a device in the inventory
+    SetMeta {
+        /// Internal ID, alias or MAC address of the device
+        #[arg(short, long)]
+        device: String,
+        key: String,
+        value: String,
+    },
+    /// Remove a metadata entry from a device in the inventory
+    UnsetMeta {
+        /// Internal ID, alias or MAC address of the device
+        #[arg(short, long)]
+        device: String,
+        key: String,
+    },
+    /// Scan and print devices arriving and departing until Ctrl-C
+    Presence,
+    /// Scan and print the values sensors broadcast in their advertisements, without connecting
+    Sensors {
+        #[command(flatten)]
+        scan: ScanArgs,
+    },
+    /// Register the MiBeacon or BTHome bind key used to decrypt a device's advertisements
+    SetBindKey {
+        /// Internal ID, alias or MAC address of the device
+        #[arg(short, long)]
+        device: String,
+        /// 16 byte key as 32 hex digits
+        key: String,
+    },
+    /// Forget the bind key of a device
+    UnsetBindKey {
+        /// Internal ID, alias or MAC address of the device
+        #[arg(short, long)]
+        device: String,
+    },
+    /// Print the services and characteristics of a device
+    Info {
+        #[command(flatten)]
+        target: DeviceArgs,
+        /// Also read every readable characteristic
+        #[arg(long)]
+        detailed: bool,
+    },
+    /// Discover services and characteristics of a device
+    Discover {
+        #[command(flatten)]
+        target: DeviceArgs,
+    },
+    /// Write the full GATT table of a device to a JSON or YAML file
+    Export {
+        #[command(flatten)]
+        target: DeviceArgs,
+        /// File to write, standard output if not given
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Output format; by default taken from the file extension, JSON otherwise
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+        /// Also read the values of readable characteristics
+        #[arg(long)]
+        read_values: bool,
+    },
+    /// Read a characteristic value
+    Read {
+        #[command(flatten)]
+        target: DeviceArgs,
+        #[command(flatten)]
+        characteristic: CharacteristicArgs,
+    },
+    /// Write a characteristic value
+    Write {
+        #[command(flatten)]
+        target: DeviceArgs,
+        #[command(flatten)]
+        characteristic: CharacteristicArgs,
+        /// How VALUE is encoded
+        #[arg(long, value_enum, default_value_t = ValueFormat::Hex)]
+        format: ValueFormat,
+        /// Write without waiting for a response from the device
+        #[arg(long)]
+        without_response: bool,
+        value: String,
+    },
+    /// Print notifications of a characteristic until Ctrl-C
+    Subscribe {
+        #[command(flatten)]
+        target: DeviceArgs,
+        #[command(flatten)]
+        characteristic: CharacteristicArgs,
+        /// Stop after this many notifications (0 means no limit)
+        #[arg(long, default_value_t = 0)]
+        count: usize,
+    },
+    /// Scan continuously, serve the REST API and Prometheus metrics and optionally publish to MQTT, until Ctrl-C
+    Serve(ServeArgs),
+    /// MJ_HT_V1 temperature and humidity sensor commands
+    #[command(name = "mj-ht-v1", subcommand)]
+    MjHtV1(MjHtV1Command),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MjHtV1Command {
+    /// Scan until the given number of MJ_HT_V1 sensors was found
+    Scan {
+        /// Number of sensors to look for
+        #[arg(long, default_value_t = 1)]
+        max_devices: u8,
+    },
+    /// Read name, firmware, manufacturer and battery level
+    Read {
+        #[command(flatten)]
+        target: DeviceArgs,
+    },
+    /// Print temperature and humidity readings until Ctrl-C
+    Subscribe {
+        #[command(flatten)]
+        target: DeviceArgs,
+    },
+    /// Take one reading from many sensors at once and print a table of the results
+    ReadAll {
+        /// Internal ID, alias or MAC address of a sensor (repeatable); all MJ_HT_V1 sensors by default
+        #[arg(short, long = "device")]
+        devices: Vec<String>,
+        /// Only sensors with this tag
+        #[arg(long)]
+        tag: Option<String>,
+        /// Sensors connected to at once
+        #[arg(long, default_value_t = 4)]
+        max_connections: usize,
+        /// Seconds each sensor gets to connect and deliver a reading
+        #[arg(long, default_value_t = DEFAULT_POLL_TIMEOUT.as_secs())]
+        timeout: u64,
+        #[command(flatten)]
+        scan: ScanArgs,
+    },
+}
+
+/// Time each sensor gets when polling many of them.
+pub const DEFAULT_POLL_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Args, Debug)]
+pub struct ScanArgs {
+    /// Scan duration in seconds
+    #[arg(long, default_value_t = 5)]
+    pub duration: u8,
+    /// Number of scan attempts
+    #[arg(long, default_value_t = 1)]
+    pub attempts: u8,
+}
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// Address the HTTP server listens on
+    #[arg(long, default_value = "127.0.0.1:9898", env = "BLUETOOTH_LISTEN")]
+    pub listen: SocketAddr,
+    /// Seconds between saving the inventory and publishing readings to MQTT
+    #[arg(long, default_value_t = 10)]
+    pub interval: u64,
+    #[command(flatten)]
+    pub mqtt: MqttArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct MqttArgs {
+    /// MQTT broker to publish readings to; nothing is published without it
+    #[arg(long, env = "BLUETOOTH_MQTT_HOST")]
+    pub mqtt_host: Option<String>,
+    #[arg(long, default_value_t = 1883, env = "BLUETOOTH_MQTT_PORT")]
+    pub mqtt_port: u16,
+    #[arg(long, env = "BLUETOOTH_MQTT_USERNAME")]
+    pub mqtt_username: Option<String>,
+    #[arg(long, env = "BLUETOOTH_MQTT_PASSWORD", hide_env_values = true)]
+    pub mqtt_password: Option<String>,
+    /// Prefix of the state and availability topics
+    #[arg(long, default_value = "bluetooth")]
+    pub mqtt_topic_prefix: String,
+    /// Home Assistant discovery prefix
+    #[arg(long, default_value = "homeassistant")]
+    pub discovery_prefix: String,
+    /// Seconds without advertisements after which a device is reported offline
+    #[arg(long, default_value_t = 300)]
+    pub availability_timeout: u64,
+}
+
+impl MqttArgs {
+    fn config(&self) -> Option<MqttConfig> {
+        Some(MqttConfig {
+            host: self.mqtt_host.clone()?,
+            port: self.mqtt_port,
+            username: self.mqtt_username.clone(),
+            password: self.mqtt_password.clone(),
+            topic_prefix: self.mqtt_topic_prefix.clone(),
+            discovery_prefix: self.discovery_prefix.clone(),
+            availability_timeout: Duration::from_secs(self.availability_timeout),
+        })
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct DeviceArgs {
+    /// Internal ID, alias or MAC address of the device
+    #[arg(short, long)]
+    pub device: String,
+    #[command(flatten)]
+    pub scan: ScanArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct CharacteristicArgs {
+    /// Service UUID, 16-bit short UUID like 180f, or name like "Battery Service"
+    #[arg(long, value_parser = assigned_numbers::parse_service_uuid)]
+    pub service: Uuid,
+    /// Characteristic UUID, 16-bit short UUID like 2a19, or name like "Battery Level"
+    #[arg(long, value_parser = assigned_numbers::parse_characteristic_uuid)]
+    pub characteristic: Uuid,
+}
+
+/// Runs a non-interactive command. Errors are returned so the process exits with a failure;
+/// the caller saves the inventory afterwards.
+pub async fn run(command: Command, manager: &BluetoothManager, storage: &mut DeviceStorage) -> Result<(), Box<dyn Error>> {
+    let ui = UserInterface::new();
+
+    match command {
+        Command::Shell => unreachable!("the shell is handled by main"),
+        Command::List { .. } | Command::Label { .. } | Command::SetMeta { .. } | Command::UnsetMeta { .. }
+        | Command::SetBindKey { .. } | Command::UnsetBindKey { .. } => {
+            return run_inventory(command, storage);
+        }
+        Command::Adapters => ui.display_adapters(manager.adapters()),
+        Command::Scan { scan } => {
+            manager.scan(storage, scan.duration, scan.attempts).await?;
+            ui.display_devices(storage);
+        }
+        Command::Presence => {
+            let stop = async { let _ = tokio::signal::ctrl_c().await; };
+            manager.watch_presence(storage, stop, |event| ui.display_presence_event(event)).await?;
+        }
+        Command::Sensors { scan } => {
+            manager.scan(storage, scan.duration, scan.attempts).await?;
+            ui.display_sensor_devices(storage);
+        }
+        Command::Info { target, detailed } => {
+            let device_id = resolve_device(manager, storage, &target).await?;
+            if detailed {
+                manager.retrieve_device_info(device_id, storage).await?;
+            } else {
+                manager.list_available_info(device_id, storage).await?;
+            }
+        }
+        Command::Discover { target } => {
+            let device_id = resolve_device(manager, storage, &target).await?;
+            manager.discover_services(device_id, storage).await?;
+        }
+        Command::Export { target, output, format, read_values } => {
+            let format = format.or_else(|| output.as_deref().and_then(ExportFormat::from_path)).unwrap_or(ExportFormat::Json);
+            let device_id = resolve_device(manager, storage, &target).await?;
+            let profile = manager.export_gatt(device_id, storage, read_values).await?;
+            write_export(&profile, format, output.as_deref())?;
+        }
+        Command::Read { target, characteristic } => {
+            let device_id = resolve_device(manager, storage, &target).await?;
+            let (service_uuid, characteristic_uuid) = characteristic.normalized();
+            manager.read_characteristic(device_id, storage, &service_uuid, &characteristic_uuid).await?;
+        }
+        Command::Write { target, characteristic, format, without_response, value } => {
+            let value = format.encode(&value)?;
+            let write_type = if without_response { WriteType::WithoutResponse } else { WriteType::WithResponse };
+            let device_id = resolve_device(manager, storage, &target).await?;
+            let (service_uuid, characteristic_uuid) = characteristic.normalized();
+            manager.write_characteristic(device_id, storage, &service_uuid, &characteristic_uuid, &value, write_type).await?;
+        }
+        Command::Subscribe { target, characteristic, count } => {
+            let device_id = resolve_device(manager, storage, &target).await?;
+            let (service_uuid, characteristic_uuid) = characteristic.normalized();
+            manager.subscribe_characteristic(device_id, storage, &service_uuid, &characteristic_uuid, count).await?;
+        }
+        Command::Serve(args) => serve(args, manager, storage).await?,
+        Command::MjHtV1(MjHtV1Command::Scan { max_devices }) => {
+            manager.scan_for_mj_ht_v1_devices(storage, max_devices).await?;
+            ui.display_mj_ht_v1_devices(storage);
+        }
+        Command::MjHtV1(MjHtV1Command::Read { target }) => {
+            let device_id = resolve_device(manager, storage, &target).await?;
+            manager.read_mj_ht_v1_information(device_id, storage).await?;
+        }
+        Command::MjHtV1(MjHtV1Command::Subscribe { target }) => {
+            let device_id = resolve_device(manager, storage, &target).await?;
+            let stop = async { let _ = tokio::signal::ctrl_c().await; };
+            manager.retrieve_temperature_and_humidity(device_id, storage, stop, |reading| ui.display_mj_ht_v1_reading(reading)).await?;
+        }
+        Command::MjHtV1(MjHtV1Command::ReadAll { devices, tag, max_connections, timeout, scan }) => {
+            manager.scan(storage, scan.duration, scan.attempts).await?;
+            let device_ids = select_sensors(storage, &devices, tag.as_deref())?;
+            let results = manager.poll_mj_ht_v1(&device_ids, storage, max_connections, Duration::from_secs(timeout)).await;
+            ui.display_poll_results(&results);
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a GATT dump to `output`, or to standard output.
+pub fn write_export(profile: &GattProfile, format: ExportFormat, output: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let contents = profile.render(format)?;
+    match output {
+        Some(path) => {
+            std::fs::write(path, contents)?;
+            info!("Wrote the GATT table of {} to {}", profile.mac_address, path.display());
+        }
+        None => print!("{}", contents),
+    }
+    Ok(())
+}
+
+/// Keeps a scan running and serves the API and metrics from the inventory, which is shared
+/// with the HTTP handlers while the server runs. Devices are stored as they advertise; every
+/// interval the inventory is saved and the readings are published to MQTT, if configured.
+async fn serve(args: ServeArgs, manager: &BluetoothManager, storage: &mut DeviceStorage) -> Result<(), Box<dyn Error>> {
+    let shared = Arc::new(Mutex::new(std::mem::replace(storage, DeviceStorage::new())));
+    let listener = tokio::net::TcpListener::bind(args.listen).await?;
+    info!("Serving the API and metrics on http://{}", args.listen);
+
+    let app = api::router(ApiState::new(manager.clone(), shared.clone())).merge(metrics::router(shared.clone()));
+    let server = axum::serve(listener, app);
+    let mut publisher = args.mqtt.config().map(MqttPublisher::connect);
+    let stop = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    // Ends the scan itself on Ctrl-C, so it is stopped before returning
+    let mut inventory = shared.clone();
+    let scan = manager.scan_until(&mut inventory, stop);
+    let publish = async {
+        let mut interval = tokio::time::interval(Duration::from_secs(args.interval));
+        loop {
+            interval.tick().await;
+            let messages = {
+                let storage = shared.lock().await;
+                if let Err(e) = storage.save() {
+                    warn!("Failed to save the inventory: {}", e);
+                }
+                publisher.as_mut().map(|publisher| publisher.messages(&storage))
+            };
+            if let (Some(publisher), Some(messages)) = (&publisher, messages) {
+                if let Err(e) = publisher.send(messages).await {
+                    warn!("Failed to publish to MQTT: {}", e);
+                }
+            }
+        }
+    };
+
+    let result: Result<(), Box<dyn Error>> = tokio::select! {
+        result = server => result.map_err(Into::into),
+        result = scan => result.map_err(Into::into),
+        _ = publish => Ok(()),
+    };
+
+    *storage = std::mem::replace(&mut *shared.lock().await, DeviceStorage::new());
+    result
+}
+
+/// Runs a command that only works on the inventory and needs no Bluetooth adapter.
+pub fn run_inventory(command: Command, storage: &mut DeviceStorage) -> Result<(), Box<dyn Error>> {
+    let ui = UserInterface::new();
+
+    match command {
+        Command::List { mj_ht_v1, thermometers } => {
+            if mj_ht_v1 {
+                ui.display_mj_ht_v1_devices(storage);
+            } else if thermometers {
+                ui.display_thermometer_devices(storage);
+            } else {
+                ui.display_devices(storage);
+            }
+        }
+        Command::Label { device, alias, location, tags } => {
+            let device_id = storage.find_device(&device).ok_or_else(|| BluetoothError::DeviceNotFound(device.clone()))?;
+            storage.label_device(device_id, alias, location, tags)?;
+        }
+        Command::SetMeta { device, key, value } => {
+            check_metadata_key(&key)?;
+            let device_id = storage.find_device(&device).ok_or_else(|| BluetoothError::DeviceNotFound(device.clone()))?;
+            if let Some(device) = storage.get_device_mut(device_id) {
+                device.metadata.insert(key, value);
+            }
+        }
+        Command::UnsetMeta { device, key } => {
+            check_metadata_key(&key)?;
+            let device_id = storage.find_device(&device).ok_or_else(|| BluetoothError::DeviceNotFound(device.clone()))?;
+            if let Some(device) = storage.get_device_mut(device_id) {
+                device.metadata.remove(&key);
+            }
+        }
+        Command::SetBindKey { device, key } => {
+            mibeacon::parse_bind_key(&key)?;
+            let device_id = storage.find_device(&device).ok_or_else(|| BluetoothError::DeviceNotFound(device.clone()))?;
+            if let Some(device) = storage.get_device_mut(device_id) {
+                device.bind_key = Some(key.trim().to_lowercase());
+                // A new key usually means the device was paired again and restarted its counter
+                device.mibeacon_packet_id = None;
+                device.bthome_counter = None;
+            }
+        }
+        Command::UnsetBindKey { device } => {
+            let device_id = storage.find_device(&device).ok_or_else(|| BluetoothError::DeviceNotFound(device.clone()))?;
+            if let Some(device) = storage.get_device_mut(device_id) {
+                device.bind_key = None;
+            }
+        }
+        _ => unreachable!("{:?} needs a Bluetooth adapter", command),
+    }
+
+    Ok(())
+}
+
+impl Command {
+    /// Whether the command talks to a Bluetooth adapter.
+    pub fn uses_bluetooth(&self) -> bool {
+        !matches!(
+            self,
+            Command::List { .. } | Command::Label { .. } | Command::SetMeta { .. } | Command::UnsetMeta { .. }
+                | Command::SetBindKey { .. } | Command::UnsetBindKey { .. }
+        )
+    }
+}
+
+impl CharacteristicArgs {
+    /// UUIDs are compared in their lowercase hyphenated form.
+    fn normalized(&self) -> (String, String) {
+        (self.service.to_string(), self.characteristic.to_string())
+    }
+}
+
+/// The sensors given by ID, alias or MAC address, or all MJ_HT_V1 sensors, narrowed to those
+/// with `tag`.
+fn select_sensors(storage: &DeviceStorage, devices: &[String], tag: Option<&str>) -> Result<Vec<u32>, BluetoothError> {
+    let candidates: Vec<u32> = if devices.is_empty() {
+        storage.list_mj_ht_v1_devices().into_iter().map(|(id, _)| id).collect()
+    } else {
+        devices
+            .iter()
+            .map(|selector| storage.find_device(selector).ok_or_else(|| BluetoothError::DeviceNotFound(selector.clone())))
+            .collect::<Result<_, _>>()?
+    };
+    Ok(candidates
+        .into_iter()
+        .filter(|&id| match tag {
+            Some(tag) => storage.get_device(id).is_some_and(|device| device.tags.contains(tag)),
+            None => true,
+        })
+        .collect())
+}
+
+/// Refuses metadata keys that are labels of their own, set with `label`.
+fn check_metadata_key(key: &str) -> Result<(), String> {
+    match key {
+        "alias" | "location" => Err(format!("'{}' is a label, set or clear it with 'label --{}'", key, key)),
+        _ => Ok(()),
+    }
+}
+
+/// Scans and then looks up the device selected on the command line.
+async fn resolve_device(manager: &BluetoothManager, storage: &mut DeviceStorage, target: &DeviceArgs) -> Result<u32, Box<dyn Error>> {
+    manager.scan(storage, target.scan.duration, target.scan.attempts).await?;
+    let device_id = storage
+        .find_device(&target.device)
+        .ok_or_else(|| BluetoothError::DeviceNotFound(target.device.clone()))?;
+    info!("Device '{}' resolved to ID {}", target.device, device_id);
+    Ok(device_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from([&["bluetooth"], args].concat())
+    }
+
+    fn command(args: &[&str]) -> Command {
+        parse(args).unwrap().take_command()
+    }
+
+    #[test]
+    fn parses_commands() {
+        match command(&["mj-ht-v1", "read", "-d", "kitchen"]) {
+            Command::MjHtV1(MjHtV1Command::Read { target }) => {
+                assert_eq!(target.device, "kitchen");
+                assert_eq!((target.scan.duration, target.scan.attempts), (5, 1));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match command(&["read", "--device", "1", "--service", "180f", "--characteristic", "2a19"]) {
+            Command::Read { target, characteristic } => {
+                assert_eq!(target.device, "1");
+                assert_eq!(characteristic.service, assigned_numbers::uuid_from_short(0x180f));
+                assert_eq!(characteristic.characteristic, assigned_numbers::uuid_from_short(0x2a19));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match command(&["scan", "--duration", "10", "--attempts", "3"]) {
+            Command::Scan { scan } => assert_eq!((scan.duration, scan.attempts), (10, 3)),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(command(&[]), Command::Shell));
+        assert!(matches!(command(&["--storage", "other.json"]), Command::Shell));
+        // Global options may follow the subcommand
+        let cli = parse(&["list", "--adapter", "hci1"]).unwrap();
+        assert!(matches!(cli.adapter_selection(), AdapterSelection::One(adapter) if adapter == "hci1"));
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        for args in [
+            &["list", "--mj-ht-v1", "--thermometers"][..],
+            &["--adapter", "hci1", "--all-adapters", "adapters"],
+            &["--filter-name", "MJ_*", "--filter-name-regex", "^MJ", "scan"],
+            &["read", "--device", "1", "--service", "not a service", "--characteristic", "2a19"],
+            &["read", "--service", "180f", "--characteristic", "2a19"],
+            &["scan", "--duration", "300"],
+            &["mj-ht-v1"],
+        ] {
+            assert!(parse(args).is_err(), "{:?} was accepted", args);
+        }
+    }
+
+    #[test]
+    fn labels_are_not_metadata() {
+        assert!(check_metadata_key("owner").is_ok());
+        assert!(check_metadata_key("alias").unwrap_err().contains("label --alias"));
+        assert!(check_metadata_key("location").unwrap_err().contains("label --location"));
+    }
+}
@@ -0,0 +1,463 @@
+//! Connections kept open between operations.
+//!
+//! `BluetoothManager` opens a device's connection here before an operation and releases it
+//! afterwards instead of disconnecting, so repeated operations skip connecting and service
+//! discovery. Connections unused for the idle timeout are closed in the background, except
+//! the ones opened explicitly with `Connect`, which stay open until `Disconnect`. Connection
+//! changes the adapters report while scanning are reflected, so a dropped connection is
+//! listed as such and reconnected on its next use. Descriptors read to decode values are
+//! kept with the connection until services are discovered again.
+
+use crate::descriptors::CharacteristicDescriptors;
+use crate::device_info::BluetoothDevice;
+use crate::error::BluetoothError;
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+/// How often idle connections are looked for.
+const REAP_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConnectionState::Connecting => write!(f, "connecting"),
+            ConnectionState::Connected => write!(f, "connected"),
+            ConnectionState::Disconnected => write!(f, "disconnected"),
+        }
+    }
+}
+
+/// Snapshot of a tracked connection.
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub mac_address: String,
+    pub state: ConnectionState,
+    /// Opened explicitly, exempt from the idle timeout.
+    pub pinned: bool,
+    /// Operations currently using the connection.
+    pub users: usize,
+    pub idle: Duration,
+}
+
+/// One use of an open connection, ended when dropped; the connection then stays open until
+/// idle for the timeout.
+pub struct ConnectionLease {
+    entry: Arc<Entry>,
+}
+
+impl Drop for ConnectionLease {
+    fn drop(&mut self) {
+        let mut connection = self.entry.connection.lock().unwrap();
+        connection.users = connection.users.saturating_sub(1);
+        connection.last_used = Instant::now();
+        // Whoever connects holds a lease, so with none left the connecting was given up
+        if connection.users == 0 && connection.state == ConnectionState::Connecting {
+            connection.state = ConnectionState::Disconnected;
+        }
+    }
+}
+
+struct Connection {
+    /// Copy of the device holding the peripheral handle, to disconnect it when idle.
+    device: BluetoothDevice,
+    state: ConnectionState,
+    pinned: bool,
+    services_loaded: bool,
+    /// Descriptors read over the connection, by service and characteristic UUID.
+    descriptors: HashMap<(String, String), CharacteristicDescriptors>,
+    users: usize,
+    last_used: Instant,
+}
+
+struct Entry {
+    /// Held while connecting or disconnecting, so concurrent operations connect only once.
+    gate: tokio::sync::Mutex<()>,
+    connection: Mutex<Connection>,
+}
+
+pub struct ConnectionManager {
+    entries: Mutex<BTreeMap<String, Arc<Entry>>>,
+    idle_timeout: Duration,
+    reaper_started: AtomicBool,
+}
+
+impl ConnectionManager {
+    pub fn new(idle_timeout: Duration) -> Self {
+        ConnectionManager { entries: Mutex::new(BTreeMap::new()), idle_timeout, reaper_started: AtomicBool::new(false) }
+    }
+
+    /// Makes sure the device is connected with its services discovered, and marks the
+    /// connection in use until the returned lease is dropped. `pin` keeps the connection open
+    /// until `close`.
+    pub async fn open(self: &Arc<Self>, device: &BluetoothDevice, pin: bool) -> Result<ConnectionLease, BluetoothError> {
+        if !self.reaper_started.swap(true, Ordering::SeqCst) {
+            self.spawn_reaper();
+        }
+
+        let entry = self.entry(device);
+        let _gate = entry.gate.lock().await;
+        let (state, services_loaded) = {
+            let mut connection = entry.connection.lock().unwrap();
+            connection.users += 1;
+            connection.pinned |= pin;
+            connection.device = device.clone();
+            (connection.state, connection.services_loaded)
+        };
+        // Also ends the use when the caller gives up on the operation, e.g. on a timeout
+        let lease = ConnectionLease { entry: entry.clone() };
+
+        async {
+            // The device may have dropped the connection since it was last used
+            let connected = state == ConnectionState::Connected && device.is_connected().await.unwrap_or(false);
+            if !connected {
+                if state == ConnectionState::Connected {
+                    info!("Device {} dropped the connection, reconnecting", device.mac_address);
+                }
+                self.set_state(&entry, ConnectionState::Connecting);
+                if let Err(e) = device.connect().await {
+                    self.set_state(&entry, ConnectionState::Disconnected);
+                    return Err(e);
+                }
+                self.set_state(&entry, ConnectionState::Connected);
+            }
+            if !connected || !services_loaded {
+                device.load_services().await?;
+                let mut connection = entry.connection.lock().unwrap();
+                connection.services_loaded = true;
+                connection.descriptors.clear();
+            } else {
+                debug!("Reusing connection to {}", device.mac_address);
+            }
+            Ok(lease)
+        }
+        .await
+    }
+
+    /// Disconnects the device, also when it was opened with `pin`.
+    pub async fn close(&self, device: &BluetoothDevice) -> Result<(), BluetoothError> {
+        let entry = self.entry(device);
+        let _gate = entry.gate.lock().await;
+        let result = device.disconnect().await;
+        Self::mark_closed(&entry);
+        result
+    }
+
+    /// Disconnects the device unless it was opened with `pin` or is in use.
+    pub async fn close_unpinned(&self, device: &BluetoothDevice) -> Result<(), BluetoothError> {
+        let entry = self.entry(device);
+        let _gate = entry.gate.lock().await;
+        {
+            let connection = entry.connection.lock().unwrap();
+            if connection.state == ConnectionState::Disconnected || connection.pinned || connection.users > 0 {
+                return Ok(());
+            }
+        }
+        let result = device.disconnect().await;
+        Self::mark_closed(&entry);
+        result
+    }
+
+    /// Disconnects every connected device.
+    pub async fn close_all(&self) {
+        let devices: Vec<BluetoothDevice> = self
+            .entries
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| entry.connection.lock().unwrap())
+            .filter(|connection| connection.state != ConnectionState::Disconnected)
+            .map(|connection| connection.device.clone())
+            .collect();
+        for device in devices {
+            if let Err(e) = self.close(&device).await {
+                warn!("Failed to close the connection to {}: {}", device.mac_address, e);
+            }
+        }
+    }
+
+    /// Descriptors of a characteristic cached with `cache_descriptors`. The Client
+    /// Configuration in them is as it was when they were read.
+    pub fn cached_descriptors(&self, mac_address: &str, service_uuid: &str, characteristic_uuid: &str) -> Option<CharacteristicDescriptors> {
+        let entry = self.entries.lock().unwrap().get(mac_address).cloned()?;
+        let connection = entry.connection.lock().unwrap();
+        connection.descriptors.get(&(service_uuid.to_string(), characteristic_uuid.to_string())).cloned()
+    }
+
+    /// Keeps descriptors read over the device's open connection until it closes.
+    pub fn cache_descriptors(&self, mac_address: &str, service_uuid: &str, characteristic_uuid: &str, descriptors: CharacteristicDescriptors) {
+        let Some(entry) = self.entries.lock().unwrap().get(mac_address).cloned() else { return };
+        let mut connection = entry.connection.lock().unwrap();
+        if connection.state == ConnectionState::Connected {
+            connection.descriptors.insert((service_uuid.to_string(), characteristic_uuid.to_string()), descriptors);
+        }
+    }
+
+    /// Records a connection change an adapter reported for a tracked device. Changes of
+    /// connections being opened or closed are left to `open` and `close`.
+    pub fn connection_changed(&self, mac_address: &str, connected: bool) {
+        let Some(entry) = self.entries.lock().unwrap().get(mac_address).cloned() else { return };
+        let Ok(_gate) = entry.gate.try_lock() else { return };
+        let mut connection = entry.connection.lock().unwrap();
+        match (connection.state, connected) {
+            (ConnectionState::Connected, false) => {
+                info!("Device {} dropped the connection", mac_address);
+                connection.state = ConnectionState::Disconnected;
+                connection.services_loaded = false;
+                connection.descriptors.clear();
+            }
+            (ConnectionState::Disconnected, true) => {
+                debug!("Device {} connected outside of an operation", mac_address);
+                connection.state = ConnectionState::Connected;
+                connection.last_used = Instant::now();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(mac_address, entry)| {
+                let connection = entry.connection.lock().unwrap();
+                ConnectionInfo {
+                    mac_address: mac_address.clone(),
+                    state: connection.state,
+                    pinned: connection.pinned,
+                    users: connection.users,
+                    idle: connection.last_used.elapsed(),
+                }
+            })
+            .collect()
+    }
+
+    fn entry(&self, device: &BluetoothDevice) -> Arc<Entry> {
+        self.entries
+            .lock()
+            .unwrap()
+            .entry(device.mac_address.clone())
+            .or_insert_with(|| {
+                Arc::new(Entry {
+                    gate: tokio::sync::Mutex::new(()),
+                    connection: Mutex::new(Connection {
+                        device: device.clone(),
+                        state: ConnectionState::Disconnected,
+                        pinned: false,
+                        services_loaded: false,
+                        descriptors: HashMap::new(),
+                        users: 0,
+                        last_used: Instant::now(),
+                    }),
+                })
+            })
+            .clone()
+    }
+
+    fn set_state(&self, entry: &Entry, state: ConnectionState) {
+        entry.connection.lock().unwrap().state = state;
+    }
+
+    fn mark_closed(entry: &Entry) {
+        let mut connection = entry.connection.lock().unwrap();
+        connection.state = ConnectionState::Disconnected;
+        connection.pinned = false;
+        connection.services_loaded = false;
+        connection.descriptors.clear();
+    }
+
+    /// Closes idle connections until the manager is dropped.
+    fn spawn_reaper(self: &Arc<Self>) {
+        let manager: Weak<Self> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(REAP_INTERVAL).await;
+                let Some(manager) = manager.upgrade() else { break };
+                manager.close_idle().await;
+            }
+        });
+    }
+
+    async fn close_idle(&self) {
+        let idle: Vec<(Arc<Entry>, BluetoothDevice)> = self
+            .entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|entry| {
+                let connection = entry.connection.lock().unwrap();
+                let expired = connection.state == ConnectionState::Connected
+                    && !connection.pinned
+                    && connection.users == 0
+                    && connection.last_used.elapsed() >= self.idle_timeout;
+                expired.then(|| (entry.clone(), connection.device.clone()))
+            })
+            .collect();
+
+        for (entry, device) in idle {
+            // Skip connections an operation is opening right now
+            let Ok(_gate) = entry.gate.try_lock() else { continue };
+            if entry.connection.lock().unwrap().users > 0 {
+                continue;
+            }
+            info!("Closing idle connection to {}", device.mac_address);
+            if let Err(e) = device.disconnect().await {
+                warn!("Failed to close the idle connection to {}: {}", device.mac_address, e);
+            }
+            Self::mark_closed(&entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::simulated::{SimulatedOperation, SimulatedPeripheral};
+    use crate::backend::BlePeripheral;
+    use btleplug::api::CharPropFlags;
+    use uuid::Uuid;
+
+    const ADDRESS: &str = "AA:BB:CC:DD:EE:01";
+
+    fn device() -> (BluetoothDevice, Arc<SimulatedPeripheral>) {
+        let peripheral = Arc::new(SimulatedPeripheral::new(ADDRESS).with_name("Sensor").with_characteristic(
+            Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb),
+            Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb),
+            CharPropFlags::READ,
+            &[87],
+        ));
+        let device = BluetoothDevice::new(ADDRESS.to_string(), "Sensor".to_string(), -60, "hci0", peripheral.clone());
+        (device, peripheral)
+    }
+
+    fn info(manager: &ConnectionManager) -> ConnectionInfo {
+        manager.list().into_iter().find(|info| info.mac_address == ADDRESS).unwrap()
+    }
+
+    #[tokio::test]
+    async fn connections_are_reused() {
+        let manager = Arc::new(ConnectionManager::new(Duration::from_secs(60)));
+        let (device, peripheral) = device();
+        let first = manager.open(&device, false).await.unwrap();
+        // Connecting again would fail, so the second use has to reuse the connection
+        peripheral.fail_next(SimulatedOperation::Connect, 1);
+        let second = manager.open(&device, false).await.unwrap();
+        assert_eq!(info(&manager).users, 2);
+        drop(first);
+        drop(second);
+        let info = info(&manager);
+        assert_eq!((info.state, info.users, info.pinned), (ConnectionState::Connected, 0, false));
+    }
+
+    #[tokio::test]
+    async fn cancelled_operations_end_their_use() {
+        let manager = Arc::new(ConnectionManager::new(Duration::from_secs(60)));
+        let (device, _peripheral) = device();
+        let operation = async {
+            let _lease = manager.open(&device, false).await.unwrap();
+            std::future::pending::<()>().await;
+        };
+        assert!(tokio::time::timeout(Duration::from_millis(50), operation).await.is_err());
+        let info = info(&manager);
+        assert_eq!((info.state, info.users), (ConnectionState::Connected, 0));
+    }
+
+    #[tokio::test]
+    async fn cancelled_connects_end_their_use() {
+        let manager = Arc::new(ConnectionManager::new(Duration::from_secs(60)));
+        let (device, peripheral) = device();
+        // The first attempt fails, so the open is cancelled while waiting to retry
+        peripheral.fail_next(SimulatedOperation::Connect, 1);
+        assert!(tokio::time::timeout(Duration::from_millis(50), manager.open(&device, false)).await.is_err());
+        let connection = info(&manager);
+        assert_eq!((connection.state, connection.users), (ConnectionState::Disconnected, 0));
+
+        drop(manager.open(&device, false).await.unwrap());
+        assert_eq!(info(&manager).state, ConnectionState::Connected);
+    }
+
+    #[tokio::test]
+    async fn failed_service_discovery_ends_the_use() {
+        let manager = Arc::new(ConnectionManager::new(Duration::from_secs(60)));
+        let (device, peripheral) = device();
+        peripheral.fail_next(SimulatedOperation::DiscoverServices, 1);
+        assert!(manager.open(&device, false).await.is_err());
+        assert_eq!(info(&manager).users, 0);
+        // Services are discovered again on the next use
+        drop(manager.open(&device, false).await.unwrap());
+        assert!(!peripheral.services().is_empty());
+    }
+
+    #[tokio::test]
+    async fn idle_connections_are_closed_unless_pinned_or_in_use() {
+        let manager = Arc::new(ConnectionManager::new(Duration::ZERO));
+        let (idle, idle_peripheral) = device();
+        let pinned_peripheral = Arc::new(SimulatedPeripheral::new("AA:BB:CC:DD:EE:02"));
+        let pinned = BluetoothDevice::new("AA:BB:CC:DD:EE:02".to_string(), "Pinned".to_string(), -60, "hci0", pinned_peripheral.clone());
+        let busy_peripheral = Arc::new(SimulatedPeripheral::new("AA:BB:CC:DD:EE:03"));
+        let busy = BluetoothDevice::new("AA:BB:CC:DD:EE:03".to_string(), "Busy".to_string(), -60, "hci0", busy_peripheral.clone());
+
+        drop(manager.open(&idle, false).await.unwrap());
+        drop(manager.open(&pinned, true).await.unwrap());
+        let _lease = manager.open(&busy, false).await.unwrap();
+        manager.close_idle().await;
+
+        assert!(!idle_peripheral.is_connected().await.unwrap());
+        assert!(pinned_peripheral.is_connected().await.unwrap());
+        assert!(busy_peripheral.is_connected().await.unwrap());
+        assert_eq!(info(&manager).state, ConnectionState::Disconnected);
+
+        // Pinned connections are only closed explicitly
+        manager.close_unpinned(&pinned).await.unwrap();
+        assert!(pinned_peripheral.is_connected().await.unwrap());
+        manager.close(&pinned).await.unwrap();
+        assert!(!pinned_peripheral.is_connected().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn dropped_connections_are_reopened() {
+        let manager = Arc::new(ConnectionManager::new(Duration::from_secs(60)));
+        let (device, peripheral) = device();
+        drop(manager.open(&device, false).await.unwrap());
+        assert_eq!(info(&manager).state, ConnectionState::Connected);
+
+        peripheral.disconnect().await.unwrap();
+        manager.connection_changed(ADDRESS, false);
+        assert_eq!(info(&manager).state, ConnectionState::Disconnected);
+
+        drop(manager.open(&device, false).await.unwrap());
+        assert_eq!(info(&manager).state, ConnectionState::Connected);
+        assert!(peripheral.is_connected().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn connections_made_elsewhere_are_tracked() {
+        let manager = Arc::new(ConnectionManager::new(Duration::from_secs(60)));
+        let (device, peripheral) = device();
+        // Untracked devices are ignored
+        manager.connection_changed(ADDRESS, true);
+        assert!(manager.list().is_empty());
+
+        drop(manager.open(&device, false).await.unwrap());
+        manager.close(&device).await.unwrap();
+        peripheral.connect().await.unwrap();
+        manager.connection_changed(ADDRESS, true);
+        assert_eq!(info(&manager).state, ConnectionState::Connected);
+
+        let lease = manager.open(&device, false).await.unwrap();
+        assert_eq!(info(&manager).users, 1);
+        drop(lease);
+        assert_eq!(info(&manager).users, 0);
+    }
+}
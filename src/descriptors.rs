@@ -0,0 +1,341 @@
+//! GATT descriptors that say what a characteristic is: its User Description (a label), its
+//! Client Characteristic Configuration (whether notifications are on) and its Presentation
+//! Format, which gives type, exponent and unit to decode values of characteristics that have
+//! no standard decoder.
+
+use crate::assigned_numbers;
+use crate::gatt_decode::DecodedValue;
+use serde::Serialize;
+use std::fmt;
+use uuid::Uuid;
+
+const USER_DESCRIPTION: u32 = 0x2901;
+const CLIENT_CONFIGURATION: u32 = 0x2902;
+const PRESENTATION_FORMAT: u32 = 0x2904;
+
+/// Whether the descriptor is one `CharacteristicDescriptors` records, i.e. worth reading.
+pub fn is_recorded(descriptor: &Uuid) -> bool {
+    matches!(assigned_numbers::short_uuid(descriptor), Some(USER_DESCRIPTION | CLIENT_CONFIGURATION | PRESENTATION_FORMAT))
+}
+
+/// What the descriptors of one characteristic say about it. Descriptors the characteristic
+/// doesn't have, or that could not be read, are `None`.
+#[derive(Debug, Clone, Default)]
+pub struct CharacteristicDescriptors {
+    pub user_description: Option<String>,
+    pub client_configuration: Option<ClientConfiguration>,
+    pub presentation_format: Option<PresentationFormat>,
+}
+
+impl CharacteristicDescriptors {
+    /// Records the value of a descriptor read from the device, ignoring descriptors not listed above.
+    pub fn add(&mut self, descriptor: &Uuid, value: &[u8]) {
+        match assigned_numbers::short_uuid(descriptor) {
+            Some(USER_DESCRIPTION) => self.user_description = Some(String::from_utf8_lossy(value).trim_end_matches('\0').to_string()),
+            Some(CLIENT_CONFIGURATION) => self.client_configuration = ClientConfiguration::parse(value),
+            Some(PRESENTATION_FORMAT) => self.presentation_format = PresentationFormat::parse(value),
+            _ => {}
+        }
+    }
+}
+
+/// Whether the device sends the characteristic's updates to this client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ClientConfiguration {
+    pub notifications: bool,
+    pub indications: bool,
+}
+
+impl ClientConfiguration {
+    pub fn parse(value: &[u8]) -> Option<Self> {
+        let bits = *value.first()?;
+        Some(ClientConfiguration { notifications: bits & 0x01 != 0, indications: bits & 0x02 != 0 })
+    }
+}
+
+impl fmt::Display for ClientConfiguration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.notifications, self.indications) {
+            (false, false) => write!(f, "off"),
+            (true, false) => write!(f, "notifying"),
+            (false, true) => write!(f, "indicating"),
+            (true, true) => write!(f, "notifying and indicating"),
+        }
+    }
+}
+
+/// Characteristic Presentation Format: how to read the characteristic's value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PresentationFormat {
+    /// Format code, e.g. 0x0e for a signed 16-bit integer
+    pub format: u8,
+    /// Integer values are multiplied by 10 to this power
+    pub exponent: i8,
+    /// Unit UUID, e.g. 0x272f for degrees Celsius
+    pub unit: u16,
+    pub namespace: u8,
+    pub description: u16,
+}
+
+impl PresentationFormat {
+    pub fn parse(value: &[u8]) -> Option<Self> {
+        let [format, exponent, unit_low, unit_high, namespace, description_low, description_high] = value.get(..7)?.try_into().ok()?;
+        Some(PresentationFormat {
+            format,
+            exponent: exponent as i8,
+            unit: u16::from_le_bytes([unit_low, unit_high]),
+            namespace,
+            description: u16::from_le_bytes([description_low, description_high]),
+        })
+    }
+
+    /// Decodes a value of this format; `None` for structs, 2, 4, 12 and 128-bit integers and
+    /// values too short or marked as not available.
+    pub fn decode(&self, value: &[u8]) -> Option<DecodedValue> {
+        let (number, decimals) = match self.format {
+            0x01 => return Some(DecodedValue::Boolean { value: *value.first()? & 0x01 != 0 }),
+            0x04..=0x0a => {
+                let integer = unsigned(value, integer_width(self.format)?)?;
+                return Some(self.scaled(integer as f64));
+            }
+            0x0c..=0x12 => {
+                let width = integer_width(self.format)?;
+                let integer = unsigned(value, width)?;
+                // Sign-extend from the integer's width
+                let shift = 64 - width * 8;
+                return Some(self.scaled(((integer << shift) as i64 >> shift) as f64));
+            }
+            0x14 => (f32::from_le_bytes(value.get(..4)?.try_into().ok()?) as f64, None),
+            0x15 => (f64::from_le_bytes(value.get(..8)?.try_into().ok()?), None),
+            0x16 => ieee11073_sfloat(u16::from_le_bytes(value.get(..2)?.try_into().ok()?))?,
+            0x17 => ieee11073_float(u32::from_le_bytes(value.get(..4)?.try_into().ok()?))?,
+            0x19 => return Some(DecodedValue::Text { text: String::from_utf8_lossy(value).trim_end_matches('\0').to_string() }),
+            0x1a => {
+                let units: Vec<u16> = value.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+                return Some(DecodedValue::Text { text: String::from_utf16_lossy(&units).trim_end_matches('\0').to_string() });
+            }
+            _ => return None,
+        };
+        Some(DecodedValue::Quantity { value: number, decimals, unit: assigned_numbers::unit_symbol(self.unit) })
+    }
+
+    fn scaled(&self, integer: f64) -> DecodedValue {
+        DecodedValue::Quantity {
+            value: scale(integer, self.exponent as i32),
+            decimals: Some(self.exponent.min(0).unsigned_abs() as usize),
+            unit: assigned_numbers::unit_symbol(self.unit),
+        }
+    }
+}
+
+impl fmt::Display for PresentationFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "format {:#04x}, exponent {}", self.format, self.exponent)?;
+        match assigned_numbers::unit_symbol(self.unit) {
+            Some(symbol) => write!(f, ", unit {}", symbol),
+            None => write!(f, ", unit {:#06x}", self.unit),
+        }
+    }
+}
+
+/// Width in bytes of the supported integer formats.
+fn integer_width(format: u8) -> Option<usize> {
+    match format {
+        0x04 | 0x0c => Some(1),
+        0x06 | 0x0e => Some(2),
+        0x07 | 0x0f => Some(3),
+        0x08 | 0x10 => Some(4),
+        0x09 | 0x11 => Some(6),
+        0x0a | 0x12 => Some(8),
+        _ => None,
+    }
+}
+
+/// Little-endian unsigned integer of `width` bytes.
+fn unsigned(value: &[u8], width: usize) -> Option<u64> {
+    let bytes = value.get(..width)?;
+    Some(bytes.iter().rev().fold(0, |integer, byte| integer << 8 | *byte as u64))
+}
+
+/// `number` times 10 to the power of `exponent`. Dividing for negative exponents keeps
+/// e.g. 2340 × 10⁻² at 23.4 instead of 23.400000000000002.
+fn scale(number: f64, exponent: i32) -> f64 {
+    if exponent < 0 {
+        number / 10f64.powi(-exponent)
+    } else {
+        number * 10f64.powi(exponent)
+    }
+}
+
+/// IEEE 11073 16-bit float: 4-bit exponent, 12-bit mantissa. Returns the value and its decimals.
+fn ieee11073_sfloat(raw: u16) -> Option<(f64, Option<usize>)> {
+    let mantissa = ((raw << 4) as i16 >> 4) as i32;
+    let exponent = (raw as i16 >> 12) as i32;
+    // NaN, NRes, +INF, reserved and -INF
+    if (0x07fe..=0x0802).contains(&(raw & 0x0fff)) && exponent == 0 {
+        return None;
+    }
+    Some((scale(mantissa as f64, exponent), Some(exponent.min(0).unsigned_abs() as usize)))
+}
+
+/// IEEE 11073 32-bit float: 8-bit exponent, 24-bit mantissa. Returns the value and its decimals.
+fn ieee11073_float(raw: u32) -> Option<(f64, Option<usize>)> {
+    let mantissa = ((raw << 8) as i32) >> 8;
+    let exponent = (raw as i32) >> 24;
+    if (0x007f_fffe..=0x0080_0002).contains(&(raw & 0x00ff_ffff)) && exponent == 0 {
+        return None;
+    }
+    Some((scale(mantissa as f64, exponent), Some(exponent.min(0).unsigned_abs() as usize)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assigned_numbers::uuid_from_short;
+
+    fn format(format: u8, exponent: i8, unit: u16) -> PresentationFormat {
+        PresentationFormat { format, exponent, unit, namespace: 1, description: 0 }
+    }
+
+    fn quantity(value: f64, decimals: Option<usize>, unit: Option<&'static str>) -> Option<DecodedValue> {
+        Some(DecodedValue::Quantity { value, decimals, unit })
+    }
+
+    #[test]
+    fn parses_presentation_formats() {
+        let parsed = PresentationFormat::parse(&[0x0e, 0xfe, 0x2f, 0x27, 0x01, 0x00, 0x01]);
+        assert_eq!(parsed, Some(PresentationFormat { format: 0x0e, exponent: -2, unit: 0x272f, namespace: 1, description: 0x0100 }));
+        assert_eq!(parsed.unwrap().to_string(), "format 0x0e, exponent -2, unit °C");
+        assert_eq!(format(0x04, 0, 0x2700).to_string(), "format 0x04, exponent 0, unit 0x2700");
+        assert_eq!(PresentationFormat::parse(&[0x0e, 0xfe, 0x2f, 0x27, 0x01, 0x00]), None);
+    }
+
+    #[test]
+    fn decodes_integers() {
+        let cases: Vec<(PresentationFormat, &[u8], _)> = vec![
+            (format(0x04, 0, 0x2700), &[200], quantity(200.0, Some(0), None)),
+            (format(0x06, -1, 0x2700), &[0xe8, 0x03], quantity(100.0, Some(1), None)),
+            (format(0x07, 0, 0x2700), &[0x01, 0x02, 0x03], quantity(197121.0, Some(0), None)),
+            (format(0x08, 0, 0x2700), &[0xff, 0xff, 0xff, 0xff], quantity(4294967295.0, Some(0), None)),
+            (format(0x09, 0, 0x2700), &[0, 0, 0, 0, 0, 0x01], quantity(1099511627776.0, Some(0), None)),
+            // Positive exponents scale up without decimals
+            (format(0x04, 2, 0x2700), &[3], quantity(300.0, Some(0), None)),
+            // Signed values are sign-extended from their width
+            (format(0x0c, 0, 0x2700), &[0x80], quantity(-128.0, Some(0), None)),
+            (format(0x0e, -2, 0x272f), &[0x24, 0x09], quantity(23.4, Some(2), Some("°C"))),
+            (format(0x0e, -2, 0x272f), &[0x0c, 0xfe], quantity(-5.0, Some(2), Some("°C"))),
+            (format(0x0f, 0, 0x2700), &[0xff, 0xff, 0xff], quantity(-1.0, Some(0), None)),
+            (format(0x0f, 0, 0x2700), &[0xff, 0xff, 0x7f], quantity(8388607.0, Some(0), None)),
+            (format(0x10, 0, 0x2700), &[0x00, 0x00, 0x00, 0x80], quantity(i32::MIN as f64, Some(0), None)),
+            (format(0x11, 0, 0x2700), &[0xfe, 0xff, 0xff, 0xff, 0xff, 0xff], quantity(-2.0, Some(0), None)),
+            (format(0x12, 0, 0x2700), &[0xff; 8], quantity(-1.0, Some(0), None)),
+            // Too short
+            (format(0x0e, -2, 0x272f), &[0x24], None),
+            (format(0x08, 0, 0x2700), &[0x01, 0x02, 0x03], None),
+        ];
+        for (format, value, expected) in cases {
+            assert_eq!(format.decode(value), expected, "{} of {:02x?}", format, value);
+        }
+    }
+
+    #[test]
+    fn decodes_other_formats() {
+        let cases: Vec<(PresentationFormat, &[u8], _)> = vec![
+            (format(0x01, 0, 0x2700), &[0x01], Some(DecodedValue::Boolean { value: true })),
+            (format(0x01, 0, 0x2700), &[0x02], Some(DecodedValue::Boolean { value: false })),
+            (format(0x01, 0, 0x2700), &[], None),
+            (format(0x14, 0, 0x2700), &[0x00, 0x00, 0xc0, 0x3f], quantity(1.5, None, None)),
+            (format(0x15, 0, 0x2700), &[0, 0, 0, 0, 0, 0, 0xd0, 0xbf], quantity(-0.25, None, None)),
+            (format(0x19, 0, 0x2700), b"abc\0", Some(DecodedValue::Text { text: "abc".to_string() })),
+            (format(0x1a, 0, 0x2700), &[0x61, 0x00, 0x62, 0x00, 0x00, 0x00], Some(DecodedValue::Text { text: "ab".to_string() })),
+            // uint2, uint12, uint128 and struct aren't decoded
+            (format(0x02, 0, 0x2700), &[0x01], None),
+            (format(0x05, 0, 0x2700), &[0x01, 0x02], None),
+            (format(0x0b, 0, 0x2700), &[0x01; 16], None),
+            (format(0x1b, 0, 0x2700), &[0x01, 0x02], None),
+        ];
+        for (format, value, expected) in cases {
+            assert_eq!(format.decode(value), expected, "{} of {:02x?}", format, value);
+        }
+    }
+
+    #[test]
+    fn decodes_sfloats() {
+        let cases = [
+            // 36.4: mantissa 364, exponent -1
+            (0xf16c, Some((36.4, Some(1)))),
+            (0xfe94, Some((-36.4, Some(1)))),
+            (0x2005, Some((500.0, Some(0)))),
+            (0x0000, Some((0.0, Some(0)))),
+            (0x07fd, Some((2045.0, Some(0)))),
+            (0x0803, Some((-2045.0, Some(0)))),
+            (0x8001, Some((1e-8, Some(8)))),
+            // NaN, NRes, +INF, reserved and -INF, with exponent 0 only
+            (0x07ff, None),
+            (0x0800, None),
+            (0x07fe, None),
+            (0x0801, None),
+            (0x0802, None),
+            (0x17ff, Some((20470.0, Some(0)))),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(ieee11073_sfloat(raw), expected, "{:#06x}", raw);
+        }
+        let sfloat = format(0x16, 0, 0x272f);
+        assert_eq!(sfloat.decode(&[0x6c, 0xf1]), quantity(36.4, Some(1), Some("°C")));
+        assert_eq!(sfloat.decode(&[0xff, 0x07]), None);
+        assert_eq!(sfloat.decode(&[0x6c]), None);
+    }
+
+    #[test]
+    fn decodes_floats() {
+        let cases = [
+            (0xff00_016c, Some((36.4, Some(1)))),
+            (0x00ff_ffff, Some((-1.0, Some(0)))),
+            (0x0200_0005, Some((500.0, Some(0)))),
+            (0xfd00_0001, Some((0.001, Some(3)))),
+            (0x007f_fffd, Some((8388605.0, Some(0)))),
+            (0x007f_ffff, None),
+            (0x0080_0000, None),
+            (0x007f_fffe, None),
+            (0x0080_0001, None),
+            (0x0080_0002, None),
+            (0x017f_ffff, Some((83886070.0, Some(0)))),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(ieee11073_float(raw), expected, "{:#010x}", raw);
+        }
+        let float = format(0x17, 0, 0x2700);
+        assert_eq!(float.decode(&[0x6c, 0x01, 0x00, 0xff]), quantity(36.4, Some(1), None));
+        assert_eq!(float.decode(&[0xff, 0xff, 0x7f, 0x00]), None);
+        assert_eq!(float.decode(&[0x6c, 0x01, 0x00]), None);
+    }
+
+    #[test]
+    fn records_descriptors() {
+        let mut descriptors = CharacteristicDescriptors::default();
+        descriptors.add(&uuid_from_short(0x2901), b"Temperature\0");
+        descriptors.add(&uuid_from_short(0x2902), &[0x01, 0x00]);
+        descriptors.add(&uuid_from_short(0x2904), &[0x0e, 0xfe, 0x2f, 0x27, 0x01, 0x00, 0x00]);
+        descriptors.add(&uuid_from_short(0x2903), &[0x01]);
+        assert_eq!(descriptors.user_description.as_deref(), Some("Temperature"));
+        assert_eq!(descriptors.client_configuration, Some(ClientConfiguration { notifications: true, indications: false }));
+        assert_eq!(descriptors.presentation_format.map(|format| format.unit), Some(0x272f));
+        assert!(is_recorded(&uuid_from_short(0x2904)));
+        assert!(!is_recorded(&uuid_from_short(0x2903)));
+    }
+
+    #[test]
+    fn client_configurations() {
+        let cases: Vec<(&[u8], _)> = vec![
+            (&[0x00, 0x00], Some("off")),
+            (&[0x01, 0x00], Some("notifying")),
+            (&[0x02, 0x00], Some("indicating")),
+            (&[0x03, 0x00], Some("notifying and indicating")),
+            (&[], None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(ClientConfiguration::parse(value).map(|configuration| configuration.to_string()).as_deref(), expected, "{:02x?}", value);
+        }
+    }
+}
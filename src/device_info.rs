@@ -1,69 +1,200 @@
-use btleplug::platform::Peripheral;
-use btleplug::api::{Peripheral as PeripheralTrait, CharPropFlags};
+use btleplug::api::{CharPropFlags, Characteristic, WriteType};
+use chrono::{DateTime, Utc};
+use crate::assigned_numbers::{self, UuidKind};
+use crate::backend::BlePeripheral;
+use crate::descriptors::{self, CharacteristicDescriptors, ClientConfiguration, PresentationFormat};
+use crate::error::BluetoothError;
+use crate::gatt_decode;
+use crate::gatt_export::{GattCharacteristic, GattDescriptor, GattProfile, GattService, GattValue};
+use crate::metrics;
+use crate::mj_ht_v1::{self, MjHtV1Reading};
+use crate::sensor::{Measurement, ReadingSource, SensorData};
+use futures::stream::{Stream, StreamExt};
 use log::{info, warn, debug, error};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::pin::Pin;
 use std::sync::Arc;
 
-#[derive(Debug, Clone)]
+/// Stream of decoded MJ_HT_V1 readings.
+pub type ReadingStream = Pin<Box<dyn Stream<Item = MjHtV1Reading> + Send>>;
+
+/// A discovered GATT service with its characteristics.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceInfo {
+    pub uuid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'static str>,
+    pub characteristics: Vec<CharacteristicInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CharacteristicInfo {
+    pub uuid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'static str>,
+    /// The Characteristic User Description
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub properties: Vec<&'static str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub descriptors: Vec<DescriptorInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_configuration: Option<ClientConfiguration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presentation_format: Option<PresentationFormat>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DescriptorInfo {
+    pub uuid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'static str>,
+}
+
+/// When and how strongly one adapter last heard a device.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AdapterSighting {
+    pub rssi: i16,
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BluetoothDevice {
     pub mac_address: String,
     pub name: String,
     pub rssi: i16,
-    pub peripheral: Arc<Peripheral>,
+    /// TX power level the device advertises, in dBm, used to estimate its distance.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_power: Option<i16>,
+    /// Company IDs of the manufacturer data the device advertises.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub manufacturer_ids: BTreeSet<u16>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Name given by the user, accepted wherever a device is selected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    /// Free-form key/value pairs set by the user.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    /// MiBeacon or BTHome bind key (32 hex digits) used to decrypt the device's advertisements.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_key: Option<String>,
+    /// Packet ID of the last accepted encrypted MiBeacon frame, to reject replays. Kept in
+    /// the inventory so frames captured before a restart are rejected too.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mibeacon_packet_id: Option<u32>,
+    /// Counter of the last accepted encrypted BTHome frame, to reject replays.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bthome_counter: Option<u32>,
+    /// Sightings per adapter identifier, e.g. `hci0`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub adapters: BTreeMap<String, AdapterSighting>,
+    /// Adapter the peripheral handle belongs to, the one connections go through.
+    #[serde(skip)]
+    pub adapter: Option<String>,
+    /// Latest values decoded from the device's advertisements.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sensor: Option<SensorData>,
+    /// Handle to the peripheral, only available for devices seen during this run.
+    #[serde(skip)]
+    pub peripheral: Option<Arc<dyn BlePeripheral>>,
 }
 
 impl BluetoothDevice {
-    pub fn new(mac_address: String, name: String, rssi: i16, peripheral: Arc<Peripheral>) -> Self {
-        debug!("Creating new BluetoothDevice: MAC={}, Name={}, RSSI={}", mac_address, name, rssi);
+    pub fn new(mac_address: String, name: String, rssi: i16, adapter: &str, peripheral: Arc<dyn BlePeripheral>) -> Self {
+        debug!("Creating new BluetoothDevice: MAC={}, Name={}, RSSI={}, Adapter={}", mac_address, name, rssi, adapter);
+        let now = Utc::now();
         BluetoothDevice {
             mac_address,
             name,
             rssi,
-            peripheral,
+            tx_power: None,
+            manufacturer_ids: BTreeSet::new(),
+            first_seen: now,
+            last_seen: now,
+            alias: None,
+            location: None,
+            tags: BTreeSet::new(),
+            metadata: BTreeMap::new(),
+            bind_key: None,
+            mibeacon_packet_id: None,
+            bthome_counter: None,
+            adapters: BTreeMap::from([(adapter.to_string(), AdapterSighting { rssi, last_seen: now })]),
+            adapter: Some(adapter.to_string()),
+            sensor: None,
+            peripheral: Some(peripheral),
         }
     }
 
-    pub async fn list_available_info(&self) -> Result<(), Box<dyn std::error::Error>> {
-        self.connect().await?;
+    /// The alias if set, the advertised name otherwise.
+    pub fn display_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
 
-        info!("Connected to device with MAC={}", self.mac_address);
-    
-        if let Err(e) = self.peripheral.discover_services().await {
+    /// Records measurements decoded from an advertisement or notification.
+    pub fn apply_measurements(&mut self, source: ReadingSource, measurements: &[Measurement]) {
+        if measurements.is_empty() {
+            return;
+        }
+        let mut update = SensorData::new(source);
+        for measurement in measurements {
+            update.apply(*measurement);
+        }
+        match &mut self.sensor {
+            Some(sensor) => sensor.merge(&update),
+            None => self.sensor = Some(update),
+        }
+    }
+
+    /// Returns the peripheral handle, failing for devices only known from the inventory file.
+    fn peripheral(&self) -> Result<&Arc<dyn BlePeripheral>, BluetoothError> {
+        self.peripheral.as_ref().ok_or_else(|| BluetoothError::NotSeen { mac: self.mac_address.clone() })
+    }
+
+    /// Wraps a btleplug error of `operation` with this device's MAC address.
+    fn ble_error<'a>(&'a self, operation: &'static str, uuid: Option<&'a str>) -> impl FnOnce(btleplug::Error) -> BluetoothError + 'a {
+        move |e| BluetoothError::ble(operation, &self.mac_address, uuid, e)
+    }
+
+    /// Discovers the services of the connected device.
+    pub async fn load_services(&self) -> Result<(), BluetoothError> {
+        if let Err(e) = self.peripheral()?.discover_services().await {
             warn!("Failed to discover services on device {}: {:?}", self.mac_address, e);
-            return Err(Box::new(e));
+            return Err(self.ble_error("discover services", None)(e));
         }
-    
-        for service in self.peripheral.services() {
-            info!("Service UUID: {:?}", service.uuid);
-    
+        Ok(())
+    }
+
+    pub async fn list_available_info(&self) -> Result<(), BluetoothError> {
+        for service in self.peripheral()?.services() {
+            info!("Service: {}", assigned_numbers::describe(&service.uuid, UuidKind::Service));
+
             for characteristic in &service.characteristics {
-                info!("Characteristic UUID: {:?}, Properties: {:?}", characteristic.uuid, characteristic.properties);
+                info!("Characteristic: {}, Properties: {:?}", assigned_numbers::describe(&characteristic.uuid, UuidKind::Characteristic), characteristic.properties);
             }
         }
-    
-        self.disconnect().await?;
         Ok(())
     }
 
-    pub async fn retrieve_additional_info(&self) -> Result<(), Box<dyn std::error::Error>> {
-        self.connect().await?;
+    pub async fn retrieve_additional_info(&self) -> Result<(), BluetoothError> {
+        for service in self.peripheral()?.services() {
+            info!("Service: {}", assigned_numbers::describe(&service.uuid, UuidKind::Service));
 
-        info!("Connected to device with MAC={}", self.mac_address);
-    
-        if let Err(e) = self.peripheral.discover_services().await {
-            warn!("Failed to discover services on device {}: {:?}", self.mac_address, e);
-            return Err(Box::new(e));
-        }
-    
-        for service in self.peripheral.services() {
-            info!("Service UUID: {:?}", service.uuid);
-    
             for characteristic in service.characteristics {
-                info!("Characteristic UUID: {:?}", characteristic.uuid);
-    
+                let descriptors = self.read_descriptors(&characteristic).await?;
+                info!("Characteristic: {}", describe_characteristic(&characteristic.uuid, &descriptors));
+
                 if characteristic.properties.contains(CharPropFlags::READ) {
-                    match self.peripheral.read(&characteristic).await {
+                    match self.peripheral()?.read(&characteristic).await {
                         Ok(value) => {
-                            info!("Read value from characteristic {:?}: {:?}", characteristic.uuid, value);
+                            let decoded = gatt_decode::decode(&characteristic.uuid, descriptors.presentation_format.as_ref(), &value);
+                            info!("Read value from characteristic {}: {}", characteristic.uuid, gatt_decode::display(decoded.as_ref(), &value));
                         }
                         Err(err) => {
                             warn!("Failed to read characteristic {:?}: {:?}", characteristic.uuid, err);
@@ -72,118 +203,120 @@ impl BluetoothDevice {
                 }
             }
         }
-    
-        self.disconnect().await?;
         Ok(())
     }
 
     // Helper method to connect with retry logic and exponential backoff
-    pub async fn connect(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn connect(&self) -> Result<(), BluetoothError> {
         info!("Connecting to device with MAC={}", self.mac_address);
-        for attempt in 1..=3 {
-            if self.peripheral.connect().await.is_ok() {
-                info!("Connected to device with MAC={}", self.mac_address);
-                return Ok(());
-            } else {
-                warn!("Attempt {}/3: Failed to connect to device {}", attempt, self.mac_address);
-                tokio::time::sleep(std::time::Duration::from_secs(2_u64.pow(attempt))).await; // Exponential backoff
+        let attempts = 3;
+        let mut attempt = 1;
+        loop {
+            metrics::record_connection_attempt();
+            match self.peripheral()?.connect().await {
+                Ok(()) => {
+                    info!("Connected to device with MAC={}", self.mac_address);
+                    return Ok(());
+                }
+                Err(e) => {
+                    metrics::record_connection_failure();
+                    warn!("Attempt {}/{}: Failed to connect to device {}: {}", attempt, attempts, self.mac_address, e);
+                    if attempt == attempts {
+                        return Err(BluetoothError::ConnectFailed { mac: self.mac_address.clone(), attempts, source: e });
+                    }
+                    tokio::time::sleep(std::time::Duration::from_secs(2_u64.pow(attempt))).await; // Exponential backoff
+                    attempt += 1;
+                }
             }
         }
-        Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Failed to connect after multiple attempts")))
     }
 
-    pub async fn disconnect(&self) -> Result<(), Box<dyn std::error::Error>> {
-        if let Err(e) = self.peripheral.disconnect().await {
+    pub async fn is_connected(&self) -> Result<bool, BluetoothError> {
+        self.peripheral()?.is_connected().await.map_err(self.ble_error("check the connection", None))
+    }
+
+    pub async fn disconnect(&self) -> Result<(), BluetoothError> {
+        if let Err(e) = self.peripheral()?.disconnect().await {
             warn!("Failed to disconnect from device {}: {:?}", self.mac_address, e);
-            return Err(Box::new(e));
+            return Err(self.ble_error("disconnect", None)(e));
         } else {
             info!("Disconnected from device with MAC={}", self.mac_address);
         }
         Ok(())
     }
 
-    pub async fn subscribe_to_mj_ht_v1_notifications(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn subscribe_to_mj_ht_v1_notifications(&self) -> Result<(), BluetoothError> {
         let max_retries = 3;
         let mut attempt = 0;
-    
-        while attempt < max_retries {
+
+        loop {
             attempt += 1;
-    
+
             // Ensure the device is connected
-            if !self.peripheral.is_connected().await? {
+            if !self.peripheral()?.is_connected().await.map_err(self.ble_error("check the connection", None))? {
                 self.connect().await?;
+                // Give the freshly connected device time to get ready; an open connection already is
+                tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+            }
+
+            // The characteristics can only be found once services are discovered
+            if self.peripheral()?.services().is_empty() {
+                self.load_services().await?;
             }
-    
-            // Introduce a longer delay to ensure the device is ready
-            tokio::time::sleep(std::time::Duration::from_secs(3)).await;
 
             // Subscribe to temperature notifications
-            let temperature_uuid = "226caa55-6476-4566-7562-66734470666d";
-            let service_uuid = "226c0000-6476-4566-7562-66734470666d";
-    
+            let temperature_uuid = mj_ht_v1::TEMPERATURE_HUMIDITY_UUID;
+            let service_uuid = mj_ht_v1::SERVICE_UUID;
+
             match self.subscribe_to_notifications(service_uuid, temperature_uuid).await {
                 Ok(_) => {
                     info!("Successfully subscribed to temperature notifications.");
                 }
                 Err(e) => {
-                    warn!("Attempt {}/{}: Failed to subscribe to temperature notifications: {:?}", attempt, max_retries, e);
+                    warn!("Attempt {}/{}: Failed to subscribe to temperature notifications: {}", attempt, max_retries, e);
                     if attempt == max_retries {
-                        return Err(Box::new(std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            format!("Failed to subscribe after {} attempts: {}", max_retries, e),
-                        )));
+                        return Err(e);
                     }
                     continue; // Retry subscription
                 }
             }
-    
+
             // Subscribe to humidity notifications
-            let humidity_uuid = "226cbb55-6476-4566-7562-66734470666d";
-    
+            let humidity_uuid = mj_ht_v1::HUMIDITY_UUID;
+
             match self.subscribe_to_notifications(service_uuid, humidity_uuid).await {
                 Ok(_) => {
                     info!("Successfully subscribed to humidity notifications.");
                     return Ok(());
                 }
                 Err(e) => {
-                    warn!("Attempt {}/{}: Failed to subscribe to humidity notifications: {:?}", attempt, max_retries, e);
+                    warn!("Attempt {}/{}: Failed to subscribe to humidity notifications: {}", attempt, max_retries, e);
                     if attempt == max_retries {
-                        return Err(Box::new(std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            format!("Failed to subscribe after {} attempts: {}", max_retries, e),
-                        )));
+                        return Err(e);
                     }
                 }
             }
         }
-    
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Failed to subscribe to notifications after multiple attempts",
-        )))
     }
-    
-    async fn subscribe_to_notifications(&self, service_uuid: &str, characteristic_uuid: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let characteristic = self.find_characteristic(service_uuid, characteristic_uuid).ok_or_else(|| {
-            let error_msg = format!("Characteristic with UUID {} not found in service {}", characteristic_uuid, service_uuid);
-            warn!("{}", error_msg);
-            std::io::Error::new(std::io::ErrorKind::NotFound, error_msg)
-        })?;
-    
+
+    async fn subscribe_to_notifications(&self, service_uuid: &str, characteristic_uuid: &str) -> Result<(), BluetoothError> {
+        let characteristic = self.require_characteristic(service_uuid, characteristic_uuid)?;
+
         // Check if the characteristic has the Notify property
         if !characteristic.properties.contains(CharPropFlags::NOTIFY) {
-            let error_msg = format!("Characteristic with UUID {} does not support notifications", characteristic_uuid);
-            warn!("{}", error_msg);
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, error_msg)));
+            let error = BluetoothError::NotifyNotSupported { mac: self.mac_address.clone(), characteristic: characteristic_uuid.to_string() };
+            warn!("{}", error);
+            return Err(error);
         }
-    
-        for attempt in 1..=3 {
-            if !self.peripheral.is_connected().await? {
+
+        let mut attempt = 1;
+        loop {
+            if !self.peripheral()?.is_connected().await.map_err(self.ble_error("check the connection", None))? {
                 info!("Connecting to device...");
-                self.peripheral.connect().await?;
+                self.peripheral()?.connect().await.map_err(self.ble_error("connect", None))?;
             }
-    
-            match self.peripheral.subscribe(&characteristic).await {
+
+            match self.peripheral()?.subscribe(&characteristic).await {
                 Ok(_) => {
                     info!("Successfully subscribed to characteristic with UUID {}", characteristic_uuid);
                     return Ok(());
@@ -192,18 +325,17 @@ impl BluetoothDevice {
                     warn!("Attempt {}/3: Failed to subscribe to characteristic with UUID {}: {:?}", attempt, characteristic_uuid, e);
                     if attempt < 3 {
                         tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+                        attempt += 1;
                     } else {
-                        return Err(Box::new(e));
+                        return Err(self.ble_error("subscribe to", Some(characteristic_uuid))(e));
                     }
                 }
             }
         }
-    
-        Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Failed to subscribe after 3 attempts")))
     }
-    
+
     fn find_characteristic(&self, service_uuid: &str, characteristic_uuid: &str) -> Option<btleplug::api::Characteristic> {
-        for service in self.peripheral.services() {
+        for service in self.peripheral.as_ref()?.services() {
             if service.uuid.to_string() == service_uuid {
                 for characteristic in &service.characteristics {
                     if characteristic.uuid.to_string() == characteristic_uuid {
@@ -214,147 +346,317 @@ impl BluetoothDevice {
         }
         None
     }
-    
-    pub async fn read_characteristic(&self, service_uuid: &str, characteristic_uuid: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+
+    /// Like `find_characteristic`, failing with `CharacteristicNotFound`.
+    fn require_characteristic(&self, service_uuid: &str, characteristic_uuid: &str) -> Result<btleplug::api::Characteristic, BluetoothError> {
+        self.find_characteristic(service_uuid, characteristic_uuid).ok_or_else(|| {
+            let error = BluetoothError::CharacteristicNotFound {
+                mac: self.mac_address.clone(),
+                service: service_uuid.to_string(),
+                characteristic: characteristic_uuid.to_string(),
+            };
+            warn!("{}", error);
+            error
+        })
+    }
+
+    pub async fn read_characteristic(&self, service_uuid: &str, characteristic_uuid: &str) -> Result<Vec<u8>, BluetoothError> {
         // Find the characteristic by service and characteristic UUIDs
-        let characteristic = self.find_characteristic(service_uuid, characteristic_uuid).ok_or_else(|| {
-            let error_msg = format!("Characteristic with UUID {} not found", characteristic_uuid);
-            warn!("{}", error_msg);
-            std::io::Error::new(std::io::ErrorKind::NotFound, error_msg)
-        })?;
-    
+        let characteristic = self.require_characteristic(service_uuid, characteristic_uuid)?;
+
         // Attempt to read the characteristic value
-        let value = self.peripheral.read(&characteristic).await.map_err(|e| {
+        let value = self.peripheral()?.read(&characteristic).await.map_err(|e| {
             error!("Failed to read characteristic {}: {:?}", characteristic_uuid, e);
-            e
+            self.ble_error("read", Some(characteristic_uuid))(e)
         })?;
-    
+
         Ok(value)
     }
 
-    fn parse_temperature(value: &[u8]) -> f32 {
-        let raw_value = i16::from_le_bytes([value[0], value[1]]);
-        raw_value as f32 / 100.0
+    pub async fn write_characteristic(&self, service_uuid: &str, characteristic_uuid: &str, value: &[u8], write_type: WriteType) -> Result<(), BluetoothError> {
+        let characteristic = self.require_characteristic(service_uuid, characteristic_uuid)?;
+
+        // Refuse writes the characteristic does not support instead of letting the device reject them
+        let required = match write_type {
+            WriteType::WithResponse => CharPropFlags::WRITE,
+            WriteType::WithoutResponse => CharPropFlags::WRITE_WITHOUT_RESPONSE,
+        };
+        if !characteristic.properties.contains(required) {
+            let error = BluetoothError::WriteNotSupported {
+                mac: self.mac_address.clone(),
+                characteristic: characteristic_uuid.to_string(),
+                write_type,
+                properties: property_names(characteristic.properties),
+            };
+            warn!("{}", error);
+            return Err(error);
+        }
+
+        self.peripheral()?.write(&characteristic, value, write_type).await.map_err(|e| {
+            error!("Failed to write characteristic {}: {:?}", characteristic_uuid, e);
+            self.ble_error("write", Some(characteristic_uuid))(e)
+        })?;
+
+        Ok(())
+    }
+
+    /// Stops notifications of a characteristic.
+    pub async fn unsubscribe(&self, service_uuid: &str, characteristic_uuid: &str) -> Result<(), BluetoothError> {
+        let characteristic = self.require_characteristic(service_uuid, characteristic_uuid)?;
+        self.peripheral()?.unsubscribe(&characteristic).await.map_err(self.ble_error("unsubscribe from", Some(characteristic_uuid)))
+    }
+
+    /// Reads the descriptors that say what the characteristic is. A descriptor that can't be
+    /// read is logged and left out.
+    pub async fn read_descriptors(&self, characteristic: &Characteristic) -> Result<CharacteristicDescriptors, BluetoothError> {
+        let peripheral = self.peripheral()?;
+        let mut result = CharacteristicDescriptors::default();
+        for descriptor in characteristic.descriptors.iter().filter(|descriptor| descriptors::is_recorded(&descriptor.uuid)) {
+            match peripheral.read_descriptor(descriptor).await {
+                Ok(value) => result.add(&descriptor.uuid, &value),
+                Err(e) => warn!("Failed to read descriptor {} of characteristic {} of {}: {}", descriptor.uuid, characteristic.uuid, self.mac_address, e),
+            }
+        }
+        Ok(result)
     }
 
-    fn parse_humidity(value: &[u8]) -> f32 {
-        let raw_value = i16::from_le_bytes([value[2], value[3]]); // Assuming humidity is in the next two bytes
-        raw_value as f32 / 100.0
+    /// Like `read_descriptors`, for a characteristic given by UUID.
+    pub async fn characteristic_descriptors(&self, service_uuid: &str, characteristic_uuid: &str) -> Result<CharacteristicDescriptors, BluetoothError> {
+        let characteristic = self.require_characteristic(service_uuid, characteristic_uuid)?;
+        self.read_descriptors(&characteristic).await
     }
 
-    pub async fn read_mj_ht_v1_information(&self) -> Result<(), Box<dyn std::error::Error>> {
-        self.connect().await?;
+    /// Subscribes to a characteristic and prints every notification received on it,
+    /// until `count` values arrived (0 means no limit) or Ctrl-C is pressed.
+    pub async fn watch_characteristic(&self, service_uuid: &str, characteristic_uuid: &str, count: usize) -> Result<(), BluetoothError> {
+        let characteristic = self.require_characteristic(service_uuid, characteristic_uuid)?;
+        let descriptors = self.read_descriptors(&characteristic).await?;
+        let name = describe_characteristic(&characteristic.uuid, &descriptors);
+        let mut notifications = self.peripheral()?.notifications().await.map_err(self.ble_error("listen to notifications of", Some(characteristic_uuid)))?;
+        self.subscribe_to_notifications(service_uuid, characteristic_uuid).await?;
 
-        if let Err(e) = self.peripheral.discover_services().await {
-            warn!("Failed to discover services: {:?}", e);
-            return Err(Box::new(e));
+        let mut received = 0;
+        loop {
+            tokio::select! {
+                notification = notifications.next() => match notification {
+                    Some(notification) if notification.uuid.to_string() == characteristic_uuid => {
+                        let decoded = gatt_decode::decode(&notification.uuid, descriptors.presentation_format.as_ref(), &notification.value);
+                        println!("{}: {}", name, gatt_decode::display(decoded.as_ref(), &notification.value));
+                        received += 1;
+                        if count > 0 && received >= count {
+                            break;
+                        }
+                    }
+                    Some(_) => {}
+                    None => {
+                        warn!("Notification stream from device {} ended", self.mac_address);
+                        break;
+                    }
+                },
+                _ = tokio::signal::ctrl_c() => {
+                    info!("Interrupted, stopping notifications from device {}", self.mac_address);
+                    break;
+                }
+            }
         }
 
-        let characteristics = vec![
-            ("Device Name", "00001800-0000-1000-8000-00805f9b34fb", "00002a00-0000-1000-8000-00805f9b34fb"),
-            ("Appearance", "00001800-0000-1000-8000-00805f9b34fb", "00002a01-0000-1000-8000-00805f9b34fb"),
-            ("Peripheral Preferred Connection Parameters", "00001800-0000-1000-8000-00805f9b34fb", "00002a04-0000-1000-8000-00805f9b34fb"),
-            ("Firmware Version", "0000180a-0000-1000-8000-00805f9b34fb", "00002a26-0000-1000-8000-00805f9b34fb"),
-            ("Manufacturer Name", "0000180a-0000-1000-8000-00805f9b34fb", "00002a29-0000-1000-8000-00805f9b34fb"),
-            ("Battery Level", "0000180f-0000-1000-8000-00805f9b34fb", "00002a19-0000-1000-8000-00805f9b34fb"),
-        ];
-
-        for (name, service_uuid, characteristic_uuid) in characteristics {
-            match self.read_characteristic(service_uuid, characteristic_uuid).await {
+        self.unsubscribe(service_uuid, characteristic_uuid).await
+    }
+
+    /// Subscribes to the MJ_HT_V1 notifications and returns the decoded readings.
+    /// The stream runs until it is dropped; the caller unsubscribes afterwards with
+    /// `unsubscribe_from_mj_ht_v1_notifications`.
+    pub async fn mj_ht_v1_readings(&self) -> Result<ReadingStream, BluetoothError> {
+        // Listen before subscribing so the first notification is not lost
+        let notifications = self.peripheral()?.notifications().await.map_err(self.ble_error("listen to notifications", None))?;
+        self.subscribe_to_mj_ht_v1_notifications().await?;
+
+        let mac_address = self.mac_address.clone();
+        Ok(Box::pin(notifications.filter_map(move |notification| {
+            let reading = MjHtV1Reading::from_payload(&notification.value);
+            if reading.is_none() {
+                debug!("Ignoring undecodable notification from {}: {:?}", mac_address, notification.value);
+            }
+            async move { reading }
+        })))
+    }
+
+    /// Stops the notifications started by `mj_ht_v1_readings`.
+    pub async fn unsubscribe_from_mj_ht_v1_notifications(&self) -> Result<(), BluetoothError> {
+        self.unsubscribe(mj_ht_v1::SERVICE_UUID, mj_ht_v1::TEMPERATURE_HUMIDITY_UUID).await?;
+        self.unsubscribe(mj_ht_v1::SERVICE_UUID, mj_ht_v1::HUMIDITY_UUID).await
+    }
+
+    pub async fn read_mj_ht_v1_information(&self) -> Result<(), BluetoothError> {
+        // (service, characteristic) short UUIDs, named from the assigned numbers
+        let characteristics = [(0x1800, 0x2a00), (0x1800, 0x2a01), (0x1800, 0x2a04), (0x180a, 0x2a26), (0x180a, 0x2a29), (0x180f, 0x2a19)];
+
+        for (service, characteristic) in characteristics {
+            let service_uuid = assigned_numbers::uuid_from_short(service).to_string();
+            let characteristic_uuid = assigned_numbers::uuid_from_short(characteristic);
+            let name = assigned_numbers::name(&characteristic_uuid, UuidKind::Characteristic).unwrap_or("Unknown");
+            match self.read_characteristic(&service_uuid, &characteristic_uuid.to_string()).await {
                 Ok(value) => {
-                    let output = match name {
-                        "Device Name" | "Firmware Version" | "Manufacturer Name" => {
-                            String::from_utf8_lossy(&value).to_string()
-                        }
-                        "Appearance" | "Peripheral Preferred Connection Parameters" => {
-                            format!("{:?}", value)
-                        }
-                        "Battery Level" => {
-                            format!("{}%", value[0])
-                        }
-                        _ => format!("{:?}", value),
-                    };
-                    println!("{}: {}", name, output);
+                    let decoded = gatt_decode::decode(&characteristic_uuid, None, &value);
+                    println!("{}: {}", name, gatt_decode::display(decoded.as_ref(), &value));
                 }
                 Err(e) => {
-                    println!("Failed to read {}: {:?}", name, e);
+                    println!("Failed to read {}: {}", name, e);
                 }
             }
         }
-
-        self.disconnect().await?;
         Ok(())
     }
 
-    pub async fn discover_services(&self) -> Result<(), Box<dyn std::error::Error>> {
-        self.connect().await?;
+    pub async fn discover_services(&self) -> Result<(), BluetoothError> {
+        for service in self.fetch_services().await? {
+            info!("Service: {}", assigned_numbers::describe_str(&service.uuid, UuidKind::Service));
 
-        if let Err(e) = self.peripheral.discover_services().await {
-            warn!("Failed to discover services: {:?}", e);
-            return Err(Box::new(e));
+            for characteristic in &service.characteristics {
+                let mut line = assigned_numbers::describe_str(&characteristic.uuid, UuidKind::Characteristic);
+                if let Some(label) = &characteristic.label {
+                    line += &format!(" \"{}\"", label);
+                }
+                line += &format!(", Properties: {}", characteristic.properties.join(" | "));
+                if let Some(configuration) = characteristic.client_configuration {
+                    line += &format!(", Notifications: {}", configuration);
+                }
+                if let Some(format) = characteristic.presentation_format {
+                    line += &format!(", Presentation: {}", format);
+                }
+                info!("Characteristic: {}", line);
+                for descriptor in &characteristic.descriptors {
+                    info!("Descriptor: {}", assigned_numbers::describe_str(&descriptor.uuid, UuidKind::Descriptor));
+                }
+            }
         }
+        Ok(())
+    }
 
-        for service in self.peripheral.services() {
-            info!("Service UUID: {:?}", service.uuid);
-
+    /// The services, characteristics and descriptors found on the connected device.
+    pub async fn fetch_services(&self) -> Result<Vec<ServiceInfo>, BluetoothError> {
+        let mut services = Vec::new();
+        for service in self.peripheral()?.services() {
+            let mut characteristics = Vec::new();
             for characteristic in &service.characteristics {
-                info!("Characteristic UUID: {:?}, Properties: {:?}", characteristic.uuid, characteristic.properties);
+                let descriptors = self.read_descriptors(characteristic).await?;
+                characteristics.push(CharacteristicInfo {
+                    uuid: characteristic.uuid.to_string(),
+                    name: assigned_numbers::name(&characteristic.uuid, UuidKind::Characteristic),
+                    label: descriptors.user_description,
+                    properties: property_names(characteristic.properties),
+                    descriptors: characteristic.descriptors.iter().map(|descriptor| DescriptorInfo {
+                        uuid: descriptor.uuid.to_string(),
+                        name: assigned_numbers::name(&descriptor.uuid, UuidKind::Descriptor),
+                    }).collect(),
+                    client_configuration: descriptors.client_configuration,
+                    presentation_format: descriptors.presentation_format,
+                });
             }
+            services.push(ServiceInfo {
+                uuid: service.uuid.to_string(),
+                name: assigned_numbers::name(&service.uuid, UuidKind::Service),
+                characteristics,
+            });
         }
+        Ok(services)
+    }
 
-        self.disconnect().await?;
-        Ok(())
+    /// The full GATT table of the connected device, with the values of the readable
+    /// characteristics if `read_values` is set. Failed reads are recorded, not returned.
+    pub async fn gatt_profile(&self, read_values: bool) -> Result<GattProfile, BluetoothError> {
+        let peripheral = self.peripheral()?;
+        let mut services = Vec::new();
+        for service in peripheral.services() {
+            let mut characteristics = Vec::new();
+            for characteristic in &service.characteristics {
+                let descriptors = self.read_descriptors(characteristic).await?;
+                let (value, read_error) = if read_values && characteristic.properties.contains(CharPropFlags::READ) {
+                    match peripheral.read(characteristic).await {
+                        Ok(value) => {
+                            let decoded = gatt_decode::decode(&characteristic.uuid, descriptors.presentation_format.as_ref(), &value);
+                            (Some(GattValue { hex: format_hex(&value), utf8: String::from_utf8(value).ok(), decoded }), None)
+                        }
+                        Err(e) => {
+                            warn!("Failed to read characteristic {} of {}: {}", characteristic.uuid, self.mac_address, e);
+                            (None, Some(e.to_string()))
+                        }
+                    }
+                } else {
+                    (None, None)
+                };
+                characteristics.push(GattCharacteristic {
+                    uuid: characteristic.uuid.to_string(),
+                    name: assigned_numbers::name(&characteristic.uuid, UuidKind::Characteristic),
+                    label: descriptors.user_description,
+                    properties: property_names(characteristic.properties),
+                    presentation_format: descriptors.presentation_format,
+                    descriptors: characteristic.descriptors.iter().map(|descriptor| GattDescriptor {
+                        uuid: descriptor.uuid.to_string(),
+                        name: assigned_numbers::name(&descriptor.uuid, UuidKind::Descriptor),
+                    }).collect(),
+                    value,
+                    read_error,
+                });
+            }
+            services.push(GattService {
+                uuid: service.uuid.to_string(),
+                name: assigned_numbers::name(&service.uuid, UuidKind::Service),
+                primary: service.primary,
+                characteristics,
+            });
+        }
+        let mut profile = GattProfile { mac_address: self.mac_address.clone(), name: self.name.clone(), services };
+        profile.sort();
+        Ok(profile)
     }
 
-    pub async fn read_mj_ht_v1(&self) -> Result<(), Box<dyn std::error::Error>> {
-        // Step 1: Connect to the device
-        info!("Connecting to device with MAC={}", self.mac_address);
-        self.peripheral.connect().await.map_err(|e| {
-            error!("Failed to connect to device {}: {:?}", self.mac_address, e);
-            e
-        })?;
-        info!("Connected to device with MAC={}", self.mac_address);
-    
-        // Step 3: Find the "Device Name" characteristic
-        let service_uuid = "00001800-0000-1000-8000-00805f9b34fb";
-        let characteristic_uuid = "00002a00-0000-1000-8000-00805f9b34fb";
-        let characteristic = self
-            .peripheral
-            .services()
-            .iter()
-            .find(|service| service.uuid.to_string() == service_uuid)
-            .and_then(|service| {
-                service
-                    .characteristics
-                    .iter()
-                    .find(|c| c.uuid.to_string() == characteristic_uuid)
-            })
-            .ok_or_else(|| {
-                let error_msg = format!(
-                    "Characteristic with UUID {} not found in service {}",
-                    characteristic_uuid, service_uuid
-                );
-                error!("{}", error_msg);
-                std::io::Error::new(std::io::ErrorKind::NotFound, error_msg)
-            })?
-            .clone();
-    
-        // Step 4: Read the "Device Name" characteristic
-        let value = self.peripheral.read(&characteristic).await.map_err(|e| {
+    pub async fn read_mj_ht_v1(&self) -> Result<(), BluetoothError> {
+        // Find the "Device Name" characteristic
+        let service_uuid = assigned_numbers::uuid_from_short(0x1800).to_string();
+        let characteristic_uuid = &assigned_numbers::uuid_from_short(0x2a00).to_string();
+        let characteristic = self.require_characteristic(&service_uuid, characteristic_uuid)?;
+
+        // Read the "Device Name" characteristic
+        let value = self.peripheral()?.read(&characteristic).await.map_err(|e| {
             error!("Failed to read characteristic {}: {:?}", characteristic_uuid, e);
-            e
-        })?;
-        let device_name = String::from_utf8_lossy(&value).to_string();
-        println!("Device Name: {}", device_name);
-    
-        // Step 5: Disconnect from the device
-        self.peripheral.disconnect().await.map_err(|e| {
-            error!("Failed to disconnect from device {}: {:?}", self.mac_address, e);
-            e
+            self.ble_error("read", Some(characteristic_uuid))(e)
         })?;
-        info!("Disconnected from device with MAC={}", self.mac_address);
-    
+        let decoded = gatt_decode::decode(&characteristic.uuid, None, &value);
+        println!("Device Name: {}", gatt_decode::display(decoded.as_ref(), &value));
         Ok(())
     }
-    
+
+}
+
+/// The characteristic's name or UUID, followed by its User Description if it has one.
+fn describe_characteristic(uuid: &uuid::Uuid, descriptors: &CharacteristicDescriptors) -> String {
+    let name = assigned_numbers::describe(uuid, UuidKind::Characteristic);
+    match &descriptors.user_description {
+        Some(label) => format!("{} \"{}\"", name, label),
+        None => name,
+    }
+}
+
+/// Names of the properties set in `flags`, as in the Bluetooth specification.
+pub fn property_names(flags: CharPropFlags) -> Vec<&'static str> {
+    [
+        (CharPropFlags::BROADCAST, "broadcast"),
+        (CharPropFlags::READ, "read"),
+        (CharPropFlags::WRITE_WITHOUT_RESPONSE, "write-without-response"),
+        (CharPropFlags::WRITE, "write"),
+        (CharPropFlags::NOTIFY, "notify"),
+        (CharPropFlags::INDICATE, "indicate"),
+        (CharPropFlags::AUTHENTICATED_SIGNED_WRITES, "authenticated-signed-writes"),
+        (CharPropFlags::EXTENDED_PROPERTIES, "extended-properties"),
+    ]
+    .into_iter()
+    .filter(|(flag, _)| flags.contains(*flag))
+    .map(|(_, name)| name)
+    .collect()
+}
+
+/// Formats raw bytes as a lowercase hex string.
+pub fn format_hex(value: &[u8]) -> String {
+    value.iter().map(|byte| format!("{:02x}", byte)).collect()
 }
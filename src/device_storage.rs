@@ -1,31 +1,174 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use crate::device_info::BluetoothDevice;
-use log::debug;
+use crate::mj_ht_v1::MjHtV1Reading;
+use crate::presence::{PresenceConfig, PresenceEvent, PresenceTracker};
+use crate::rssi::{RssiHistory, SignalSummary, DEFAULT_PATH_LOSS_EXPONENT};
+use crate::sensor::ReadingSource;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
 
+/// Seconds without a sighting after which another adapter takes over a device.
+const ADAPTER_TIMEOUT_SECONDS: i64 = 30;
+
+fn default_path_loss_exponent() -> f64 {
+    DEFAULT_PATH_LOSS_EXPONENT
+}
+
+/// Where a scan stores what it finds: an inventory of its own, or one shared with the HTTP
+/// handlers that is only locked while a device is stored.
+pub trait Inventory {
+    async fn update<R>(&mut self, f: impl FnOnce(&mut DeviceStorage) -> R) -> R;
+}
+
+impl Inventory for DeviceStorage {
+    async fn update<R>(&mut self, f: impl FnOnce(&mut DeviceStorage) -> R) -> R {
+        f(self)
+    }
+}
+
+impl Inventory for Arc<Mutex<DeviceStorage>> {
+    async fn update<R>(&mut self, f: impl FnOnce(&mut DeviceStorage) -> R) -> R {
+        f(&mut *self.lock().await)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct DeviceStorage {
-    devices: HashMap<u32, BluetoothDevice>,
+    devices: BTreeMap<u32, BluetoothDevice>,
     next_id: u32,
+    /// Recent RSSI samples per device ID, from the adapter the device is reached through.
+    #[serde(default)]
+    rssi_history: BTreeMap<u32, RssiHistory>,
+    /// Used to estimate distances, see `rssi::estimate_distance`.
+    #[serde(skip, default = "default_path_loss_exponent")]
+    path_loss_exponent: f64,
+    #[serde(skip)]
+    presence: PresenceTracker,
+    /// File the inventory is saved to, `None` keeps it in memory only.
+    #[serde(skip)]
+    path: Option<PathBuf>,
 }
 
 impl DeviceStorage {
     pub fn new() -> Self {
         DeviceStorage {
-            devices: HashMap::new(),
+            devices: BTreeMap::new(),
             next_id: 1,
+            rssi_history: BTreeMap::new(),
+            path_loss_exponent: DEFAULT_PATH_LOSS_EXPONENT,
+            presence: PresenceTracker::default(),
+            path: None,
         }
     }
 
+    /// Loads the inventory from `path`, starting empty if the file does not exist yet.
+    /// Subsequent calls to `save` write back to the same file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut storage = if path.exists() {
+            info!("Loading device inventory from {}", path.display());
+            let contents = std::fs::read_to_string(path)?;
+            serde_json::from_str(&contents)
+                .map_err(|e| format!("Invalid device inventory {}: {}", path.display(), e))?
+        } else {
+            info!("No device inventory at {}, starting empty", path.display());
+            DeviceStorage::new()
+        };
+        storage.path = Some(path.to_path_buf());
+        storage.migrate_labels();
+        Ok(storage)
+    }
+
+    /// Moves `alias` and `location` metadata entries, which labelled devices before they
+    /// became fields, into their fields. Entries that would clash are left in place.
+    fn migrate_labels(&mut self) {
+        let ids: Vec<u32> = self.devices.keys().copied().collect();
+        for id in ids {
+            let device = &self.devices[&id];
+            let alias = device.metadata.get("alias").filter(|_| device.alias.is_none()).cloned();
+            let location = device.metadata.get("location").filter(|_| device.location.is_none()).cloned();
+            if alias.is_none() && location.is_none() {
+                continue;
+            }
+            match self.label_device(id, alias.clone(), location.clone(), Vec::new()) {
+                Ok(()) => {
+                    let device = self.devices.get_mut(&id).unwrap();
+                    if alias.is_some() {
+                        device.metadata.remove("alias");
+                    }
+                    if location.is_some() {
+                        device.metadata.remove("location");
+                    }
+                }
+                Err(e) => warn!("Keeping the alias and location metadata of device {}: {}", id, e),
+            }
+        }
+    }
+
+    /// Writes the inventory to its file. The file is replaced atomically so an
+    /// interrupted write never leaves a truncated inventory behind.
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        debug!("Saving {} device(s) to {}", self.devices.len(), path.display());
+        let contents = serde_json::to_string_pretty(self)?;
+        let temporary = path.with_extension("json.tmp");
+        std::fs::write(&temporary, contents)?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
     pub fn add_or_update_device(&mut self, device: BluetoothDevice) {
         debug!("Adding or updating device with MAC: {}", device.mac_address);
 
         // Check if the device with the same MAC address already exists
-        if let Some((_id, existing_device)) = self.devices.iter_mut()
+        if let Some((_, existing_device)) = self.devices.iter_mut()
                                                          .find(|(_, d)| d.mac_address == device.mac_address) {
             // Update the existing device's information
             debug!("Updating existing device with MAC: {}", device.mac_address);
-            existing_device.name = device.name;
-            existing_device.rssi = device.rssi;
-            existing_device.peripheral = device.peripheral.clone(); // Ensure peripheral is updated
+            // Keep a remembered name when the device advertises without one
+            if device.name != "Unknown Device" || existing_device.name.is_empty() {
+                existing_device.name = device.name;
+            }
+            existing_device.last_seen = device.last_seen;
+            if device.mibeacon_packet_id.is_some() {
+                existing_device.mibeacon_packet_id = device.mibeacon_packet_id;
+            }
+            if device.bthome_counter.is_some() {
+                existing_device.bthome_counter = device.bthome_counter;
+            }
+            if device.tx_power.is_some() {
+                existing_device.tx_power = device.tx_power;
+            }
+            existing_device.manufacturer_ids.extend(&device.manufacturer_ids);
+            if let Some(sensor) = &device.sensor {
+                match &mut existing_device.sensor {
+                    Some(existing_sensor) => existing_sensor.merge(sensor),
+                    None => existing_device.sensor = Some(sensor.clone()),
+                }
+            }
+            // Connect through the adapter hearing the device best, moving on when it loses it
+            let current = existing_device.adapter.as_ref().and_then(|adapter| existing_device.adapters.get(adapter));
+            let switch = match current {
+                Some(sighting) => {
+                    device.adapter == existing_device.adapter
+                        || device.rssi > sighting.rssi
+                        || device.last_seen - sighting.last_seen > chrono::Duration::seconds(ADAPTER_TIMEOUT_SECONDS)
+                }
+                None => true,
+            };
+            existing_device.adapters.extend(device.adapters);
+            if switch {
+                if device.adapter != existing_device.adapter {
+                    debug!("Device {} now reached through adapter {:?}", device.mac_address, device.adapter);
+                }
+                existing_device.rssi = device.rssi;
+                existing_device.adapter = device.adapter;
+                existing_device.peripheral = device.peripheral.clone(); // Ensure peripheral is updated
+            }
         } else {
             // Add new device with a new internal ID
             debug!("Adding new device with MAC: {} as ID: {}", device.mac_address, self.next_id);
@@ -34,17 +177,138 @@ impl DeviceStorage {
         }
     }
 
+    /// Records a reading notified by an MJ_HT_V1 as the device's latest sensor values.
+    pub fn record_mj_ht_v1_reading(&mut self, id: u32, reading: &MjHtV1Reading) {
+        if let Some(device) = self.devices.get_mut(&id) {
+            device.apply_measurements(ReadingSource::MjHtV1, &reading.measurements());
+        }
+    }
+
+    /// Sets the path loss exponent distances are estimated with.
+    pub fn set_path_loss_exponent(&mut self, path_loss_exponent: f64) {
+        self.path_loss_exponent = path_loss_exponent;
+    }
+
+    pub fn set_presence_config(&mut self, config: PresenceConfig) {
+        self.presence.set_config(config);
+    }
+
+    /// Arrivals and departures from now on, see `record_sighting` and `check_presence`.
+    pub fn presence_events(&self) -> tokio::sync::broadcast::Receiver<PresenceEvent> {
+        self.presence.subscribe()
+    }
+
+    pub fn is_present(&self, id: u32) -> bool {
+        self.presence.is_present(id)
+    }
+
+    /// Tells presence detection and the RSSI history that the device with this MAC address
+    /// just advertised on `adapter`. Only advertisements count: `add_or_update_device` is also
+    /// fed from the adapters' caches, whose RSSI may be long stale.
+    pub fn record_sighting(&mut self, mac_address: &str, adapter: &str) {
+        let Some((&id, device)) = self.devices.iter().find(|(_, d)| d.mac_address.eq_ignore_ascii_case(mac_address)) else {
+            return;
+        };
+        self.presence.sighted(id, device, device.last_seen);
+        // Samples of other adapters would mix different antennas and distances
+        if device.adapter.as_deref() == Some(adapter) && device.rssi != 0 {
+            self.rssi_history.entry(id).or_default().push(device.rssi, device.last_seen);
+        }
+    }
+
+    /// Emits departures of devices not heard for the absence timeout.
+    pub fn check_presence(&mut self) {
+        self.presence.check(self.devices.iter().map(|(&id, device)| (id, device)), chrono::Utc::now());
+    }
+
+    pub fn rssi_history(&self, id: u32) -> Option<&RssiHistory> {
+        self.rssi_history.get(&id)
+    }
+
+    /// Smoothed RSSI and estimated distance of a device, once it has been heard.
+    pub fn signal(&self, id: u32) -> Option<SignalSummary> {
+        let tx_power = self.devices.get(&id)?.tx_power;
+        self.rssi_history.get(&id)?.summary(tx_power, self.path_loss_exponent)
+    }
+
     pub fn get_device(&self, id: u32) -> Option<&BluetoothDevice> {
         debug!("Retrieving device with ID: {}", id);
         self.devices.get(&id)
     }
 
+    pub fn get_device_mut(&mut self, id: u32) -> Option<&mut BluetoothDevice> {
+        self.devices.get_mut(&id)
+    }
+
+    pub fn get_device_by_mac(&self, mac_address: &str) -> Option<&BluetoothDevice> {
+        self.devices.values().find(|d| d.mac_address.eq_ignore_ascii_case(mac_address))
+    }
+
+    /// Resolves a device selector, either an internal ID or a MAC address.
+    pub fn find_device(&self, selector: &str) -> Option<u32> {
+        debug!("Resolving device selector: {}", selector);
+        let selector = selector.trim();
+        if let Ok(id) = selector.parse::<u32>() {
+            return self.devices.contains_key(&id).then_some(id);
+        }
+        self.devices.iter()
+            .find(|(_, d)| {
+                d.mac_address.eq_ignore_ascii_case(selector)
+                    || d.alias.as_deref().is_some_and(|alias| alias.eq_ignore_ascii_case(selector))
+            })
+            .map(|(&id, _)| id)
+    }
+
+    /// Updates the user labels of a device. An empty alias or location clears it; tags
+    /// starting with `-` are removed, others added. Aliases must be unique and not numeric,
+    /// so they never shadow an ID.
+    pub fn label_device(&mut self, id: u32, alias: Option<String>, location: Option<String>, tags: Vec<String>) -> Result<(), String> {
+        if let Some(alias) = alias.as_deref().map(str::trim).filter(|alias| !alias.is_empty()) {
+            if alias.parse::<u32>().is_ok() {
+                return Err(format!("Alias '{}' is a number and would be mistaken for an ID", alias));
+            }
+            if let Some(other) = self.find_device(alias).filter(|&other| other != id) {
+                return Err(format!("Alias '{}' already names device {}", alias, other));
+            }
+        }
+
+        let device = self.devices.get_mut(&id).ok_or_else(|| format!("Device {} not found", id))?;
+        if let Some(alias) = alias {
+            device.alias = Some(alias.trim().to_string()).filter(|alias| !alias.is_empty());
+        }
+        if let Some(location) = location {
+            device.location = Some(location.trim().to_string()).filter(|location| !location.is_empty());
+        }
+        for tag in tags {
+            match tag.trim().strip_prefix('-') {
+                Some(tag) => {
+                    device.tags.remove(tag);
+                }
+                None if !tag.trim().is_empty() => {
+                    device.tags.insert(tag.trim().to_string());
+                }
+                None => {}
+            }
+        }
+        info!("Labelled device {}: alias {:?}, location {:?}, tags {:?}", id, device.alias, device.location, device.tags);
+        Ok(())
+    }
+
     pub fn list_devices(&self) -> Vec<(u32, &BluetoothDevice)> {
         debug!("Listing all devices...");
         // Return a vector of tuples containing the internal ID and a reference to the device
         self.devices.iter().map(|(&id, device)| (id, device)).collect()
     }
 
+    /// Lists devices with values decoded from their advertisements.
+    pub fn list_sensor_devices(&self) -> Vec<(u32, &BluetoothDevice)> {
+        debug!("Listing all devices with sensor data...");
+        self.devices.iter()
+            .filter(|(_, device)| device.sensor.is_some())
+            .map(|(&id, device)| (id, device))
+            .collect()
+    }
+
     /// Lists only devices that are MJ_HT_V1 sensors.
     pub fn list_mj_ht_v1_devices(&self) -> Vec<(u32, &BluetoothDevice)> {
         debug!("Listing all MJ_HT_V1 devices...");
@@ -55,8 +319,146 @@ impl DeviceStorage {
             .collect()
     }
 
-    // Count the number of devices with a specific name
+    /// Lists MJ_HT_V1 sensors together with thermometers running the ATC or pvvx firmware.
+    pub fn list_thermometer_devices(&self) -> Vec<(u32, &BluetoothDevice)> {
+        debug!("Listing all thermometers...");
+        self.devices.iter()
+            .filter(|(_, device)| {
+                device.name.contains("MJ_HT_V1")
+                    || matches!(device.sensor.as_ref().map(|s| s.source), Some(ReadingSource::Atc | ReadingSource::Pvvx))
+            })
+            .map(|(&id, device)| (id, device))
+            .collect()
+    }
+
+    // Count the number of devices with a specific name seen since startup
     pub fn count_devices_by_name(&self, name: &str) -> usize {
-        self.devices.values().filter(|d| d.name == name).count()
+        self.devices.values().filter(|d| d.name == name && d.peripheral.is_some()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::simulated::SimulatedPeripheral;
+
+    const ADDRESS: &str = "A4:C1:38:00:00:02";
+
+    fn sighting(rssi: i16, adapter: &str) -> BluetoothDevice {
+        BluetoothDevice::new(ADDRESS.to_string(), "ATC_000002".to_string(), rssi, adapter, Arc::new(SimulatedPeripheral::new(ADDRESS)))
+    }
+
+    /// A fresh directory for inventory files of one test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bluetooth-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn samples(storage: &DeviceStorage) -> Vec<i16> {
+        let id = storage.find_device(ADDRESS).unwrap();
+        storage.rssi_history(id).map(|history| history.samples().map(|sample| sample.rssi).collect()).unwrap_or_default()
+    }
+
+    #[test]
+    fn rssi_is_sampled_from_advertisements_only() {
+        let mut storage = DeviceStorage::new();
+        // Cached properties, e.g. listed when a scan starts
+        storage.add_or_update_device(sighting(-70, "hci0"));
+        storage.add_or_update_device(sighting(-70, "hci0"));
+        assert!(samples(&storage).is_empty());
+
+        storage.add_or_update_device(sighting(-65, "hci0"));
+        storage.record_sighting(ADDRESS, "hci0");
+        storage.add_or_update_device(sighting(-66, "hci0"));
+        storage.record_sighting(&ADDRESS.to_lowercase(), "hci0");
+        assert_eq!(samples(&storage), [-65, -66]);
+    }
+
+    #[test]
+    fn rssi_is_sampled_from_the_current_adapter_only() {
+        let mut storage = DeviceStorage::new();
+        storage.add_or_update_device(sighting(-60, "hci0"));
+        storage.record_sighting(ADDRESS, "hci0");
+        // A weaker adapter doesn't take over, so its samples are left out
+        storage.add_or_update_device(sighting(-80, "hci1"));
+        storage.record_sighting(ADDRESS, "hci1");
+        assert_eq!(samples(&storage), [-60]);
+        // A stronger one does
+        storage.add_or_update_device(sighting(-50, "hci1"));
+        storage.record_sighting(ADDRESS, "hci1");
+        assert_eq!(samples(&storage), [-60, -50]);
+    }
+
+    fn device(mac_address: &str) -> BluetoothDevice {
+        BluetoothDevice::new(mac_address.to_string(), "ATC".to_string(), -60, "hci0", Arc::new(SimulatedPeripheral::new(mac_address)))
+    }
+
+    #[test]
+    fn ids_stay_the_same_across_runs() {
+        let path = temp_dir("ids").join("devices.json");
+        let mut storage = DeviceStorage::load(&path).unwrap();
+        storage.add_or_update_device(device("A4:C1:38:00:00:01"));
+        storage.add_or_update_device(device("A4:C1:38:00:00:02"));
+        storage.add_or_update_device(device("A4:C1:38:00:00:03"));
+        // IDs of forgotten devices aren't handed out again
+        let forgotten = storage.find_device("A4:C1:38:00:00:03").unwrap();
+        storage.devices.remove(&forgotten);
+        storage.save().unwrap();
+        let first = storage.find_device("A4:C1:38:00:00:01").unwrap();
+        let second = storage.find_device("A4:C1:38:00:00:02").unwrap();
+
+        let mut storage = DeviceStorage::load(&path).unwrap();
+        storage.add_or_update_device(device("A4:C1:38:00:00:02"));
+        storage.add_or_update_device(device("A4:C1:38:00:00:04"));
+        storage.add_or_update_device(device("A4:C1:38:00:00:01"));
+        assert_eq!(storage.find_device("A4:C1:38:00:00:01"), Some(first));
+        assert_eq!(storage.find_device("A4:C1:38:00:00:02"), Some(second));
+        assert_eq!(storage.find_device("A4:C1:38:00:00:04"), Some(forgotten + 1));
+        assert_eq!(storage.list_devices().len(), 3);
+    }
+
+    #[test]
+    fn missing_inventory_starts_empty() {
+        let path = temp_dir("missing").join("devices.json");
+        let storage = DeviceStorage::load(&path).unwrap();
+        assert!(storage.list_devices().is_empty());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn corrupt_inventory_is_not_overwritten() {
+        let path = temp_dir("corrupt").join("devices.json");
+        std::fs::write(&path, "{\"devices\": {").unwrap();
+        let error = DeviceStorage::load(&path).err().unwrap();
+        assert!(error.to_string().starts_with("Invalid device inventory"), "{}", error);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"devices\": {");
+    }
+
+    #[test]
+    fn alias_and_location_metadata_become_labels() {
+        let path = temp_dir("labels").join("devices.json");
+        let mut storage = DeviceStorage::load(&path).unwrap();
+        storage.add_or_update_device(sighting(-60, "hci0"));
+        let other = BluetoothDevice::new("A4:C1:38:00:00:03".to_string(), "ATC_000003".to_string(), -60, "hci0", Arc::new(SimulatedPeripheral::new("A4:C1:38:00:00:03")));
+        storage.add_or_update_device(other);
+        let (id, other_id) = (storage.find_device(ADDRESS).unwrap(), storage.find_device("A4:C1:38:00:00:03").unwrap());
+        let device = storage.get_device_mut(id).unwrap();
+        device.metadata.insert("alias".to_string(), "kitchen".to_string());
+        device.metadata.insert("location".to_string(), "ground floor".to_string());
+        device.metadata.insert("owner".to_string(), "me".to_string());
+        // Clashes with the first alias, so it stays in the metadata
+        storage.get_device_mut(other_id).unwrap().metadata.insert("alias".to_string(), "Kitchen".to_string());
+        storage.save().unwrap();
+
+        let storage = DeviceStorage::load(&path).unwrap();
+        let device = storage.get_device(id).unwrap();
+        assert_eq!((device.alias.as_deref(), device.location.as_deref()), (Some("kitchen"), Some("ground floor")));
+        assert_eq!(device.metadata.keys().collect::<Vec<_>>(), ["owner"]);
+        assert_eq!(storage.find_device("kitchen"), Some(id));
+        let other = storage.get_device(other_id).unwrap();
+        assert_eq!(other.alias, None);
+        assert_eq!(other.metadata.get("alias").map(String::as_str), Some("Kitchen"));
     }
 }
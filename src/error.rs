@@ -0,0 +1,171 @@
+//! Errors of the Bluetooth operations in `bluetooth_manager` and `device_info`.
+
+use btleplug::api::WriteType;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum BluetoothError {
+    /// No adapter is available, or none matches the selection.
+    NoAdapter(String),
+    /// No device in the inventory matches the given ID, alias or MAC address.
+    DeviceNotFound(String),
+    /// The device is only known from the inventory file, it was not seen during this run.
+    NotSeen { mac: String },
+    ConnectFailed { mac: String, attempts: u32, source: btleplug::Error },
+    NotConnected { mac: String },
+    CharacteristicNotFound { mac: String, service: String, characteristic: String },
+    NotifyNotSupported { mac: String, characteristic: String },
+    WriteNotSupported { mac: String, characteristic: String, write_type: WriteType, properties: Vec<&'static str> },
+    Timeout { mac: String, operation: &'static str, source: btleplug::Error },
+    /// The operation did not finish within the time the caller gave it.
+    Elapsed { mac: String, operation: &'static str, timeout: Duration },
+    /// Any other failure of the Bluetooth stack. `mac` and `uuid` are set when the operation
+    /// concerned a device or characteristic.
+    Ble { operation: &'static str, mac: Option<String>, uuid: Option<String>, source: btleplug::Error },
+}
+
+impl BluetoothError {
+    /// Wraps a btleplug error of `operation` on a device, recognizing the failures callers
+    /// can act on.
+    pub fn ble(operation: &'static str, mac: &str, uuid: Option<&str>, source: btleplug::Error) -> Self {
+        match source {
+            btleplug::Error::NotConnected => BluetoothError::NotConnected { mac: mac.to_string() },
+            btleplug::Error::TimedOut(_) => BluetoothError::Timeout { mac: mac.to_string(), operation, source },
+            source => BluetoothError::Ble { operation, mac: Some(mac.to_string()), uuid: uuid.map(str::to_string), source },
+        }
+    }
+
+    /// Wraps a btleplug error of an adapter operation.
+    pub fn adapter(operation: &'static str, source: btleplug::Error) -> Self {
+        BluetoothError::Ble { operation, mac: None, uuid: None, source }
+    }
+
+    /// What the user can do about the error, if anything.
+    pub fn hint(&self) -> Option<String> {
+        let hint = match self {
+            BluetoothError::NoAdapter(_) => {
+                "Check the adapter is plugged in and powered on (e.g. `bluetoothctl power on`); `adapters` lists the available ones".to_string()
+            }
+            BluetoothError::DeviceNotFound(_) => "Run `list` to see the inventory, or scan to add the device".to_string(),
+            BluetoothError::NotSeen { .. } => "Scan first so the device is found during this run".to_string(),
+            BluetoothError::ConnectFailed { .. } => {
+                "Move closer to the device, make sure no phone app is connected to it, and check its battery".to_string()
+            }
+            BluetoothError::NotConnected { .. } => "The device dropped the connection; retry, if possible closer to it".to_string(),
+            BluetoothError::CharacteristicNotFound { .. } => {
+                "Run `discover` to list the services and characteristics the device offers".to_string()
+            }
+            BluetoothError::NotifyNotSupported { .. } => {
+                "Read the characteristic instead, or pick one with the notify property (see `discover`)".to_string()
+            }
+            BluetoothError::WriteNotSupported { write_type, properties, .. } => match write_type {
+                WriteType::WithResponse if properties.contains(&"write-without-response") => {
+                    "The characteristic accepts writes without response, retry with --without-response".to_string()
+                }
+                WriteType::WithoutResponse if properties.contains(&"write") => {
+                    "The characteristic only accepts writes with response, retry without --without-response".to_string()
+                }
+                _ => "The characteristic is not writable".to_string(),
+            },
+            BluetoothError::Timeout { .. } | BluetoothError::Elapsed { .. } => "The device did not answer in time; it may be asleep or out of range, retry".to_string(),
+            BluetoothError::Ble { source: btleplug::Error::PermissionDenied, .. } => {
+                "The process lacks permission to use Bluetooth; grant it, or run as a user in the bluetooth group".to_string()
+            }
+            BluetoothError::Ble { .. } => return None,
+        };
+        Some(hint)
+    }
+}
+
+impl fmt::Display for BluetoothError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BluetoothError::NoAdapter(reason) => write!(f, "{}", reason),
+            BluetoothError::DeviceNotFound(selector) => write!(f, "Device '{}' not found", selector),
+            BluetoothError::NotSeen { mac } => write!(f, "Device {} has not been seen since startup", mac),
+            BluetoothError::ConnectFailed { mac, attempts, source } => {
+                write!(f, "Failed to connect to device {} after {} attempts: {}", mac, attempts, source)
+            }
+            BluetoothError::NotConnected { mac } => write!(f, "Device {} is not connected", mac),
+            BluetoothError::CharacteristicNotFound { mac, service, characteristic } => {
+                write!(f, "Characteristic {} not found in service {} of device {}", characteristic, service, mac)
+            }
+            BluetoothError::NotifyNotSupported { mac, characteristic } => {
+                write!(f, "Characteristic {} of device {} does not support notifications", characteristic, mac)
+            }
+            BluetoothError::WriteNotSupported { mac, characteristic, write_type, properties } => write!(
+                f,
+                "Characteristic {} of device {} does not support {:?} writes (properties: {})",
+                characteristic, mac, write_type, properties.join(", ")
+            ),
+            BluetoothError::Timeout { mac, operation, source } => write!(f, "Timed out trying to {} on device {}: {}", operation, mac, source),
+            BluetoothError::Elapsed { mac, operation, timeout } => write!(f, "Gave up trying to {} on device {} after {:?}", operation, mac, timeout),
+            BluetoothError::Ble { operation, mac, uuid, source } => {
+                write!(f, "Failed to {}", operation)?;
+                if let Some(uuid) = uuid {
+                    write!(f, " {}", uuid)?;
+                }
+                if let Some(mac) = mac {
+                    write!(f, " on device {}", mac)?;
+                }
+                write!(f, ": {}", source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BluetoothError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BluetoothError::ConnectFailed { source, .. } | BluetoothError::Timeout { source, .. } | BluetoothError::Ble { source, .. } => {
+                Some(source)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: &str = "4C:65:A8:D0:00:01";
+
+    fn write_not_supported(write_type: WriteType, properties: Vec<&'static str>) -> BluetoothError {
+        BluetoothError::WriteNotSupported { mac: MAC.to_string(), characteristic: "2a00".to_string(), write_type, properties }
+    }
+
+    #[test]
+    fn ble_errors_are_recognized() {
+        assert!(matches!(
+            BluetoothError::ble("read", MAC, Some("2a19"), btleplug::Error::NotConnected),
+            BluetoothError::NotConnected { mac } if mac == MAC
+        ));
+        let timeout = BluetoothError::ble("read", MAC, Some("2a19"), btleplug::Error::TimedOut(Duration::from_secs(5)));
+        assert!(matches!(&timeout, BluetoothError::Timeout { mac, operation: "read", .. } if mac == MAC));
+        assert!(std::error::Error::source(&timeout).is_some());
+        let other = BluetoothError::ble("read", MAC, Some("2a19"), btleplug::Error::DeviceNotFound);
+        assert!(matches!(&other, BluetoothError::Ble { operation: "read", mac: Some(mac), uuid: Some(uuid), .. } if mac == MAC && uuid == "2a19"));
+        assert_eq!(other.to_string(), format!("Failed to read 2a19 on device {}: {}", MAC, btleplug::Error::DeviceNotFound));
+        assert_eq!(other.hint(), None);
+    }
+
+    #[test]
+    fn write_hints_suggest_the_other_write_type() {
+        let hint = write_not_supported(WriteType::WithResponse, vec!["read", "write-without-response"]).hint().unwrap();
+        assert!(hint.contains("retry with --without-response"), "{}", hint);
+        let hint = write_not_supported(WriteType::WithoutResponse, vec!["read", "write"]).hint().unwrap();
+        assert!(hint.contains("retry without --without-response"), "{}", hint);
+        let hint = write_not_supported(WriteType::WithResponse, vec!["read", "notify"]).hint().unwrap();
+        assert_eq!(hint, "The characteristic is not writable");
+    }
+
+    #[test]
+    fn permission_denied_has_a_hint() {
+        let hint = BluetoothError::adapter("start scanning", btleplug::Error::PermissionDenied).hint().unwrap();
+        assert!(hint.contains("bluetooth group"), "{}", hint);
+        let hint = BluetoothError::ble("connect", MAC, None, btleplug::Error::PermissionDenied).hint().unwrap();
+        assert!(hint.contains("permission"), "{}", hint);
+    }
+}
@@ -0,0 +1,373 @@
+//! Decoders for the values of standard GATT characteristics, so reads show typed values
+//! with units instead of raw bytes.
+//!
+//! Decoders are registered by 16-bit UUID. Other characteristics are decoded with their
+//! Presentation Format descriptor, if they have one. A value that is too short, or that
+//! holds the "unknown" marker of its characteristic, doesn't decode and is shown raw.
+
+use crate::assigned_numbers;
+use crate::descriptors::PresentationFormat;
+use crate::device_info::format_hex;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use std::fmt;
+use uuid::Uuid;
+
+/// A decoded characteristic value. Units are in the field names.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DecodedValue {
+    Text { text: String },
+    Boolean { value: bool },
+    /// A number decoded with a Presentation Format descriptor
+    Quantity {
+        value: f64,
+        /// Decimals given by the exponent, all of them when `None`
+        #[serde(skip)]
+        decimals: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        unit: Option<&'static str>,
+    },
+    BatteryLevel { percent: u8 },
+    Temperature { celsius: f64 },
+    Humidity { percent: f64 },
+    Pressure { pascal: f64 },
+    TxPowerLevel { dbm: i8 },
+    Appearance { value: u16, name: String },
+    ConnectionParameters {
+        /// `None` when the device has no preference
+        min_interval_ms: Option<f64>,
+        max_interval_ms: Option<f64>,
+        latency: Option<u16>,
+        supervision_timeout_ms: Option<u32>,
+    },
+    PnpId {
+        /// 1: Bluetooth SIG company ID, 2: USB Implementer's Forum vendor ID
+        vendor_id_source: u8,
+        vendor_id: u16,
+        product_id: u16,
+        product_version: u16,
+    },
+    SystemId { manufacturer_id: u64, organizationally_unique_id: u32 },
+    CurrentTime {
+        time: NaiveDateTime,
+        /// 1 is Monday, 7 Sunday; `None` when unknown
+        day_of_week: Option<u8>,
+        /// Bit field: manual update, external reference, time zone and DST change
+        adjust_reason: u8,
+    },
+}
+
+impl fmt::Display for DecodedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodedValue::Text { text } => write!(f, "{}", text),
+            DecodedValue::Boolean { value } => write!(f, "{}", value),
+            DecodedValue::Quantity { value, decimals, unit } => {
+                match decimals {
+                    Some(decimals) => write!(f, "{:.*}", decimals, value)?,
+                    None => write!(f, "{}", value)?,
+                }
+                match unit {
+                    Some(unit) => write!(f, " {}", unit),
+                    None => Ok(()),
+                }
+            }
+            DecodedValue::BatteryLevel { percent } => write!(f, "{} %", percent),
+            DecodedValue::Temperature { celsius } => write!(f, "{:.2} °C", celsius),
+            DecodedValue::Humidity { percent } => write!(f, "{:.2} %", percent),
+            DecodedValue::Pressure { pascal } => write!(f, "{:.1} hPa", pascal / 100.0),
+            DecodedValue::TxPowerLevel { dbm } => write!(f, "{} dBm", dbm),
+            DecodedValue::Appearance { value, name } => write!(f, "{} ({:#06x})", name, value),
+            DecodedValue::ConnectionParameters { min_interval_ms, max_interval_ms, latency, supervision_timeout_ms } => {
+                let any = |value: Option<String>| value.unwrap_or_else(|| "any".to_string());
+                write!(
+                    f,
+                    "interval {}-{} ms, latency {}, supervision timeout {} ms",
+                    any(min_interval_ms.map(|ms| format!("{:.2}", ms))),
+                    any(max_interval_ms.map(|ms| format!("{:.2}", ms))),
+                    any(latency.map(|latency| latency.to_string())),
+                    any(supervision_timeout_ms.map(|ms| ms.to_string())),
+                )
+            }
+            DecodedValue::PnpId { vendor_id_source, vendor_id, product_id, product_version } => {
+                let vendor = match (vendor_id_source, assigned_numbers::company_name(*vendor_id)) {
+                    (1, Some(name)) => format!("{} ({:#06x})", name, vendor_id),
+                    (2, _) => format!("USB {:#06x}", vendor_id),
+                    _ => format!("{:#06x}", vendor_id),
+                };
+                write!(f, "vendor {}, product {:#06x}, version {:#06x}", vendor, product_id, product_version)
+            }
+            DecodedValue::SystemId { manufacturer_id, organizationally_unique_id } => {
+                write!(f, "manufacturer {:#012x}, OUI {:06x}", manufacturer_id, organizationally_unique_id)
+            }
+            DecodedValue::CurrentTime { time, day_of_week, .. } => {
+                const DAYS: [&str; 7] = ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"];
+                write!(f, "{}", time.format("%Y-%m-%d %H:%M:%S%.3f"))?;
+                if let Some(day) = day_of_week {
+                    write!(f, " ({})", DAYS[*day as usize - 1])?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+type Decoder = fn(&[u8]) -> Option<DecodedValue>;
+
+/// Decoders by 16-bit characteristic UUID.
+const DECODERS: &[(u32, Decoder)] = &[
+    (0x2a00, text),
+    (0x2a01, appearance),
+    (0x2a04, connection_parameters),
+    (0x2a07, tx_power_level),
+    (0x2a19, battery_level),
+    (0x2a23, system_id),
+    (0x2a24, text),
+    (0x2a25, text),
+    (0x2a26, text),
+    (0x2a27, text),
+    (0x2a28, text),
+    (0x2a29, text),
+    (0x2a2b, current_time),
+    (0x2a50, pnp_id),
+    (0x2a6d, pressure),
+    (0x2a6e, temperature),
+    (0x2a6f, humidity),
+];
+
+/// Decodes the value of a standard characteristic, or with the characteristic's
+/// Presentation Format; `None` if neither applies or the value doesn't decode.
+pub fn decode(characteristic: &Uuid, format: Option<&PresentationFormat>, value: &[u8]) -> Option<DecodedValue> {
+    let standard = assigned_numbers::short_uuid(characteristic)
+        .and_then(|short| DECODERS.iter().find(|(number, _)| *number == short))
+        .and_then(|(_, decoder)| decoder(value));
+    standard.or_else(|| format?.decode(value))
+}
+
+/// The decoded value, or hex followed by the value as text for values that didn't decode.
+pub fn display(decoded: Option<&DecodedValue>, value: &[u8]) -> String {
+    match decoded {
+        Some(decoded) => decoded.to_string(),
+        None => format!("{} ({})", format_hex(value), String::from_utf8_lossy(value)),
+    }
+}
+
+fn u16_at(value: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(value.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn text(value: &[u8]) -> Option<DecodedValue> {
+    // Some devices pad strings with NULs
+    let text = String::from_utf8_lossy(value).trim_end_matches('\0').to_string();
+    Some(DecodedValue::Text { text })
+}
+
+fn appearance(value: &[u8]) -> Option<DecodedValue> {
+    let value = u16_at(value, 0)?;
+    Some(DecodedValue::Appearance { value, name: assigned_numbers::appearance_name(value) })
+}
+
+fn connection_parameters(value: &[u8]) -> Option<DecodedValue> {
+    // 0xffff in any field means no specific value
+    let field = |offset| u16_at(value, offset).map(|field| (field != 0xffff).then_some(field));
+    Some(DecodedValue::ConnectionParameters {
+        min_interval_ms: field(0)?.map(|units| units as f64 * 1.25),
+        max_interval_ms: field(2)?.map(|units| units as f64 * 1.25),
+        latency: field(4)?,
+        supervision_timeout_ms: field(6)?.map(|units| units as u32 * 10),
+    })
+}
+
+fn tx_power_level(value: &[u8]) -> Option<DecodedValue> {
+    Some(DecodedValue::TxPowerLevel { dbm: *value.first()? as i8 })
+}
+
+fn battery_level(value: &[u8]) -> Option<DecodedValue> {
+    let percent = *value.first()?;
+    (percent <= 100).then_some(DecodedValue::BatteryLevel { percent })
+}
+
+fn system_id(value: &[u8]) -> Option<DecodedValue> {
+    let bytes: [u8; 8] = value.get(..8)?.try_into().ok()?;
+    let id = u64::from_le_bytes(bytes);
+    Some(DecodedValue::SystemId { manufacturer_id: id & 0xff_ffff_ffff, organizationally_unique_id: (id >> 40) as u32 })
+}
+
+fn current_time(value: &[u8]) -> Option<DecodedValue> {
+    let year = u16_at(value, 0)?;
+    let [month, day, hours, minutes, seconds, day_of_week, fractions] = value.get(2..9)?.try_into().ok()?;
+    let milliseconds = fractions as u32 * 1000 / 256;
+    let time = NaiveDate::from_ymd_opt(year as i32, month as u32, day as u32)?.and_hms_milli_opt(hours as u32, minutes as u32, seconds as u32, milliseconds)?;
+    Some(DecodedValue::CurrentTime {
+        time,
+        day_of_week: (1..=7).contains(&day_of_week).then_some(day_of_week),
+        adjust_reason: value.get(9).copied().unwrap_or(0),
+    })
+}
+
+fn pnp_id(value: &[u8]) -> Option<DecodedValue> {
+    Some(DecodedValue::PnpId {
+        vendor_id_source: *value.first()?,
+        vendor_id: u16_at(value, 1)?,
+        product_id: u16_at(value, 3)?,
+        product_version: u16_at(value, 5)?,
+    })
+}
+
+fn pressure(value: &[u8]) -> Option<DecodedValue> {
+    let tenths = u32::from_le_bytes(value.get(..4)?.try_into().ok()?);
+    Some(DecodedValue::Pressure { pascal: tenths as f64 / 10.0 })
+}
+
+fn temperature(value: &[u8]) -> Option<DecodedValue> {
+    let hundredths = u16_at(value, 0)? as i16;
+    (hundredths != i16::MIN).then(|| DecodedValue::Temperature { celsius: hundredths as f64 / 100.0 })
+}
+
+fn humidity(value: &[u8]) -> Option<DecodedValue> {
+    let hundredths = u16_at(value, 0)?;
+    (hundredths != 0xffff).then(|| DecodedValue::Humidity { percent: hundredths as f64 / 100.0 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_short(characteristic: u32, value: &[u8]) -> Option<DecodedValue> {
+        decode(&assigned_numbers::uuid_from_short(characteristic), None, value)
+    }
+
+    fn time(date: (i32, u32, u32), (hours, minutes, seconds, milliseconds): (u32, u32, u32, u32)) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap().and_hms_milli_opt(hours, minutes, seconds, milliseconds).unwrap()
+    }
+
+    #[test]
+    fn text() {
+        let decoded = decode_short(0x2a00, b"MJ_HT_V1\0\0");
+        assert_eq!(decoded, Some(DecodedValue::Text { text: "MJ_HT_V1".to_string() }));
+        assert_eq!(decode_short(0x2a29, b""), Some(DecodedValue::Text { text: String::new() }));
+        assert_eq!(decode_short(0x2a26, &[0x31, 0xff]), Some(DecodedValue::Text { text: "1\u{fffd}".to_string() }));
+    }
+
+    #[test]
+    fn appearance() {
+        let decoded = decode_short(0x2a01, &[0x41, 0x03]).unwrap();
+        assert_eq!(decoded, DecodedValue::Appearance { value: 0x0341, name: "Heart Rate Belt".to_string() });
+        assert_eq!(decoded.to_string(), "Heart Rate Belt (0x0341)");
+        assert_eq!(decode_short(0x2a01, &[0x41]), None);
+    }
+
+    #[test]
+    fn connection_parameters() {
+        let decoded = decode_short(0x2a04, &[0x18, 0x00, 0x28, 0x00, 0x00, 0x00, 0xe8, 0x03]).unwrap();
+        assert_eq!(
+            decoded,
+            DecodedValue::ConnectionParameters {
+                min_interval_ms: Some(30.0),
+                max_interval_ms: Some(50.0),
+                latency: Some(0),
+                supervision_timeout_ms: Some(10000),
+            }
+        );
+        assert_eq!(decoded.to_string(), "interval 30.00-50.00 ms, latency 0, supervision timeout 10000 ms");
+
+        // 0xffff: no preference
+        let decoded = decode_short(0x2a04, &[0xff; 8]).unwrap();
+        assert_eq!(
+            decoded,
+            DecodedValue::ConnectionParameters { min_interval_ms: None, max_interval_ms: None, latency: None, supervision_timeout_ms: None }
+        );
+        assert_eq!(decoded.to_string(), "interval any-any ms, latency any, supervision timeout any ms");
+        assert_eq!(decode_short(0x2a04, &[0x18, 0x00, 0x28, 0x00, 0x00, 0x00, 0xe8]), None);
+    }
+
+    #[test]
+    fn tx_power_level() {
+        assert_eq!(decode_short(0x2a07, &[0xf4]), Some(DecodedValue::TxPowerLevel { dbm: -12 }));
+        assert_eq!(decode_short(0x2a07, &[0x04]).unwrap().to_string(), "4 dBm");
+        assert_eq!(decode_short(0x2a07, &[]), None);
+    }
+
+    #[test]
+    fn battery_level() {
+        assert_eq!(decode_short(0x2a19, &[87]).unwrap().to_string(), "87 %");
+        assert_eq!(decode_short(0x2a19, &[100]), Some(DecodedValue::BatteryLevel { percent: 100 }));
+        // Above 100 is reserved
+        assert_eq!(decode_short(0x2a19, &[101]), None);
+        assert_eq!(decode_short(0x2a19, &[0xff]), None);
+        assert_eq!(decode_short(0x2a19, &[]), None);
+    }
+
+    #[test]
+    fn system_id() {
+        // 40-bit manufacturer identifier, then the 24-bit OUI, both least significant byte first
+        let decoded = decode_short(0x2a23, &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]).unwrap();
+        assert_eq!(decoded, DecodedValue::SystemId { manufacturer_id: 0x05_0403_0201, organizationally_unique_id: 0x08_0706 });
+        assert_eq!(decoded.to_string(), "manufacturer 0x0504030201, OUI 080706");
+        assert_eq!(decode_short(0x2a23, &[0x01; 7]), None);
+    }
+
+    #[test]
+    fn current_time() {
+        // 2024-01-15 13:30:45, Monday, 128/256 s, manual update
+        let decoded = decode_short(0x2a2b, &[0xe8, 0x07, 1, 15, 13, 30, 45, 1, 128, 0x01]).unwrap();
+        assert_eq!(decoded, DecodedValue::CurrentTime { time: time((2024, 1, 15), (13, 30, 45, 500)), day_of_week: Some(1), adjust_reason: 0x01 });
+        assert_eq!(decoded.to_string(), "2024-01-15 13:30:45.500 (Monday)");
+
+        // Day of week 0 is unknown; the adjust reason may be left out
+        let decoded = decode_short(0x2a2b, &[0xe8, 0x07, 12, 31, 23, 59, 59, 0, 0]).unwrap();
+        assert_eq!(decoded, DecodedValue::CurrentTime { time: time((2024, 12, 31), (23, 59, 59, 0)), day_of_week: None, adjust_reason: 0 });
+        assert_eq!(decoded.to_string(), "2024-12-31 23:59:59.000");
+
+        // Month 13 and a too short value don't decode
+        assert_eq!(decode_short(0x2a2b, &[0xe8, 0x07, 13, 1, 0, 0, 0, 1, 0]), None);
+        assert_eq!(decode_short(0x2a2b, &[0xe8, 0x07, 1, 15, 13, 30, 45, 1]), None);
+    }
+
+    #[test]
+    fn pnp_id() {
+        let decoded = decode_short(0x2a50, &[0x01, 0x8f, 0x03, 0xaa, 0x01, 0x66, 0x00]).unwrap();
+        assert_eq!(decoded, DecodedValue::PnpId { vendor_id_source: 1, vendor_id: 0x038f, product_id: 0x01aa, product_version: 0x0066 });
+        assert_eq!(decoded.to_string(), "vendor Xiaomi Inc. (0x038f), product 0x01aa, version 0x0066");
+        let usb = decode_short(0x2a50, &[0x02, 0x6d, 0x04, 0x2b, 0xc5, 0x00, 0x01]).unwrap();
+        assert_eq!(usb.to_string(), "vendor USB 0x046d, product 0xc52b, version 0x0100");
+        assert_eq!(decode_short(0x2a50, &[0x01, 0x8f, 0x03, 0xaa, 0x01, 0x66]), None);
+    }
+
+    #[test]
+    fn pressure() {
+        // 101325.0 Pa in tenths
+        let decoded = decode_short(0x2a6d, &[0x02, 0x76, 0x0f, 0x00]).unwrap();
+        assert_eq!(decoded, DecodedValue::Pressure { pascal: 101325.0 });
+        assert_eq!(decoded.to_string(), "1013.2 hPa");
+        assert_eq!(decode_short(0x2a6d, &[0x02, 0x76, 0x0f]), None);
+    }
+
+    #[test]
+    fn temperature() {
+        assert_eq!(decode_short(0x2a6e, &[0x24, 0x09]), Some(DecodedValue::Temperature { celsius: 23.4 }));
+        assert_eq!(decode_short(0x2a6e, &[0x0c, 0xfe]).unwrap().to_string(), "-5.00 °C");
+        assert_eq!(decode_short(0x2a6e, &[0x01, 0x80]), Some(DecodedValue::Temperature { celsius: -327.67 }));
+        // i16::MIN means the value is unknown
+        assert_eq!(decode_short(0x2a6e, &[0x00, 0x80]), None);
+        assert_eq!(decode_short(0x2a6e, &[0x24]), None);
+    }
+
+    #[test]
+    fn humidity() {
+        assert_eq!(decode_short(0x2a6f, &[0xc8, 0x11]), Some(DecodedValue::Humidity { percent: 45.52 }));
+        assert_eq!(decode_short(0x2a6f, &[0x10, 0x27]).unwrap().to_string(), "100.00 %");
+        // 0xffff means the value is unknown
+        assert_eq!(decode_short(0x2a6f, &[0xff, 0xff]), None);
+        assert_eq!(decode_short(0x2a6f, &[0xc8]), None);
+    }
+
+    #[test]
+    fn undecoded_values_show_raw() {
+        let vendor = Uuid::from_u128(0x226ccc55_6476_4566_7562_66734470666d);
+        assert_eq!(decode(&vendor, None, &[0x24, 0x09]), None);
+        assert_eq!(display(None, b"hi"), "6869 (hi)");
+        assert_eq!(display(Some(&DecodedValue::Boolean { value: true }), b"\x01"), "true");
+    }
+}
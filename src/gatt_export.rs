@@ -0,0 +1,194 @@
+//! Structured dump of a device's GATT table, to archive device profiles and diff them
+//! across firmware revisions.
+//!
+//! Services, characteristics and descriptors are sorted by UUID so two dumps of the same
+//! firmware are identical. btleplug doesn't report which services include which, so
+//! included services only show up as services with `primary: false`.
+
+use crate::descriptors::PresentationFormat;
+use crate::gatt_decode::DecodedValue;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Yaml,
+}
+
+impl ExportFormat {
+    /// The format matching the file extension, `.yaml` / `.yml` or `.json`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(ExportFormat::Json),
+            "yaml" | "yml" => Some(ExportFormat::Yaml),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GattProfile {
+    pub mac_address: String,
+    pub name: String,
+    pub services: Vec<GattService>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GattService {
+    pub uuid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'static str>,
+    pub primary: bool,
+    pub characteristics: Vec<GattCharacteristic>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GattCharacteristic {
+    pub uuid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'static str>,
+    /// The Characteristic User Description
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub properties: Vec<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presentation_format: Option<PresentationFormat>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub descriptors: Vec<GattDescriptor>,
+    /// Only when values were read and the characteristic is readable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<GattValue>,
+    /// Why the value could not be read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GattDescriptor {
+    pub uuid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GattValue {
+    /// Value as lowercase hex
+    pub hex: String,
+    /// Value as text, when it is valid UTF-8
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub utf8: Option<String>,
+    /// Value of a standard characteristic, decoded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decoded: Option<DecodedValue>,
+}
+
+impl GattProfile {
+    /// Sorts services, characteristics and descriptors by UUID.
+    pub fn sort(&mut self) {
+        self.services.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+        for service in &mut self.services {
+            service.characteristics.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+            for characteristic in &mut service.characteristics {
+                characteristic.descriptors.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+            }
+        }
+    }
+
+    pub fn render(&self, format: ExportFormat) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(match format {
+            ExportFormat::Json => serde_json::to_string_pretty(self)? + "\n",
+            ExportFormat::Yaml => serde_yaml::to_string(self)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn characteristic(uuid: &str, descriptors: &[&str]) -> GattCharacteristic {
+        GattCharacteristic {
+            uuid: uuid.to_string(),
+            name: None,
+            label: None,
+            properties: vec!["read"],
+            presentation_format: None,
+            descriptors: descriptors.iter().map(|uuid| GattDescriptor { uuid: uuid.to_string(), name: None }).collect(),
+            value: None,
+            read_error: None,
+        }
+    }
+
+    fn profile() -> GattProfile {
+        let mut battery_level = characteristic("00002a19-0000-1000-8000-00805f9b34fb", &["00002902-0000-1000-8000-00805f9b34fb"]);
+        battery_level.name = Some("Battery Level");
+        battery_level.properties = vec!["read", "notify"];
+        battery_level.value = Some(GattValue { hex: "57".to_string(), utf8: Some("W".to_string()), decoded: Some(DecodedValue::BatteryLevel { percent: 87 }) });
+        let mut temperature = characteristic(
+            "226ccc55-6476-4566-7562-66734470666d",
+            &["00002904-0000-1000-8000-00805f9b34fb", "00002901-0000-1000-8000-00805f9b34fb"],
+        );
+        temperature.label = Some("Temperature".to_string());
+        temperature.presentation_format = Some(PresentationFormat { format: 0x0e, exponent: -2, unit: 0x272f, namespace: 1, description: 0 });
+        temperature.read_error = Some("Not permitted".to_string());
+        GattProfile {
+            mac_address: "4C:65:A8:D0:00:01".to_string(),
+            name: "MJ_HT_V1".to_string(),
+            services: vec![
+                GattService {
+                    uuid: "226c0000-6476-4566-7562-66734470666d".to_string(),
+                    name: None,
+                    primary: true,
+                    characteristics: vec![temperature, characteristic("226caa55-6476-4566-7562-66734470666d", &[])],
+                },
+                GattService {
+                    uuid: "0000180f-0000-1000-8000-00805f9b34fb".to_string(),
+                    name: Some("Battery Service"),
+                    primary: true,
+                    characteristics: vec![battery_level],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn sorts_by_uuid() {
+        let mut profile = profile();
+        profile.sort();
+        let services: Vec<&str> = profile.services.iter().map(|service| service.uuid.as_str()).collect();
+        assert_eq!(services, ["0000180f-0000-1000-8000-00805f9b34fb", "226c0000-6476-4566-7562-66734470666d"]);
+        let vendor = &profile.services[1];
+        assert_eq!(vendor.characteristics[0].uuid, "226caa55-6476-4566-7562-66734470666d");
+        let descriptors: Vec<&str> = vendor.characteristics[1].descriptors.iter().map(|descriptor| descriptor.uuid.as_str()).collect();
+        assert_eq!(descriptors, ["00002901-0000-1000-8000-00805f9b34fb", "00002904-0000-1000-8000-00805f9b34fb"]);
+    }
+
+    #[test]
+    fn json_and_yaml_hold_the_same_profile() {
+        let profile = profile();
+        let expected = serde_json::to_value(&profile).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&profile.render(ExportFormat::Json).unwrap()).unwrap();
+        let yaml: serde_json::Value = serde_yaml::from_str(&profile.render(ExportFormat::Yaml).unwrap()).unwrap();
+        assert_eq!(json, expected);
+        assert_eq!(yaml, expected);
+
+        let battery_level = &expected["services"][1]["characteristics"][0];
+        assert_eq!(battery_level["value"]["decoded"], serde_json::json!({ "type": "battery_level", "percent": 87 }));
+        assert_eq!(battery_level["properties"], serde_json::json!(["read", "notify"]));
+        let temperature = &expected["services"][0]["characteristics"][0];
+        assert_eq!(temperature["presentation_format"]["exponent"], -2);
+        assert!(temperature.get("value").is_none());
+        assert!(expected["services"][0]["characteristics"][1].get("descriptors").is_none());
+    }
+
+    #[test]
+    fn formats_follow_the_extension() {
+        assert_eq!(ExportFormat::from_path(Path::new("profile.json")), Some(ExportFormat::Json));
+        assert_eq!(ExportFormat::from_path(Path::new("profile.YML")), Some(ExportFormat::Yaml));
+        assert_eq!(ExportFormat::from_path(Path::new("profile.yaml")), Some(ExportFormat::Yaml));
+        assert_eq!(ExportFormat::from_path(Path::new("profile.txt")), None);
+        assert_eq!(ExportFormat::from_path(Path::new("profile")), None);
+    }
+}
@@ -1,21 +1,88 @@
+mod api;
+mod assigned_numbers;
+mod atc;
+mod backend;
+mod bthome;
 mod bluetooth_manager;
+mod cli;
+mod connection_manager;
+mod descriptors;
 mod device_storage;
 mod ui;
+mod value_format;
 mod device_info;
+mod error;
+mod gatt_decode;
+mod gatt_export;
+mod metrics;
+mod mibeacon;
+mod mj_ht_v1;
+mod mqtt;
+mod presence;
+mod rssi;
+mod scan_filter;
+mod sensor;
 
+use backend::simulated::SimulatedBackend;
 use bluetooth_manager::BluetoothManager;
+use clap::Parser;
+use cli::{BackendKind, Cli, Command};
 use device_storage::DeviceStorage;
+use error::BluetoothError;
+use gatt_export::ExportFormat;
+use presence::PresenceConfig;
 use ui::UserInterface;
+use std::time::Duration;
 use log::{info, debug, error};  // Import the logging macros
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() {
     env_logger::init();  // Initialize the logger
+    if let Err(e) = run(Cli::parse()).await {
+        eprintln!("Error: {}", e);
+        if let Some(hint) = e.downcast_ref::<BluetoothError>().and_then(BluetoothError::hint) {
+            eprintln!("Hint: {}", hint);
+        }
+        std::process::exit(1);
+    }
+}
+
+async fn run(mut cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
 
-    // Initialize Bluetooth Manager, Device Storage, and UI
-    info!("Initializing Bluetooth Manager, Device Storage, and UI...");
-    let bluetooth_manager = BluetoothManager::new().await?;
-    let mut device_storage = DeviceStorage::new();
+    info!("Loading Device Storage...");
+    let mut device_storage = DeviceStorage::load(&cli.storage)?;
+    device_storage.set_path_loss_exponent(cli.path_loss_exponent);
+    device_storage.set_presence_config(PresenceConfig {
+        absence_timeout: Duration::from_secs(cli.absence_timeout),
+        ..PresenceConfig::default()
+    });
+    let command = cli.take_command();
+
+    let result = if command.uses_bluetooth() {
+        info!("Initializing Bluetooth Manager...");
+        let filter = cli.filter.build()?;
+        let selection = cli.adapter_selection();
+        let bluetooth_manager = match cli.backend {
+            BackendKind::Simulated => BluetoothManager::with_backend(&SimulatedBackend::demo(), selection).await?,
+            BackendKind::Platform => BluetoothManager::new(selection).await?,
+        }
+        .with_filter(filter)
+        .with_idle_timeout(Duration::from_secs(cli.idle_timeout));
+        let result = match command {
+            Command::Shell => run_shell(&bluetooth_manager, &mut device_storage).await,
+            command => cli::run(command, &bluetooth_manager, &mut device_storage).await,
+        };
+        bluetooth_manager.close_connections().await;
+        result
+    } else {
+        cli::run_inventory(command, &mut device_storage)
+    };
+    device_storage.save()?;
+    result
+}
+
+/// Interactive numbered menu.
+async fn run_shell(bluetooth_manager: &BluetoothManager, device_storage: &mut DeviceStorage) -> Result<(), Box<dyn std::error::Error>> {
     let ui = UserInterface::new();
 
     info!("Starting the main application loop...");
@@ -30,89 +97,210 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let attempts = ui.get_scan_attempts();
                 let duration = ui.get_scan_duration();
                 info!("User requested a scan with {} attempt(s) and a duration of {} seconds", attempts, duration);
-                if let Err(e) = bluetooth_manager.scan(&mut device_storage, duration, attempts).await {
+                if let Err(e) = bluetooth_manager.scan(device_storage, duration, attempts).await {
                     error!("Failed to perform scan: {}", e);
+                    ui.display_hint(&e);
+                }
+                if let Err(e) = device_storage.save() {
+                    error!("Failed to save device inventory: {}", e);
                 }
             }
             2 => {
                 let max_devices = ui.get_max_devices_to_scan();
                 info!("User requested to scan for MJ_HT_V1 devices");
-                if let Err(e) = bluetooth_manager.scan_for_mj_ht_v1_devices(&mut device_storage, max_devices).await {
+                if let Err(e) = bluetooth_manager.scan_for_mj_ht_v1_devices(device_storage, max_devices).await {
                     error!("Failed to scan for MJ_HT_V1 devices: {}", e);
+                    ui.display_hint(&e);
+                }
+                if let Err(e) = device_storage.save() {
+                    error!("Failed to save device inventory: {}", e);
                 }
             }
             3 => {
                 info!("User requested to list devices");
-                ui.display_devices(&device_storage);
+                ui.display_devices(device_storage);
             }
             4 => {
                 info!("User requested to list MJ_HT_V1 devices");
-                ui.display_mj_ht_v1_devices(&device_storage);
+                ui.display_mj_ht_v1_devices(device_storage);
             }
             5 => {
-                let device_id = ui.get_device_id();
+                let Some(device_id) = ui.get_device_id(device_storage) else { continue };
                 info!("User requested to retrieve config information for device ID: {}", device_id);
-                if let Err(e) = bluetooth_manager.list_available_info(device_id, &device_storage).await {
+                if let Err(e) = bluetooth_manager.list_available_info(device_id, device_storage).await {
                     error!("Failed to retrieve available information: {}", e);
+                    ui.display_hint(&e);
                 }
             }
             6 => {
-                let device_id = ui.get_device_id();
+                let Some(device_id) = ui.get_device_id(device_storage) else { continue };
                 info!("User requested to retrieve detailed information for device ID: {}", device_id);
-                if let Err(e) = bluetooth_manager.retrieve_device_info(device_id, &device_storage).await {
+                if let Err(e) = bluetooth_manager.retrieve_device_info(device_id, device_storage).await {
                     error!("Failed to retrieve device information: {}", e);
+                    ui.display_hint(&e);
                 }
             }
             7 => {
-                let device_id = ui.get_device_id();
+                let Some(device_id) = ui.get_device_id(device_storage) else { continue };
                 info!("Get temperature and humidity data from MJ_HT_V1 sensor with device ID: {}", device_id);
-                if let Err(e) = bluetooth_manager.retrieve_temperature_and_humidity(device_id, &device_storage).await {
+                let mut enter = ui.spawn_wait_for_enter();
+                let stop = async { let _ = (&mut enter).await; };
+                if let Err(e) = bluetooth_manager.retrieve_temperature_and_humidity(device_id, device_storage, stop, |reading| ui.display_mj_ht_v1_reading(reading)).await {
                     error!("Failed to retrieve temperature and humidity: {}", e);
+                    ui.display_hint(&e);
                 } else {
                     info!("Successfully retrieved temperature and humidity.");
                 }
+                // Don't let the pending Enter swallow the next menu choice
+                if !enter.is_finished() {
+                    println!("Press Enter to return to the menu");
+                    let _ = enter.await;
+                }
             }
             8 => {
-                let device_id = ui.get_device_id();
+                let Some(device_id) = ui.get_device_id(device_storage) else { continue };
                 info!("Get all data from MJ_HT_V1 sensor with device ID: {}", device_id);
-                if let Err(e) = bluetooth_manager.read_mj_ht_v1_information(device_id, &device_storage).await {
+                if let Err(e) = bluetooth_manager.read_mj_ht_v1_information(device_id, device_storage).await {
                     error!("Failed to retrieve all data: {}", e);
+                    ui.display_hint(&e);
                 } else {
                     info!("Successfully retrieved all data.");
                 }
             }
             9 => {
-                let device_id = ui.get_device_id();
+                let Some(device_id) = ui.get_device_id(device_storage) else { continue };
                 info!("User requested to connect to device ID: {}", device_id);
-                if let Err(e) = bluetooth_manager.connect_device(device_id, &device_storage).await {
+                if let Err(e) = bluetooth_manager.connect_device(device_id, device_storage).await {
                     error!("Failed to connect to device: {}", e);
+                    ui.display_hint(&e);
                 }
             }
             10 => {
-                let device_id = ui.get_device_id();
+                let Some(device_id) = ui.get_device_id(device_storage) else { continue };
                 info!("User requested to disconnect from device ID: {}", device_id);
-                if let Err(e) = bluetooth_manager.disconnect_device(device_id, &device_storage).await {
+                if let Err(e) = bluetooth_manager.disconnect_device(device_id, device_storage).await {
                     error!("Failed to disconnect from device: {}", e);
+                    ui.display_hint(&e);
                 }
             }
             11 => {
-                let device_id = ui.get_device_id();
+                let Some(device_id) = ui.get_device_id(device_storage) else { continue };
                 info!("User requested to discover services from device ID: {}", device_id);
-                if let Err(e) = bluetooth_manager.discover_services(device_id, &device_storage).await {
+                if let Err(e) = bluetooth_manager.discover_services(device_id, device_storage).await {
                     error!("Failed to discover services: {}", e);
+                    ui.display_hint(&e);
                 }
             }
             12 => {
-                let device_id = ui.get_device_id();
+                let Some(device_id) = ui.get_device_id(device_storage) else { continue };
                 info!("User requested to read characteristic from device ID: {}", device_id);
-                if let Err(e) = bluetooth_manager.read_mj_ht_v1(device_id, &device_storage).await {
+                if let Err(e) = bluetooth_manager.read_mj_ht_v1(device_id, device_storage).await {
                     error!("Failed to read sensor: {}", e);
+                    ui.display_hint(&e);
+                }
+            }
+            13 => {
+                info!("User requested to list sensor readings");
+                ui.display_sensor_devices(device_storage);
+            }
+            14 => {
+                info!("User requested to list thermometers");
+                ui.display_thermometer_devices(device_storage);
+            }
+            15 => {
+                let Some(device_id) = ui.get_device_id(device_storage) else { continue };
+                info!("User requested to write a characteristic of device ID: {}", device_id);
+                let request = ui.get_service_uuid().and_then(|service| {
+                    Ok((service, ui.get_characteristic_uuid()?, ui.get_value_format()?, ui.get_value()?, ui.get_write_type()?))
+                });
+                match request {
+                    Ok((service_uuid, characteristic_uuid, format, value, write_type)) => match format.encode(&value) {
+                        Ok(value) => {
+                            if let Err(e) = bluetooth_manager
+                                .write_characteristic(device_id, device_storage, &service_uuid, &characteristic_uuid, &value, write_type)
+                                .await
+                            {
+                                error!("Failed to write characteristic: {}", e);
+                                ui.display_hint(&e);
+                            }
+                        }
+                        Err(e) => error!("{}", e),
+                    },
+                    Err(e) => error!("Failed to read input: {}", e),
+                }
+            }
+            16 => {
+                let Some(device_id) = ui.get_device_id(device_storage) else { continue };
+                info!("User requested to label device ID: {}", device_id);
+                match ui.get_labels() {
+                    Ok(labels) => {
+                        if let Err(e) = device_storage.label_device(device_id, labels.alias, labels.location, labels.tags) {
+                            error!("Failed to label device: {}", e);
+                        }
+                    }
+                    Err(e) => error!("Failed to read input: {}", e),
                 }
             }
+            17 => {
+                info!("User requested to list Bluetooth adapters");
+                ui.display_adapters(bluetooth_manager.adapters());
+            }
+            18 => {
+                info!("User requested to list connections");
+                ui.display_connections(&bluetooth_manager.connections());
+            }
+            19 => {
+                let max_connections = ui.get_max_connections();
+                info!("User requested to read all MJ_HT_V1 sensors, {} at a time", max_connections);
+                let device_ids: Vec<u32> = device_storage.list_mj_ht_v1_devices().into_iter().map(|(id, _)| id).collect();
+                let results = bluetooth_manager.poll_mj_ht_v1(&device_ids, device_storage, max_connections, cli::DEFAULT_POLL_TIMEOUT).await;
+                ui.display_poll_results(&results);
+            }
             20 => {
                 info!("User selected exit. Terminating the application...");
                 break;
             }
+            21 => {
+                info!("User requested to watch arrivals and departures");
+                let mut enter = ui.spawn_wait_for_enter();
+                let stop = async { let _ = (&mut enter).await; };
+                if let Err(e) = bluetooth_manager.watch_presence(device_storage, stop, |event| ui.display_presence_event(event)).await {
+                    error!("Failed to watch arrivals and departures: {}", e);
+                    ui.display_hint(&e);
+                }
+                if !enter.is_finished() {
+                    println!("Press Enter to return to the menu");
+                    let _ = enter.await;
+                }
+                if let Err(e) = device_storage.save() {
+                    error!("Failed to save device inventory: {}", e);
+                }
+            }
+            22 => {
+                let Some(device_id) = ui.get_device_id(device_storage) else { continue };
+                info!("User requested to export the GATT table of device ID: {}", device_id);
+                let (path, read_values) = match ui.get_export_path().and_then(|path| Ok((path, ui.get_read_values()?))) {
+                    Ok(request) => request,
+                    Err(e) => {
+                        error!("Failed to read input: {}", e);
+                        continue;
+                    }
+                };
+                let Some(format) = ExportFormat::from_path(&path) else {
+                    println!("Unknown file type of '{}', use .json, .yaml or .yml", path.display());
+                    continue;
+                };
+                match bluetooth_manager.export_gatt(device_id, device_storage, read_values).await {
+                    Ok(profile) => match cli::write_export(&profile, format, Some(&path)) {
+                        Ok(()) => println!("GATT table written to {}", path.display()),
+                        Err(e) => error!("Failed to write {}: {}", path.display(), e),
+                    },
+                    Err(e) => {
+                        error!("Failed to export the GATT table: {}", e);
+                        ui.display_hint(&e);
+                    }
+                }
+            }
             _ => {
                 debug!("User selected an invalid option.");
                 println!("Invalid option. Please try again.");
@@ -0,0 +1,147 @@
+//! Prometheus metrics: per-device gauges rendered from the inventory and process-wide
+//! counters, served as text on `/metrics`.
+
+use crate::device_info::BluetoothDevice;
+use crate::device_storage::DeviceStorage;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use chrono::Utc;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+static SCANS: AtomicU64 = AtomicU64::new(0);
+static CONNECTION_ATTEMPTS: AtomicU64 = AtomicU64::new(0);
+static CONNECTION_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Counts a pass collecting the discovered peripherals into the inventory.
+pub fn record_scan() {
+    SCANS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_connection_attempt() {
+    CONNECTION_ATTEMPTS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_connection_failure() {
+    CONNECTION_FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Router serving `/metrics` from the shared inventory.
+pub fn router(storage: Arc<Mutex<DeviceStorage>>) -> Router {
+    Router::new().route("/metrics", get(metrics)).with_state(storage)
+}
+
+async fn metrics(State(storage): State<Arc<Mutex<DeviceStorage>>>) -> impl IntoResponse {
+    let body = render(&*storage.lock().await);
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], body)
+}
+
+/// Renders all metrics in the Prometheus text exposition format.
+pub fn render(storage: &DeviceStorage) -> String {
+    let mut out = String::new();
+    let devices: Vec<&BluetoothDevice> = storage.list_devices().into_iter().map(|(_, device)| device).collect();
+    let now = Utc::now();
+
+    gauge(&mut out, "bluetooth_device_temperature_celsius", "Temperature broadcast or notified by the device.", &devices, |d| {
+        d.sensor.as_ref()?.temperature.map(|v| v.to_string())
+    });
+    gauge(&mut out, "bluetooth_device_humidity_percent", "Relative humidity broadcast or notified by the device.", &devices, |d| {
+        d.sensor.as_ref()?.humidity.map(|v| v.to_string())
+    });
+    gauge(&mut out, "bluetooth_device_battery_percent", "Battery level broadcast by the device.", &devices, |d| {
+        d.sensor.as_ref()?.battery.map(|v| v.to_string())
+    });
+    gauge(&mut out, "bluetooth_device_rssi_dbm", "Signal strength of the last advertisement.", &devices, |d| {
+        Some(d.rssi.to_string())
+    });
+    gauge(&mut out, "bluetooth_device_last_seen_seconds", "Seconds since the device was last seen.", &devices, |d| {
+        Some(((now - d.last_seen).num_milliseconds() as f64 / 1000.0).to_string())
+    });
+
+    counter(&mut out, "bluetooth_scans_total", "Passes collecting discovered devices into the inventory.", &SCANS);
+    counter(&mut out, "bluetooth_connection_attempts_total", "Attempts to connect to a device.", &CONNECTION_ATTEMPTS);
+    counter(&mut out, "bluetooth_connection_failures_total", "Failed attempts to connect to a device.", &CONNECTION_FAILURES);
+    out
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, devices: &[&BluetoothDevice], value: impl Fn(&BluetoothDevice) -> Option<String>) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    for device in devices {
+        if let Some(value) = value(device) {
+            let _ = writeln!(
+                out,
+                "{}{{mac=\"{}\",name=\"{}\",alias=\"{}\"}} {}",
+                name,
+                escape(&device.mac_address),
+                escape(&device.name),
+                escape(device.alias.as_deref().unwrap_or_default()),
+                value
+            );
+        }
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::simulated::SimulatedPeripheral;
+    use crate::sensor::{Measurement, ReadingSource};
+
+    fn sensor(mac_address: &str, name: &str) -> BluetoothDevice {
+        let mut device = BluetoothDevice::new(mac_address.to_string(), name.to_string(), -61, "hci0", Arc::new(SimulatedPeripheral::new(mac_address)));
+        device.apply_measurements(ReadingSource::MiBeacon, &[Measurement::Temperature(23.4), Measurement::Humidity(45.5)]);
+        device
+    }
+
+    #[test]
+    fn renders_gauges_and_counters() {
+        let mut storage = DeviceStorage::new();
+        storage.add_or_update_device(sensor("4C:65:A8:D0:00:01", "MJ_HT_V1"));
+        storage.add_or_update_device(sensor("4C:65:A8:D0:00:02", "Desk \"big\" \\ small"));
+        let id = storage.find_device("4C:65:A8:D0:00:02").unwrap();
+        storage.label_device(id, Some("Kitchen".to_string()), None, Vec::new()).unwrap();
+
+        let rendered = render(&storage);
+        let lines: Vec<&str> = rendered.lines().collect();
+        for expected in [
+            "# HELP bluetooth_device_temperature_celsius Temperature broadcast or notified by the device.",
+            "# TYPE bluetooth_device_temperature_celsius gauge",
+            r#"bluetooth_device_temperature_celsius{mac="4C:65:A8:D0:00:01",name="MJ_HT_V1",alias=""} 23.4"#,
+            r#"bluetooth_device_temperature_celsius{mac="4C:65:A8:D0:00:02",name="Desk \"big\" \\ small",alias="Kitchen"} 23.4"#,
+            r#"bluetooth_device_humidity_percent{mac="4C:65:A8:D0:00:01",name="MJ_HT_V1",alias=""} 45.5"#,
+            "# TYPE bluetooth_device_battery_percent gauge",
+            r#"bluetooth_device_rssi_dbm{mac="4C:65:A8:D0:00:01",name="MJ_HT_V1",alias=""} -61"#,
+            "# HELP bluetooth_scans_total Passes collecting discovered devices into the inventory.",
+            "# TYPE bluetooth_scans_total counter",
+            "# TYPE bluetooth_connection_failures_total counter",
+        ] {
+            assert!(lines.contains(&expected), "{} missing from\n{}", expected, rendered);
+        }
+        // No battery level was broadcast
+        assert!(!lines.iter().any(|line| line.starts_with("bluetooth_device_battery_percent{")));
+        assert_eq!(lines.iter().filter(|line| line.starts_with("bluetooth_device_last_seen_seconds{")).count(), 2);
+        // Other tests count too, so only the shape of the counters is fixed
+        for counter in ["bluetooth_scans_total", "bluetooth_connection_attempts_total", "bluetooth_connection_failures_total"] {
+            let line = lines.iter().find(|line| line.starts_with(&format!("{} ", counter))).unwrap();
+            assert!(line[counter.len() + 1..].parse::<u64>().is_ok(), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,448 @@
+//! Xiaomi MiBeacon advertisements (service data for UUID 0xFE95).
+//!
+//! Frame layout: frame control (u16), product ID (u16), frame counter (u8), then the
+//! optional MAC (6 bytes, reversed), capability byte(s) and objects, as announced by the
+//! frame control bits. Every object is `id (u16) | length (u8) | data`.
+//!
+//! Version 4 and 5 frames may encrypt the objects with AES-CCM (4 byte MIC) using the
+//! device's 16 byte bind key. Such frames end with a 3 byte extension of the frame
+//! counter followed by the MIC, and the nonce is `MAC | product ID | frame counter |
+//! counter extension`.
+
+use crate::sensor::Measurement;
+use aes::Aes128;
+use ccm::aead::generic_array::GenericArray;
+use ccm::aead::{Aead, KeyInit, Payload};
+use ccm::consts::{U12, U4};
+use ccm::Ccm;
+use log::debug;
+use std::fmt;
+use uuid::Uuid;
+
+pub const SERVICE_UUID: Uuid = Uuid::from_u128(0x0000fe95_0000_1000_8000_00805f9b34fb);
+
+const FLAG_ENCRYPTED: u16 = 0x0008;
+const FLAG_MAC_INCLUDED: u16 = 0x0010;
+const FLAG_CAPABILITY_INCLUDED: u16 = 0x0020;
+const FLAG_OBJECT_INCLUDED: u16 = 0x0040;
+const CAPABILITY_IO: u8 = 0x20;
+
+const COUNTER_EXTENSION_LENGTH: usize = 3;
+const MIC_LENGTH: usize = 4;
+const ASSOCIATED_DATA: [u8; 1] = [0x11];
+/// Encrypted frames this far behind the last accepted packet ID are taken as a restart of
+/// the device (battery swap, reboot), whose counter starts over, rather than a replay.
+const RESTART_GAP: u32 = 256;
+
+type MiBeaconCcm = Ccm<Aes128, U4, U12>;
+
+const OBJECT_TEMPERATURE: u16 = 0x1004;
+const OBJECT_HUMIDITY: u16 = 0x1006;
+const OBJECT_BATTERY: u16 = 0x100a;
+const OBJECT_TEMPERATURE_HUMIDITY: u16 = 0x100d;
+/// Float variants sent by newer sensors such as the XMWSDJ04MMC.
+const OBJECT_TEMPERATURE_FLOAT: u16 = 0x4c01;
+const OBJECT_HUMIDITY_FLOAT: u16 = 0x4c08;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MiBeaconFrame {
+    pub version: u8,
+    pub product_id: u16,
+    pub frame_counter: u8,
+    /// Whether the objects were encrypted (and authenticated) with the bind key.
+    pub encrypted: bool,
+    /// Frame counter including the 3 byte extension of encrypted frames, used to detect replays.
+    pub packet_id: u32,
+    /// MAC address announced in the frame, formatted like `A4:C1:38:00:00:01`.
+    pub mac_address: Option<String>,
+    pub measurements: Vec<Measurement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MiBeaconError {
+    /// The frame ends before the part announced by its header.
+    Truncated,
+    /// The payload is encrypted and no bind key is registered for the device.
+    Encrypted,
+    /// Encrypted frames of this version (before v4) are not supported.
+    UnsupportedEncryption(u8),
+    /// The MIC does not match: wrong bind key or tampered frame.
+    AuthenticationFailed,
+    /// The frame carries plaintext objects although the device has a bind key, as a spoofed
+    /// frame would.
+    NotEncrypted,
+    /// The MAC address is needed for the nonce but is not in the frame nor known.
+    MissingMacAddress,
+}
+
+impl fmt::Display for MiBeaconError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MiBeaconError::Truncated => write!(f, "MiBeacon frame is truncated"),
+            MiBeaconError::Encrypted => write!(f, "MiBeacon frame is encrypted and no bind key is registered"),
+            MiBeaconError::UnsupportedEncryption(version) => write!(f, "Encrypted MiBeacon v{} frames are not supported", version),
+            MiBeaconError::AuthenticationFailed => write!(f, "MiBeacon frame failed authentication (wrong bind key?)"),
+            MiBeaconError::NotEncrypted => write!(f, "MiBeacon frame is not encrypted although a bind key is registered"),
+            MiBeaconError::MissingMacAddress => write!(f, "MiBeacon frame has no MAC address to build the nonce"),
+        }
+    }
+}
+
+impl std::error::Error for MiBeaconError {}
+
+/// Decodes a MiBeacon frame. Unknown objects are skipped.
+///
+/// `mac_address` is the address the frame was received from, used for the nonce when the
+/// frame does not carry one. Encrypted frames need the device's `bind_key`; once it is
+/// given, frames with plaintext objects are rejected.
+pub fn parse(data: &[u8], mac_address: &str, bind_key: Option<&[u8; 16]>) -> Result<MiBeaconFrame, MiBeaconError> {
+    let mut reader = Reader::new(data);
+    let frame_control = reader.u16()?;
+    let product_id = reader.u16()?;
+    let frame_counter = reader.u8()?;
+    let version = (frame_control >> 12) as u8;
+    let encrypted = frame_control & FLAG_ENCRYPTED != 0;
+
+    let reversed_mac = if frame_control & FLAG_MAC_INCLUDED != 0 {
+        Some(reader.take(6)?)
+    } else {
+        None
+    };
+
+    if frame_control & FLAG_CAPABILITY_INCLUDED != 0 {
+        let capability = reader.u8()?;
+        if capability & CAPABILITY_IO != 0 {
+            reader.take(2)?;
+        }
+    }
+
+    let mut measurements = Vec::new();
+    let mut packet_id = frame_counter as u32;
+    if frame_control & FLAG_OBJECT_INCLUDED != 0 {
+        if encrypted {
+            if version < 4 {
+                return Err(MiBeaconError::UnsupportedEncryption(version));
+            }
+            let bind_key = bind_key.ok_or(MiBeaconError::Encrypted)?;
+            let mac = match reversed_mac {
+                Some(mac) => mac.to_vec(),
+                None => reverse_mac(mac_address).ok_or(MiBeaconError::MissingMacAddress)?,
+            };
+
+            let payload = reader.rest();
+            if payload.len() < COUNTER_EXTENSION_LENGTH + MIC_LENGTH {
+                return Err(MiBeaconError::Truncated);
+            }
+            let (ciphertext, trailer) = payload.split_at(payload.len() - COUNTER_EXTENSION_LENGTH - MIC_LENGTH);
+            let (counter_extension, mic) = trailer.split_at(COUNTER_EXTENSION_LENGTH);
+
+            let mut nonce = Vec::with_capacity(12);
+            nonce.extend_from_slice(&mac);
+            nonce.extend_from_slice(&data[2..5]);
+            nonce.extend_from_slice(counter_extension);
+
+            let plaintext = decrypt(bind_key, &nonce, ciphertext, mic)?;
+            measurements = parse_objects(&plaintext)?;
+            packet_id = u32::from_le_bytes([frame_counter, counter_extension[0], counter_extension[1], counter_extension[2]]);
+        } else if bind_key.is_some() {
+            return Err(MiBeaconError::NotEncrypted);
+        } else {
+            measurements = parse_objects(reader.rest())?;
+        }
+    }
+
+    Ok(MiBeaconFrame {
+        version,
+        product_id,
+        frame_counter,
+        encrypted,
+        packet_id,
+        mac_address: reversed_mac.map(format_mac),
+        measurements,
+    })
+}
+
+impl MiBeaconFrame {
+    /// Whether the frame is newer than the last accepted packet ID of the device. Only
+    /// encrypted frames have a counter wide enough to reject replays on.
+    pub fn is_newer_than(&self, last_packet_id: Option<u32>) -> bool {
+        match last_packet_id {
+            Some(last) if self.encrypted => self.packet_id > last,
+            _ => true,
+        }
+    }
+
+    /// Whether the frame's packet ID is so far behind the last accepted one that the device
+    /// must have restarted its counter.
+    pub fn is_restart_after(&self, last_packet_id: Option<u32>) -> bool {
+        match last_packet_id {
+            Some(last) if self.encrypted => last.saturating_sub(self.packet_id) > RESTART_GAP,
+            _ => false,
+        }
+    }
+}
+
+/// Parses a bind key given as 32 hex digits.
+pub fn parse_bind_key(key: &str) -> Result<[u8; 16], String> {
+    let bytes = hex::decode(key.trim()).map_err(|e| format!("Invalid bind key '{}': {}", key, e))?;
+    bytes
+        .try_into()
+        .map_err(|bytes: Vec<u8>| format!("Bind key must be 16 bytes, got {}", bytes.len()))
+}
+
+fn decrypt(bind_key: &[u8; 16], nonce: &[u8], ciphertext: &[u8], mic: &[u8]) -> Result<Vec<u8>, MiBeaconError> {
+    let cipher = MiBeaconCcm::new(GenericArray::from_slice(bind_key));
+    let mut message = ciphertext.to_vec();
+    message.extend_from_slice(mic);
+    cipher
+        .decrypt(GenericArray::from_slice(nonce), Payload { msg: &message, aad: &ASSOCIATED_DATA })
+        .map_err(|_| MiBeaconError::AuthenticationFailed)
+}
+
+fn parse_objects(data: &[u8]) -> Result<Vec<Measurement>, MiBeaconError> {
+    let mut reader = Reader::new(data);
+    let mut measurements = Vec::new();
+
+    while !reader.is_empty() {
+        let id = reader.u16()?;
+        let length = reader.u8()? as usize;
+        let value = reader.take(length)?;
+
+        match (id, value) {
+            (OBJECT_TEMPERATURE, [a, b]) => {
+                measurements.push(Measurement::Temperature(i16::from_le_bytes([*a, *b]) as f32 / 10.0));
+            }
+            (OBJECT_HUMIDITY, [a, b]) => {
+                measurements.push(Measurement::Humidity(u16::from_le_bytes([*a, *b]) as f32 / 10.0));
+            }
+            (OBJECT_BATTERY, [level]) => {
+                measurements.push(Measurement::Battery(*level));
+            }
+            (OBJECT_TEMPERATURE_HUMIDITY, [a, b, c, d]) => {
+                measurements.push(Measurement::Temperature(i16::from_le_bytes([*a, *b]) as f32 / 10.0));
+                measurements.push(Measurement::Humidity(u16::from_le_bytes([*c, *d]) as f32 / 10.0));
+            }
+            (OBJECT_TEMPERATURE_FLOAT, [a, b, c, d]) => {
+                measurements.push(Measurement::Temperature(f32::from_le_bytes([*a, *b, *c, *d])));
+            }
+            (OBJECT_HUMIDITY_FLOAT, [a, b, c, d]) => {
+                measurements.push(Measurement::Humidity(f32::from_le_bytes([*a, *b, *c, *d])));
+            }
+            _ => debug!("Skipping MiBeacon object {:#06x} ({} bytes)", id, length),
+        }
+    }
+
+    Ok(measurements)
+}
+
+/// MiBeacon carries the MAC address least significant byte first.
+fn format_mac(reversed: &[u8]) -> String {
+    reversed.iter().rev().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(":")
+}
+
+/// Turns `A4:C1:38:12:34:56` into the byte order used on air.
+fn reverse_mac(mac_address: &str) -> Option<Vec<u8>> {
+    let bytes = mac_address
+        .split(':')
+        .map(|part| u8::from_str_radix(part, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    (bytes.len() == 6).then(|| bytes.into_iter().rev().collect())
+}
+
+/// Little-endian cursor over a frame.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], MiBeaconError> {
+        if self.data.len() < count {
+            return Err(MiBeaconError::Truncated);
+        }
+        let (head, tail) = self.data.split_at(count);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, MiBeaconError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, MiBeaconError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // XMWSDJ04MMC v5 frame with its bind key and MAC, as published in the xiaomi-ble test suite
+    const CAPTURED_BIND_KEY: &str = "b2cf9a553d53571b5657defd582d676e";
+    const CAPTURED_MAC: &str = "2C:11:65:25:70:04";
+    const CAPTURED_FRAME: &str = "48590312a41b776e7c96add7000000f2bf545b";
+    // Frames encrypted here with BIND_KEY and packet ID 0x0000012a / 0x0000012b, only used
+    // for the bad MIC and replay cases
+    const BIND_KEY: &str = "b853075158487ca39a5b5ea9ffaa6d27";
+    const MAC: &str = "A4:C1:38:12:34:56";
+    const TEMPERATURE_HUMIDITY_FRAME: &str = "58585b052a56341238c1a4dd552f38b1c967010000fc1f9b41";
+    const BATTERY_FRAME: &str = "58585b052b56341238c1a4373704d3010000eda8dadc";
+
+    fn frame(hex_frame: &str) -> Vec<u8> {
+        hex::decode(hex_frame).unwrap()
+    }
+
+    #[test]
+    fn parses_plain_temperature_humidity_frame() {
+        let data = [0x50, 0x20, 0xaa, 0x01, 0x17, 0x01, 0x00, 0xd0, 0xa8, 0x65, 0x4c, 0x0d, 0x10, 0x04, 0xea, 0x00, 0xc8, 0x01];
+        let frame = parse(&data, "4C:65:A8:D0:00:01", None).unwrap();
+        assert_eq!(frame.version, 2);
+        assert_eq!(frame.product_id, 0x01aa);
+        assert!(!frame.encrypted);
+        assert_eq!(frame.mac_address.as_deref(), Some("4C:65:A8:D0:00:01"));
+        assert_eq!(frame.measurements, vec![Measurement::Temperature(23.4), Measurement::Humidity(45.6)]);
+    }
+
+    /// A plain v2 frame of product 0x01aa with frame counter 0x17, followed by `rest`.
+    fn plain_frame(flags: u16, rest: &[u8]) -> Vec<u8> {
+        let mut data = (0x2000 | flags).to_le_bytes().to_vec();
+        data.extend_from_slice(&[0xaa, 0x01, 0x17]);
+        data.extend_from_slice(rest);
+        data
+    }
+
+    #[test]
+    fn parses_plain_frames() {
+        const MAC_BYTES: [u8; 6] = [0x01, 0x00, 0xd0, 0xa8, 0x65, 0x4c];
+        let with_mac = |objects: &[u8]| [&MAC_BYTES[..], objects].concat();
+        let cases: Vec<(&str, Vec<u8>, _)> = vec![
+            ("temperature", plain_frame(0x0050, &with_mac(&[0x04, 0x10, 0x02, 0xea, 0x00])), Ok(vec![Measurement::Temperature(23.4)])),
+            ("negative temperature", plain_frame(0x0050, &with_mac(&[0x04, 0x10, 0x02, 0xce, 0xff])), Ok(vec![Measurement::Temperature(-5.0)])),
+            ("humidity", plain_frame(0x0050, &with_mac(&[0x06, 0x10, 0x02, 0xc8, 0x01])), Ok(vec![Measurement::Humidity(45.6)])),
+            ("battery", plain_frame(0x0050, &with_mac(&[0x0a, 0x10, 0x01, 0x5d])), Ok(vec![Measurement::Battery(93)])),
+            (
+                "unknown object skipped",
+                plain_frame(0x0050, &with_mac(&[0x01, 0x10, 0x03, 0x01, 0x02, 0x03, 0x0a, 0x10, 0x01, 0x5d])),
+                Ok(vec![Measurement::Battery(93)]),
+            ),
+            (
+                "object of unexpected length skipped",
+                plain_frame(0x0050, &with_mac(&[0x0a, 0x10, 0x02, 0x5d, 0x00])),
+                Ok(vec![]),
+            ),
+            ("without MAC", plain_frame(0x0040, &[0x0a, 0x10, 0x01, 0x5d]), Ok(vec![Measurement::Battery(93)])),
+            (
+                "capability with IO bytes",
+                plain_frame(0x0060, &[0x20, 0x00, 0x00, 0x0a, 0x10, 0x01, 0x5d]),
+                Ok(vec![Measurement::Battery(93)]),
+            ),
+            ("capability only", plain_frame(0x0070, &with_mac(&[0x08])), Ok(vec![])),
+            ("no objects", plain_frame(0x0010, &MAC_BYTES), Ok(vec![])),
+            ("truncated object", plain_frame(0x0050, &with_mac(&[0x0d, 0x10, 0x04, 0xea, 0x00])), Err(MiBeaconError::Truncated)),
+            ("truncated MAC", plain_frame(0x0050, &MAC_BYTES[..4]), Err(MiBeaconError::Truncated)),
+            ("truncated header", vec![0x50, 0x20, 0xaa], Err(MiBeaconError::Truncated)),
+        ];
+        for (name, data, expected) in cases {
+            assert_eq!(parse(&data, "4C:65:A8:D0:00:01", None).map(|frame| frame.measurements), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn plain_frames_without_mac_take_none() {
+        let frame = parse(&plain_frame(0x0040, &[0x0a, 0x10, 0x01, 0x5d]), MAC, None).unwrap();
+        assert_eq!(frame.mac_address, None);
+        assert_eq!(frame.packet_id, 0x17);
+        assert!(frame.is_newer_than(Some(0xff)));
+    }
+
+    #[test]
+    fn decrypts_captured_v5_frame() {
+        let key = parse_bind_key(CAPTURED_BIND_KEY).unwrap();
+        let frame = parse(&frame(CAPTURED_FRAME), CAPTURED_MAC, Some(&key)).unwrap();
+        assert!(frame.encrypted);
+        assert_eq!(frame.version, 5);
+        assert_eq!(frame.product_id, 0x1203);
+        assert_eq!(frame.packet_id, 0x000000a4);
+        assert_eq!(frame.mac_address, None);
+        assert_eq!(frame.measurements, vec![Measurement::Humidity(45.0)]);
+    }
+
+    #[test]
+    fn captured_frame_needs_its_mac() {
+        let key = parse_bind_key(CAPTURED_BIND_KEY).unwrap();
+        // The MAC address is part of the nonce
+        assert_eq!(parse(&frame(CAPTURED_FRAME), MAC, Some(&key)), Err(MiBeaconError::AuthenticationFailed));
+        assert_eq!(parse(&frame(CAPTURED_FRAME), "not a MAC", Some(&key)), Err(MiBeaconError::MissingMacAddress));
+    }
+
+    #[test]
+    fn parses_float_objects() {
+        let data = plain_frame(0x0040, &[0x01, 0x4c, 0x04, 0x00, 0x00, 0xbc, 0x41, 0x08, 0x4c, 0x04, 0x00, 0x00, 0x34, 0x42]);
+        let frame = parse(&data, MAC, None).unwrap();
+        assert_eq!(frame.measurements, vec![Measurement::Temperature(23.5), Measurement::Humidity(45.0)]);
+    }
+
+    #[test]
+    fn plain_frame_with_key_is_rejected() {
+        let key = parse_bind_key(BIND_KEY).unwrap();
+        let data = plain_frame(0x0040, &[0x0a, 0x10, 0x01, 0x5d]);
+        assert_eq!(parse(&data, MAC, Some(&key)), Err(MiBeaconError::NotEncrypted));
+        // Frames without objects carry nothing to spoof
+        assert!(parse(&plain_frame(0x0000, &[]), MAC, Some(&key)).is_ok());
+    }
+
+    #[test]
+    fn bad_mic_is_rejected() {
+        let key = parse_bind_key(BIND_KEY).unwrap();
+        let mut data = frame(BATTERY_FRAME);
+        let last = data.len() - 1;
+        data[last] ^= 0x01;
+        assert_eq!(parse(&data, MAC, Some(&key)), Err(MiBeaconError::AuthenticationFailed));
+    }
+
+    #[test]
+    fn wrong_key_is_rejected() {
+        let key = parse_bind_key("00112233445566778899aabbccddeeff").unwrap();
+        assert_eq!(parse(&frame(CAPTURED_FRAME), CAPTURED_MAC, Some(&key)), Err(MiBeaconError::AuthenticationFailed));
+    }
+
+    #[test]
+    fn replayed_packet_ids_are_detected() {
+        let key = parse_bind_key(BIND_KEY).unwrap();
+        let frame = parse(&frame(TEMPERATURE_HUMIDITY_FRAME), MAC, Some(&key)).unwrap();
+        assert!(frame.is_newer_than(None));
+        assert!(frame.is_newer_than(Some(0x00000129)));
+        assert!(!frame.is_newer_than(Some(0x0000012a)));
+        assert!(!frame.is_newer_than(Some(0x0000012b)));
+        assert!(!frame.is_restart_after(Some(0x0000012b)));
+        assert!(!frame.is_restart_after(Some(0x0000022a)));
+        // A counter this far behind means the device started over
+        assert!(frame.is_restart_after(Some(0x0000022b)));
+        assert!(!frame.is_restart_after(None));
+    }
+
+    #[test]
+    fn plain_frames_never_restart() {
+        let frame = parse(&plain_frame(0x0040, &[0x0a, 0x10, 0x01, 0x5d]), MAC, None).unwrap();
+        assert!(!frame.is_restart_after(Some(0x00010000)));
+    }
+
+    #[test]
+    fn bind_key_must_be_16_bytes() {
+        assert!(parse_bind_key("b853075158487ca3").is_err());
+        assert!(parse_bind_key("not a key").is_err());
+    }
+}
@@ -0,0 +1,82 @@
+//! Decoding of the MJ_HT_V1 temperature and humidity notifications.
+//!
+//! The sensor notifies a NUL-terminated ASCII payload such as `T=23.4 H=45.6` on its
+//! `226caa55` characteristic.
+
+use crate::sensor::Measurement;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+pub const SERVICE_UUID: &str = "226c0000-6476-4566-7562-66734470666d";
+pub const TEMPERATURE_HUMIDITY_UUID: &str = "226caa55-6476-4566-7562-66734470666d";
+pub const HUMIDITY_UUID: &str = "226cbb55-6476-4566-7562-66734470666d";
+
+/// A single temperature and humidity measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MjHtV1Reading {
+    pub timestamp: DateTime<Utc>,
+    /// Degrees Celsius
+    pub temperature: f32,
+    /// Relative humidity in percent
+    pub humidity: f32,
+}
+
+impl MjHtV1Reading {
+    /// Decodes a notification payload, stamping it with the current time.
+    pub fn from_payload(value: &[u8]) -> Option<Self> {
+        let (temperature, humidity) = parse_payload(value)?;
+        Some(MjHtV1Reading { timestamp: Utc::now(), temperature, humidity })
+    }
+
+    pub fn measurements(&self) -> [Measurement; 2] {
+        [Measurement::Temperature(self.temperature), Measurement::Humidity(self.humidity)]
+    }
+}
+
+/// Parses a `T=23.4 H=45.6` payload into (temperature, humidity).
+pub fn parse_payload(value: &[u8]) -> Option<(f32, f32)> {
+    let text = std::str::from_utf8(value).ok()?.trim_end_matches('\0');
+    let mut temperature = None;
+    let mut humidity = None;
+
+    for field in text.split_whitespace() {
+        match field.split_once('=') {
+            Some(("T", number)) => temperature = number.parse().ok(),
+            Some(("H", number)) => humidity = number.parse().ok(),
+            _ => {}
+        }
+    }
+
+    Some((temperature?, humidity?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_payloads() {
+        let cases: Vec<(&[u8], _)> = vec![
+            (b"T=23.4 H=45.6\0", Some((23.4, 45.6))),
+            (b"T=23.4 H=45.6", Some((23.4, 45.6))),
+            (b"T=-5.2 H=99.9\0", Some((-5.2, 99.9))),
+            (b"H=45.6 T=23.4\0", Some((23.4, 45.6))),
+            (b"T=23.4  H=45.6\0\0", Some((23.4, 45.6))),
+            (b"T=23.4\0", None),
+            (b"H=45.6\0", None),
+            (b"T=abc H=45.6\0", None),
+            (b"T23.4 H45.6\0", None),
+            (b"", None),
+            (&[0xff, 0xfe, 0x00], None),
+        ];
+        for (payload, expected) in cases {
+            assert_eq!(parse_payload(payload), expected, "payload {:?}", String::from_utf8_lossy(payload));
+        }
+    }
+
+    #[test]
+    fn readings_carry_temperature_and_humidity() {
+        let reading = MjHtV1Reading::from_payload(b"T=23.4 H=45.6\0").unwrap();
+        assert_eq!(reading.measurements(), [Measurement::Temperature(23.4), Measurement::Humidity(45.6)]);
+    }
+}
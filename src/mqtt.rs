@@ -0,0 +1,265 @@
+//! Publishing of sensor readings to an MQTT broker, with Home Assistant discovery.
+//!
+//! For every device with sensor values the publisher sends, under the topic prefix:
+//! - `<prefix>/<id>/state`: JSON with the latest values, RSSI and last-seen time
+//! - `<prefix>/<id>/availability`: `online` or `offline`, retained, following last-seen
+//! - once per run, a retained discovery config per entity on
+//!   `<discovery prefix>/sensor/<prefix>_<id>/<entity>/config`
+//!
+//! `<id>` is the MAC address without separators, in lowercase.
+//!
+//! `<prefix>/availability` is `online` while the publisher is connected; the broker sets it
+//! to `offline` as the last will when the connection is lost. Entities are available when
+//! both the publisher and their device are.
+
+use crate::device_info::BluetoothDevice;
+use crate::device_storage::DeviceStorage;
+use chrono::Utc;
+use log::{debug, info, warn};
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, QoS};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Prefix of the state and availability topics.
+    pub topic_prefix: String,
+    /// Prefix Home Assistant listens on for discovery configs.
+    pub discovery_prefix: String,
+    /// Devices not seen for this long are reported offline.
+    pub availability_timeout: Duration,
+}
+
+impl MqttConfig {
+    fn topic(&self, id: &str, name: &str) -> String {
+        format!("{}/{}/{}", self.topic_prefix, id, name)
+    }
+
+    /// Availability of the publisher itself.
+    fn availability_topic(&self) -> String {
+        format!("{}/availability", self.topic_prefix)
+    }
+
+    fn discovery_topic(&self, id: &str, entity: &Entity) -> String {
+        format!("{}/sensor/{}_{}/{}/config", self.discovery_prefix, self.topic_prefix, id, entity.key)
+    }
+
+    fn discovery_config(&self, device: &BluetoothDevice, id: &str, entity: &Entity) -> serde_json::Value {
+        let mut config = json!({
+            "name": entity.name,
+            "unique_id": format!("{}_{}_{}", self.topic_prefix, id, entity.key),
+            "state_topic": self.topic(id, "state"),
+            "availability": [{ "topic": self.availability_topic() }, { "topic": self.topic(id, "availability") }],
+            "availability_mode": "all",
+            "value_template": format!("{{{{ value_json.{} }}}}", entity.key),
+            "device_class": entity.device_class,
+            "unit_of_measurement": entity.unit,
+            "state_class": "measurement",
+            "device": {
+                "identifiers": [format!("{}_{}", self.topic_prefix, id)],
+                "connections": [["mac", device.mac_address.to_lowercase()]],
+                "name": device.display_name(),
+                "model": device.name,
+            },
+        });
+        if entity.diagnostic {
+            config["entity_category"] = json!("diagnostic");
+        }
+        config
+    }
+}
+
+/// A message to publish, prepared while the inventory is locked and sent after.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    topic: String,
+    payload: String,
+    retain: bool,
+}
+
+/// A sensor entity announced to Home Assistant, read from `key` of the state JSON.
+struct Entity {
+    key: &'static str,
+    name: &'static str,
+    device_class: &'static str,
+    unit: &'static str,
+    diagnostic: bool,
+}
+
+const ENTITIES: [Entity; 4] = [
+    Entity { key: "temperature", name: "Temperature", device_class: "temperature", unit: "°C", diagnostic: false },
+    Entity { key: "humidity", name: "Humidity", device_class: "humidity", unit: "%", diagnostic: false },
+    Entity { key: "battery", name: "Battery", device_class: "battery", unit: "%", diagnostic: true },
+    Entity { key: "rssi", name: "RSSI", device_class: "signal_strength", unit: "dBm", diagnostic: true },
+];
+
+pub struct MqttPublisher {
+    client: AsyncClient,
+    config: MqttConfig,
+    /// Discovery configs sent during this run, as `<id>/<entity>`.
+    announced: HashSet<String>,
+    /// Availability last published per device ID.
+    availability: HashMap<String, bool>,
+}
+
+impl MqttPublisher {
+    /// Connects to the broker. The connection is driven by a background task that logs
+    /// errors and keeps reconnecting.
+    pub fn connect(config: MqttConfig) -> Self {
+        let client_id = format!("{}-{}", config.topic_prefix, std::process::id());
+        let mut options = MqttOptions::new(client_id, config.host.clone(), config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        options.set_last_will(LastWill::new(config.availability_topic(), "offline", QoS::AtLeastOnce, true));
+        if let Some(username) = &config.username {
+            options.set_credentials(username.clone(), config.password.clone().unwrap_or_default());
+        }
+
+        let (client, mut event_loop) = AsyncClient::new(options, 64);
+        info!("Publishing to MQTT broker {}:{}", config.host, config.port);
+        let availability = (client.clone(), config.availability_topic());
+        tokio::spawn(async move {
+            loop {
+                match event_loop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        // Replaces the last will left by a previous connection
+                        let (client, topic) = &availability;
+                        if let Err(e) = client.try_publish(topic, QoS::AtLeastOnce, true, "online") {
+                            warn!("Failed to publish MQTT availability: {}", e);
+                        }
+                    }
+                    Ok(event) => debug!("MQTT event: {:?}", event),
+                    Err(e) => {
+                        warn!("MQTT connection error: {}", e);
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            }
+        });
+
+        MqttPublisher { client, config, announced: HashSet::new(), availability: HashMap::new() }
+    }
+
+    /// Discovery configs, state and availability of every device with sensor values, to
+    /// `send` once the inventory is unlocked.
+    pub fn messages(&mut self, storage: &DeviceStorage) -> Vec<Message> {
+        let mut messages = Vec::new();
+        for (_, device) in storage.list_sensor_devices() {
+            let id = object_id(device);
+            let state = state(device);
+
+            for entity in ENTITIES.iter().filter(|entity| !state[entity.key].is_null()) {
+                if self.announced.insert(format!("{}/{}", id, entity.key)) {
+                    let payload = self.config.discovery_config(device, &id, entity);
+                    messages.push(Message { topic: self.config.discovery_topic(&id, entity), payload: payload.to_string(), retain: true });
+                }
+            }
+
+            let online = (Utc::now() - device.last_seen).to_std().map_or(true, |age| age < self.config.availability_timeout);
+            if self.availability.insert(id.clone(), online) != Some(online) {
+                let payload = if online { "online" } else { "offline" };
+                debug!("Device {} is {}", device.mac_address, payload);
+                messages.push(Message { topic: self.config.topic(&id, "availability"), payload: payload.to_string(), retain: true });
+            }
+
+            if online {
+                messages.push(Message { topic: self.config.topic(&id, "state"), payload: state.to_string(), retain: false });
+            }
+        }
+        messages
+    }
+
+    pub async fn send(&self, messages: Vec<Message>) -> Result<(), Box<dyn Error>> {
+        for message in messages {
+            self.client.publish(message.topic, QoS::AtLeastOnce, message.retain, message.payload).await?;
+        }
+        Ok(())
+    }
+}
+
+fn object_id(device: &BluetoothDevice) -> String {
+    device.mac_address.replace(':', "").to_lowercase()
+}
+
+fn state(device: &BluetoothDevice) -> serde_json::Value {
+    let sensor = device.sensor.as_ref();
+    json!({
+        "temperature": sensor.and_then(|s| s.temperature).map(round),
+        "humidity": sensor.and_then(|s| s.humidity).map(round),
+        "battery": sensor.and_then(|s| s.battery),
+        "rssi": device.rssi,
+        "last_seen": device.last_seen,
+    })
+}
+
+/// JSON numbers are f64; rounding avoids publishing f32 artifacts like 23.399999618530273.
+fn round(value: f32) -> f64 {
+    (value as f64 * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::simulated::SimulatedPeripheral;
+    use crate::sensor::{Measurement, ReadingSource};
+    use std::sync::Arc;
+
+    fn config() -> MqttConfig {
+        MqttConfig {
+            host: "localhost".to_string(),
+            port: 1883,
+            username: None,
+            password: None,
+            topic_prefix: "bluetooth".to_string(),
+            discovery_prefix: "homeassistant".to_string(),
+            availability_timeout: Duration::from_secs(300),
+        }
+    }
+
+    fn thermometer() -> BluetoothDevice {
+        let mac_address = "A4:C1:38:00:00:02";
+        let mut device = BluetoothDevice::new(mac_address.to_string(), "ATC_000002".to_string(), -70, "hci0", Arc::new(SimulatedPeripheral::new(mac_address)));
+        device.alias = Some("Kitchen".to_string());
+        device.apply_measurements(ReadingSource::Pvvx, &[Measurement::Temperature(21.53), Measurement::Humidity(52.1)]);
+        device
+    }
+
+    #[test]
+    fn state_holds_rounded_values() {
+        let state = state(&thermometer());
+        assert_eq!(state["temperature"], json!(21.53));
+        assert_eq!(state["humidity"], json!(52.1));
+        assert_eq!(state["battery"], json!(null));
+        assert_eq!(state["rssi"], json!(-70));
+        assert!(state["last_seen"].is_string());
+    }
+
+    #[test]
+    fn discovery_config_describes_the_entity() {
+        let device = thermometer();
+        let config = config();
+        let id = object_id(&device);
+        assert_eq!(id, "a4c138000002");
+        assert_eq!(config.discovery_topic(&id, &ENTITIES[0]), "homeassistant/sensor/bluetooth_a4c138000002/temperature/config");
+
+        let payload = config.discovery_config(&device, &id, &ENTITIES[0]);
+        assert_eq!(payload["unique_id"], "bluetooth_a4c138000002_temperature");
+        assert_eq!(payload["state_topic"], "bluetooth/a4c138000002/state");
+        assert_eq!(payload["value_template"], "{{ value_json.temperature }}");
+        assert_eq!(payload["unit_of_measurement"], "°C");
+        assert_eq!(payload["availability"], json!([{ "topic": "bluetooth/availability" }, { "topic": "bluetooth/a4c138000002/availability" }]));
+        assert_eq!(payload["availability_mode"], "all");
+        assert_eq!(payload["device"]["name"], "Kitchen");
+        assert_eq!(payload["device"]["model"], "ATC_000002");
+        assert_eq!(payload["device"]["connections"], json!([["mac", "a4:c1:38:00:00:02"]]));
+        assert!(payload.get("entity_category").is_none());
+
+        let rssi = config.discovery_config(&device, &id, &ENTITIES[3]);
+        assert_eq!(rssi["entity_category"], "diagnostic");
+    }
+}
@@ -0,0 +1,263 @@
+//! Presence detection: which devices are around, with events when they arrive or depart.
+//!
+//! A device departs once no advertisement was heard from it for the absence timeout. To
+//! arrive, it has to be heard several times, so a device at the edge of reception that
+//! gets through once in a while doesn't flap between present and absent.
+//!
+//! The events are shown by the `presence` command and the interactive menu. `serve` tracks
+//! presence while it scans, and the API tells which devices are present.
+
+use crate::device_info::BluetoothDevice;
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+pub const DEFAULT_ABSENCE_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_ARRIVAL_SIGHTINGS: u32 = 2;
+/// Sightings closer together than this count once; a single advertisement can be reported
+/// as several adapter events.
+const MIN_SIGHTING_GAP: chrono::Duration = chrono::Duration::milliseconds(500);
+/// Events kept for subscribers that fall behind.
+const EVENT_BUFFER: usize = 256;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PresenceConfig {
+    /// Time without advertisements after which a present device departs.
+    pub absence_timeout: Duration,
+    /// Sightings, each within the absence timeout of the previous one, before a device arrives.
+    pub arrival_sightings: u32,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        PresenceConfig { absence_timeout: DEFAULT_ABSENCE_TIMEOUT, arrival_sightings: DEFAULT_ARRIVAL_SIGHTINGS }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceEventKind {
+    Arrived,
+    Departed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PresenceEvent {
+    pub kind: PresenceEventKind,
+    pub id: u32,
+    pub mac_address: String,
+    /// Alias if set, advertised name otherwise.
+    pub name: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl fmt::Display for PresenceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            PresenceEventKind::Arrived => "Arrived",
+            PresenceEventKind::Departed => "Departed",
+        };
+        write!(f, "{} {}: ID {}, {} ({})", self.timestamp.format("%Y-%m-%d %H:%M:%S"), kind, self.id, self.name, self.mac_address)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PresenceState {
+    Absent { sightings: u32, last_sighting: Option<DateTime<Utc>> },
+    Present { last_sighting: DateTime<Utc> },
+}
+
+/// Presence of the inventory's devices, fed with the advertisements heard.
+pub struct PresenceTracker {
+    config: PresenceConfig,
+    states: HashMap<u32, PresenceState>,
+    sender: broadcast::Sender<PresenceEvent>,
+}
+
+impl Default for PresenceTracker {
+    fn default() -> Self {
+        PresenceTracker::new(PresenceConfig::default())
+    }
+}
+
+impl PresenceTracker {
+    pub fn new(config: PresenceConfig) -> Self {
+        PresenceTracker { config, states: HashMap::new(), sender: broadcast::channel(EVENT_BUFFER).0 }
+    }
+
+    pub fn set_config(&mut self, config: PresenceConfig) {
+        self.config = config;
+    }
+
+    /// Receives every event emitted from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<PresenceEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn is_present(&self, id: u32) -> bool {
+        matches!(self.states.get(&id), Some(PresenceState::Present { .. }))
+    }
+
+    /// Records an advertisement heard from the device at `timestamp`.
+    pub fn sighted(&mut self, id: u32, device: &BluetoothDevice, timestamp: DateTime<Utc>) {
+        let absence_timeout = self.absence_timeout();
+        let state = self.states.entry(id).or_insert(PresenceState::Absent { sightings: 0, last_sighting: None });
+        let sightings = match *state {
+            PresenceState::Present { .. } => {
+                *state = PresenceState::Present { last_sighting: timestamp };
+                return;
+            }
+            PresenceState::Absent { last_sighting: Some(last), .. } if timestamp - last < MIN_SIGHTING_GAP => return,
+            PresenceState::Absent { sightings, last_sighting: Some(last) } if timestamp - last <= absence_timeout => sightings + 1,
+            PresenceState::Absent { .. } => 1,
+        };
+        if sightings >= self.config.arrival_sightings {
+            *state = PresenceState::Present { last_sighting: timestamp };
+            emit(&self.sender, PresenceEventKind::Arrived, id, device, timestamp);
+        } else {
+            *state = PresenceState::Absent { sightings, last_sighting: Some(timestamp) };
+        }
+    }
+
+    /// Departs the present devices not heard for the absence timeout. Devices the tracker
+    /// doesn't know yet, e.g. from the inventory file, count as present if they were seen
+    /// within the timeout, without an arrival event.
+    pub fn check<'a>(&mut self, devices: impl IntoIterator<Item = (u32, &'a BluetoothDevice)>, now: DateTime<Utc>) {
+        let absence_timeout = self.absence_timeout();
+        for (id, device) in devices {
+            let state = self.states.entry(id).or_insert_with(|| {
+                if now - device.last_seen <= absence_timeout {
+                    PresenceState::Present { last_sighting: device.last_seen }
+                } else {
+                    PresenceState::Absent { sightings: 0, last_sighting: None }
+                }
+            });
+            if let PresenceState::Present { last_sighting } = *state {
+                if now - last_sighting > absence_timeout {
+                    *state = PresenceState::Absent { sightings: 0, last_sighting: None };
+                    emit(&self.sender, PresenceEventKind::Departed, id, device, now);
+                }
+            }
+        }
+    }
+
+    fn absence_timeout(&self) -> chrono::Duration {
+        chrono::Duration::from_std(self.config.absence_timeout).unwrap_or(chrono::Duration::MAX)
+    }
+}
+
+fn emit(sender: &broadcast::Sender<PresenceEvent>, kind: PresenceEventKind, id: u32, device: &BluetoothDevice, timestamp: DateTime<Utc>) {
+    info!("Device {} ({}) {:?}", id, device.mac_address, kind);
+    // Nobody may be listening, which is fine
+    let _ = sender.send(PresenceEvent {
+        kind,
+        id,
+        mac_address: device.mac_address.clone(),
+        name: device.display_name().to_string(),
+        timestamp,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::simulated::SimulatedPeripheral;
+    use chrono::TimeZone;
+    use std::sync::Arc;
+
+    fn tracker() -> (PresenceTracker, broadcast::Receiver<PresenceEvent>) {
+        let tracker = PresenceTracker::new(PresenceConfig { absence_timeout: Duration::from_secs(60), arrival_sightings: 2 });
+        let events = tracker.subscribe();
+        (tracker, events)
+    }
+
+    fn device(last_seen: DateTime<Utc>) -> BluetoothDevice {
+        let mut device = BluetoothDevice::new("AA:BB:CC:DD:EE:01".to_string(), "Tag".to_string(), -70, "hci0", Arc::new(SimulatedPeripheral::new("AA:BB:CC:DD:EE:01")));
+        device.last_seen = last_seen;
+        device
+    }
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap() + chrono::Duration::seconds(seconds)
+    }
+
+    fn events(receiver: &mut broadcast::Receiver<PresenceEvent>) -> Vec<(PresenceEventKind, DateTime<Utc>)> {
+        std::iter::from_fn(|| receiver.try_recv().ok()).map(|event| (event.kind, event.timestamp)).collect()
+    }
+
+    #[test]
+    fn arrival_needs_repeated_sightings() {
+        let (mut tracker, mut receiver) = tracker();
+        let device = device(at(0));
+        tracker.sighted(1, &device, at(0));
+        assert!(!tracker.is_present(1));
+        // Reported again for the same advertisement
+        tracker.sighted(1, &device, at(0) + chrono::Duration::milliseconds(100));
+        assert!(!tracker.is_present(1));
+        tracker.sighted(1, &device, at(5));
+        assert!(tracker.is_present(1));
+        assert_eq!(events(&mut receiver), [(PresenceEventKind::Arrived, at(5))]);
+    }
+
+    #[test]
+    fn devices_at_the_edge_of_reception_do_not_flap() {
+        let (mut tracker, mut receiver) = tracker();
+        let device = device(at(0));
+        // Heard once every two absence timeouts, it never arrives
+        for minute in 0..5 {
+            tracker.sighted(1, &device, at(minute * 120));
+            tracker.check([(1, &device)], at(minute * 120 + 61));
+        }
+        assert!(!tracker.is_present(1));
+        assert!(events(&mut receiver).is_empty());
+
+        // Once present, missed advertisements within the timeout don't depart it
+        tracker.sighted(1, &device, at(1000));
+        tracker.sighted(1, &device, at(1010));
+        tracker.check([(1, &device)], at(1060));
+        tracker.sighted(1, &device, at(1065));
+        tracker.check([(1, &device)], at(1120));
+        assert!(tracker.is_present(1));
+        assert_eq!(events(&mut receiver), [(PresenceEventKind::Arrived, at(1010))]);
+    }
+
+    #[test]
+    fn devices_depart_after_the_absence_timeout() {
+        let (mut tracker, mut receiver) = tracker();
+        let device = device(at(0));
+        tracker.sighted(1, &device, at(0));
+        tracker.sighted(1, &device, at(1));
+        tracker.check([(1, &device)], at(61));
+        assert!(tracker.is_present(1));
+        tracker.check([(1, &device)], at(62));
+        assert!(!tracker.is_present(1));
+        // Departed once, not on every check
+        tracker.check([(1, &device)], at(63));
+        assert_eq!(events(&mut receiver), [(PresenceEventKind::Arrived, at(1)), (PresenceEventKind::Departed, at(62))]);
+
+        // Coming back takes the arrival sightings again
+        tracker.sighted(1, &device, at(100));
+        assert!(!tracker.is_present(1));
+        tracker.sighted(1, &device, at(101));
+        assert_eq!(events(&mut receiver), [(PresenceEventKind::Arrived, at(101))]);
+    }
+
+    #[test]
+    fn inventory_devices_are_present_if_seen_recently() {
+        let (mut tracker, mut receiver) = tracker();
+        let recent = device(at(-30));
+        let old = device(at(-3600));
+        tracker.check([(1, &recent), (2, &old)], at(0));
+        assert!(tracker.is_present(1));
+        assert!(!tracker.is_present(2));
+        // Without an arrival, but with a departure once the timeout passes
+        assert!(events(&mut receiver).is_empty());
+        tracker.check([(1, &recent), (2, &old)], at(31));
+        assert!(!tracker.is_present(1));
+        assert_eq!(events(&mut receiver), [(PresenceEventKind::Departed, at(31))]);
+    }
+}
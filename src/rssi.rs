@@ -0,0 +1,283 @@
+//! RSSI history per device, smoothing and distance estimation.
+//!
+//! Single RSSI samples jump by several dB between advertisements, so listings show a moving
+//! average and a Kalman filtered value next to the last sample. The distance follows the
+//! log-distance path loss model from the filtered RSSI and the TX power the device advertises.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Samples kept per device.
+pub const HISTORY_LENGTH: usize = 32;
+/// Samples the moving average is taken over.
+pub const MOVING_AVERAGE_WINDOW: usize = 8;
+/// Path loss exponent of free space; 2.7 to 4 suits rooms with walls and furniture.
+pub const DEFAULT_PATH_LOSS_EXPONENT: f64 = 2.0;
+/// Advertised TX power is the level at 0 m; the level at 1 m is this much lower.
+const ONE_METER_LOSS_DB: f64 = 41.0;
+/// Change in dB between the older and newer half of the history that counts as a trend.
+const TREND_THRESHOLD_DB: f64 = 2.0;
+
+/// How much the true RSSI is expected to drift between samples, in dB².
+const PROCESS_NOISE: f64 = 0.5;
+/// Variance of single samples around the true RSSI, in dB².
+const MEASUREMENT_NOISE: f64 = 16.0;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RssiSample {
+    pub rssi: i16,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// One-dimensional Kalman filter over the RSSI, assuming the device moves slowly.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct KalmanFilter {
+    pub estimate: f64,
+    /// Variance of the estimate.
+    pub variance: f64,
+}
+
+impl KalmanFilter {
+    fn new(rssi: f64) -> Self {
+        KalmanFilter { estimate: rssi, variance: MEASUREMENT_NOISE }
+    }
+
+    fn update(&mut self, rssi: f64) {
+        let predicted_variance = self.variance + PROCESS_NOISE;
+        let gain = predicted_variance / (predicted_variance + MEASUREMENT_NOISE);
+        self.estimate += gain * (rssi - self.estimate);
+        self.variance = (1.0 - gain) * predicted_variance;
+    }
+}
+
+/// The latest RSSI samples of a device, oldest first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RssiHistory {
+    samples: VecDeque<RssiSample>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kalman: Option<KalmanFilter>,
+}
+
+impl RssiHistory {
+    /// Records a sample, dropping the oldest once `HISTORY_LENGTH` are kept.
+    pub fn push(&mut self, rssi: i16, timestamp: DateTime<Utc>) {
+        if self.samples.len() == HISTORY_LENGTH {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(RssiSample { rssi, timestamp });
+        match &mut self.kalman {
+            Some(kalman) => kalman.update(rssi as f64),
+            None => self.kalman = Some(KalmanFilter::new(rssi as f64)),
+        }
+    }
+
+    pub fn samples(&self) -> impl Iterator<Item = &RssiSample> {
+        self.samples.iter()
+    }
+
+    /// Mean of the last `MOVING_AVERAGE_WINDOW` samples.
+    pub fn moving_average(&self) -> Option<f64> {
+        let window = self.samples.len().min(MOVING_AVERAGE_WINDOW);
+        mean(self.samples.iter().rev().take(window))
+    }
+
+    pub fn filtered(&self) -> Option<f64> {
+        self.kalman.map(|kalman| kalman.estimate)
+    }
+
+    /// Whether the signal got stronger or weaker over the history.
+    pub fn trend(&self) -> Trend {
+        if self.samples.len() < 4 {
+            return Trend::Unknown;
+        }
+        let half = self.samples.len() / 2;
+        let (Some(older), Some(newer)) = (mean(self.samples.iter().take(half)), mean(self.samples.iter().skip(half))) else {
+            return Trend::Unknown;
+        };
+        if newer - older >= TREND_THRESHOLD_DB {
+            Trend::Approaching
+        } else if older - newer >= TREND_THRESHOLD_DB {
+            Trend::Receding
+        } else {
+            Trend::Steady
+        }
+    }
+
+    /// Smoothed values and distance of the history, `None` without samples.
+    pub fn summary(&self, tx_power: Option<i16>, path_loss_exponent: f64) -> Option<SignalSummary> {
+        let filtered = self.filtered()?;
+        Some(SignalSummary {
+            samples: self.samples.len(),
+            moving_average: self.moving_average()?,
+            filtered,
+            distance: tx_power.map(|tx_power| estimate_distance(filtered, tx_power, path_loss_exponent)),
+            trend: self.trend(),
+        })
+    }
+}
+
+fn mean<'a>(samples: impl Iterator<Item = &'a RssiSample>) -> Option<f64> {
+    let (sum, count) = samples.fold((0.0, 0), |(sum, count), sample| (sum + sample.rssi as f64, count + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+/// Parses a path loss exponent, which has to be positive.
+pub fn parse_path_loss_exponent(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(exponent) if exponent > 0.0 && exponent.is_finite() => Ok(exponent),
+        Ok(_) => Err(format!("Path loss exponent must be positive, got {}", value)),
+        Err(e) => Err(format!("Invalid path loss exponent '{}': {}", value, e)),
+    }
+}
+
+/// Distance in meters at which a device advertising `tx_power` is received with `rssi`.
+pub fn estimate_distance(rssi: f64, tx_power: i16, path_loss_exponent: f64) -> f64 {
+    let one_meter = tx_power as f64 - ONE_METER_LOSS_DB;
+    10f64.powf((one_meter - rssi) / (10.0 * path_loss_exponent))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trend {
+    Approaching,
+    Receding,
+    Steady,
+    /// Too few samples to tell.
+    Unknown,
+}
+
+impl fmt::Display for Trend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trend::Approaching => write!(f, "approaching"),
+            Trend::Receding => write!(f, "receding"),
+            Trend::Steady => write!(f, "steady"),
+            Trend::Unknown => write!(f, "trend unknown"),
+        }
+    }
+}
+
+/// Smoothed signal of a device.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SignalSummary {
+    pub samples: usize,
+    /// dBm
+    pub moving_average: f64,
+    /// Kalman filtered RSSI, dBm
+    pub filtered: f64,
+    /// Estimated meters, only for devices advertising their TX power
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance: Option<f64>,
+    pub trend: Trend,
+}
+
+impl fmt::Display for SignalSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "average {:.1} dBm, filtered {:.1} dBm", self.moving_average, self.filtered)?;
+        if let Some(distance) = self.distance {
+            write!(f, ", ~{:.1} m", distance)?;
+        }
+        write!(f, ", {} ({} samples)", self.trend, self.samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn history(samples: &[i16]) -> RssiHistory {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut history = RssiHistory::default();
+        for (second, &rssi) in samples.iter().enumerate() {
+            history.push(rssi, start + chrono::Duration::seconds(second as i64));
+        }
+        history
+    }
+
+    #[test]
+    fn kalman_filter_smooths_samples() {
+        let history = history(&[-60]);
+        assert_eq!(history.filtered(), Some(-60.0));
+
+        // A single outlier moves the estimate only part of the way
+        let history = self::history(&[-60, -60, -60, -60, -80]);
+        let filtered = history.filtered().unwrap();
+        assert!(filtered < -60.0 && filtered > -70.0, "{}", filtered);
+
+        // A lasting change is followed
+        let history = self::history(&[-60; 4].iter().chain(&[-80; 28]).copied().collect::<Vec<_>>());
+        assert!((history.filtered().unwrap() + 80.0).abs() < 1.0, "{:?}", history.filtered());
+        assert!(RssiHistory::default().filtered().is_none());
+    }
+
+    #[test]
+    fn moving_average_covers_the_last_samples() {
+        assert_eq!(history(&[-50, -60]).moving_average(), Some(-55.0));
+        let samples: Vec<i16> = [-90; 4].iter().chain(&[-60; MOVING_AVERAGE_WINDOW]).copied().collect();
+        assert_eq!(history(&samples).moving_average(), Some(-60.0));
+        assert_eq!(RssiHistory::default().moving_average(), None);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let samples: Vec<i16> = (0..HISTORY_LENGTH as i16 + 5).map(|n| -40 - n).collect();
+        let history = history(&samples);
+        assert_eq!(history.samples().count(), HISTORY_LENGTH);
+        assert_eq!(history.samples().next().unwrap().rssi, -45);
+    }
+
+    #[test]
+    fn trends() {
+        let cases = [
+            (vec![-60, -60, -60], Trend::Unknown),
+            (vec![-60, -60, -60, -60], Trend::Steady),
+            (vec![-61, -60, -60, -59], Trend::Steady),
+            (vec![-70, -70, -60, -60], Trend::Approaching),
+            // The threshold itself counts
+            (vec![-60, -60, -62, -62], Trend::Receding),
+            (vec![-60, -60, -70, -70], Trend::Receding),
+        ];
+        for (samples, expected) in cases {
+            assert_eq!(history(&samples).trend(), expected, "{:?}", samples);
+        }
+    }
+
+    #[test]
+    fn distances() {
+        // At the 1 m level the distance is 1 m, every 10·n dB further it is 10 times more
+        let cases = [
+            (-41.0, 0, 2.0, 1.0),
+            (-61.0, 0, 2.0, 10.0),
+            (-81.0, 0, 2.0, 100.0),
+            (-71.0, 0, 3.0, 10.0),
+            (-21.0, 0, 2.0, 0.1),
+            (-49.0, -8, 2.0, 1.0),
+        ];
+        for (rssi, tx_power, exponent, expected) in cases {
+            let distance = estimate_distance(rssi, tx_power, exponent);
+            assert!((distance - expected).abs() < 1e-9, "{} dBm at {} dBm, n={}: {}", rssi, tx_power, exponent, distance);
+        }
+    }
+
+    #[test]
+    fn summary_needs_tx_power_for_the_distance() {
+        let history = history(&[-61, -61, -61, -61]);
+        let summary = history.summary(Some(0), 2.0).unwrap();
+        assert_eq!((summary.samples, summary.trend), (4, Trend::Steady));
+        assert!((summary.distance.unwrap() - 10.0).abs() < 1e-9);
+        assert!(history.summary(None, 2.0).unwrap().distance.is_none());
+        assert!(RssiHistory::default().summary(Some(0), 2.0).is_none());
+    }
+
+    #[test]
+    fn parses_path_loss_exponents() {
+        assert_eq!(parse_path_loss_exponent("2"), Ok(2.0));
+        assert_eq!(parse_path_loss_exponent(" 3.5 "), Ok(3.5));
+        for value in ["0", "-2", "inf", "NaN", "two"] {
+            assert!(parse_path_loss_exponent(value).is_err(), "{}", value);
+        }
+    }
+}
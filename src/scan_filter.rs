@@ -0,0 +1,228 @@
+//! Filters deciding which discovered peripherals make it into the inventory.
+
+use crate::assigned_numbers;
+use btleplug::api::{PeripheralProperties, ScanFilter};
+use regex::{Regex, RegexBuilder};
+use uuid::Uuid;
+
+/// Criteria a peripheral must all meet to be stored. An empty filter accepts everything.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceFilter {
+    /// Advertised service UUIDs, at least one must match. Also passed to the OS scan.
+    pub services: Vec<Uuid>,
+    /// Pattern the advertised name must match.
+    pub name: Option<Regex>,
+    /// Weakest accepted signal, in dBm.
+    pub min_rssi: Option<i16>,
+    /// Manufacturer company IDs, at least one must be advertised.
+    pub manufacturer_ids: Vec<u16>,
+    /// Address patterns; when not empty, only matching devices are accepted.
+    pub allow: Vec<Regex>,
+    /// Address patterns that are always rejected.
+    pub deny: Vec<Regex>,
+}
+
+impl DeviceFilter {
+    /// The part of the filter the OS can apply while scanning.
+    pub fn scan_filter(&self) -> ScanFilter {
+        ScanFilter { services: self.services.clone() }
+    }
+
+    pub fn matches(&self, mac_address: &str, properties: Option<&PeripheralProperties>) -> bool {
+        if self.deny.iter().any(|pattern| pattern.is_match(mac_address)) {
+            return false;
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|pattern| pattern.is_match(mac_address)) {
+            return false;
+        }
+
+        let name = properties.and_then(|p| p.local_name.as_deref());
+        let rssi = properties.and_then(|p| p.rssi);
+        if let Some(pattern) = &self.name {
+            if !name.is_some_and(|name| pattern.is_match(name)) {
+                return false;
+            }
+        }
+        if let Some(min_rssi) = self.min_rssi {
+            if rssi.is_none_or(|rssi| rssi < min_rssi) {
+                return false;
+            }
+        }
+        // Not every backend honours the OS-level filter, so services are checked here too
+        if !self.services.is_empty()
+            && !properties.is_some_and(|p| {
+                self.services.iter().any(|uuid| p.services.contains(uuid) || p.service_data.contains_key(uuid))
+            })
+        {
+            return false;
+        }
+        if !self.manufacturer_ids.is_empty()
+            && !properties.is_some_and(|p| self.manufacturer_ids.iter().any(|id| p.manufacturer_data.contains_key(id)))
+        {
+            return false;
+        }
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty()
+            && self.name.is_none()
+            && self.min_rssi.is_none()
+            && self.manufacturer_ids.is_empty()
+            && self.allow.is_empty()
+            && self.deny.is_empty()
+    }
+}
+
+/// Compiles a case-insensitive regular expression.
+pub fn regex(pattern: &str) -> Result<Regex, String> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))
+}
+
+/// Compiles a glob (`*` any run of characters, `?` one character) matching the whole text.
+pub fn glob(pattern: &str) -> Result<Regex, String> {
+    let mut expression = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => expression.push_str(".*"),
+            '?' => expression.push('.'),
+            c => expression.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    expression.push('$');
+    regex(&expression)
+}
+
+/// Parses a manufacturer company ID given in decimal, as `0x` hex or by the company's name
+/// or a prefix only it has.
+pub fn parse_company_id(value: &str) -> Result<u16, String> {
+    let value = value.trim();
+    let number = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    match number {
+        Ok(id) => Ok(id),
+        // Numbers that don't fit aren't names either
+        Err(e) if value.starts_with(|c: char| c.is_ascii_digit()) => Err(format!("Invalid company ID '{}': {}", value, e)),
+        Err(_) => assigned_numbers::company_id(value).map_err(|e| format!("Invalid company ID '{}': {}", value, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const XIAOMI_SERVICE: Uuid = Uuid::from_u128(0x0000fe95_0000_1000_8000_00805f9b34fb);
+    const BATTERY_SERVICE: Uuid = Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb);
+
+    fn properties(name: Option<&str>, rssi: Option<i16>, service: Option<Uuid>, company: Option<u16>) -> PeripheralProperties {
+        PeripheralProperties {
+            local_name: name.map(str::to_string),
+            rssi,
+            services: service.into_iter().collect(),
+            manufacturer_data: company.into_iter().map(|id| (id, vec![0x01])).collect(),
+            service_data: HashMap::new(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn globs_match_whole_addresses() {
+        let cases = [
+            ("A4:C1:38:*", "A4:C1:38:12:34:56", true),
+            ("a4:c1:38:*", "A4:C1:38:12:34:56", true),
+            ("A4:C1:38:*", "00:A4:C1:38:12:34", false),
+            ("*:56", "A4:C1:38:12:34:56", true),
+            ("*:56", "A4:C1:38:12:56:34", false),
+            ("A4:C1:38:12:34:5?", "A4:C1:38:12:34:56", true),
+            ("A4:C1:38:12:34:5?", "A4:C1:38:12:34:5", false),
+            ("A4:C1:38:12:34:56", "A4:C1:38:12:34:56", true),
+            // Regex characters are literal
+            ("A4.C1.*", "A4:C1:38:12:34:56", false),
+            ("(A4)*", "(A4):C1", true),
+            ("*", "", true),
+        ];
+        for (pattern, address, expected) in cases {
+            assert_eq!(glob(pattern).unwrap().is_match(address), expected, "{} on {}", pattern, address);
+        }
+    }
+
+    #[test]
+    fn parses_company_ids() {
+        let cases = [
+            ("76", Ok(0x004c)),
+            ("0x004c", Ok(0x004c)),
+            ("0X038F", Ok(0x038f)),
+            (" 0x038f ", Ok(0x038f)),
+            ("apple", Ok(0x004c)),
+            ("Xiaomi", Ok(0x038f)),
+            ("", Err(())),
+            ("a", Err(())),
+            ("65536", Err(())),
+            ("0x1ffff", Err(())),
+            ("0xzz", Err(())),
+            ("No Such Company", Err(())),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_company_id(value).map_err(|_| ()), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn filters_peripherals() {
+        let address = "A4:C1:38:12:34:56";
+        let sensor = properties(Some("LYWSD03MMC"), Some(-60), Some(XIAOMI_SERVICE), Some(0x038f));
+        let silent = properties(None, None, None, None);
+        let filter = |f: fn(&mut DeviceFilter)| {
+            let mut filter = DeviceFilter::default();
+            f(&mut filter);
+            filter
+        };
+        let cases: Vec<(&str, DeviceFilter, _, bool)> = vec![
+            ("empty accepts all", DeviceFilter::default(), Some(&sensor), true),
+            ("empty accepts no properties", DeviceFilter::default(), None, true),
+            ("name matches", filter(|f| f.name = Some(regex("^lywsd").unwrap())), Some(&sensor), true),
+            ("name differs", filter(|f| f.name = Some(regex("^ATC").unwrap())), Some(&sensor), false),
+            ("name missing", filter(|f| f.name = Some(regex(".*").unwrap())), Some(&silent), false),
+            ("rssi strong enough", filter(|f| f.min_rssi = Some(-70)), Some(&sensor), true),
+            ("rssi at the limit", filter(|f| f.min_rssi = Some(-60)), Some(&sensor), true),
+            ("rssi too weak", filter(|f| f.min_rssi = Some(-50)), Some(&sensor), false),
+            ("rssi missing", filter(|f| f.min_rssi = Some(-100)), Some(&silent), false),
+            ("service advertised", filter(|f| f.services = vec![BATTERY_SERVICE, XIAOMI_SERVICE]), Some(&sensor), true),
+            ("service not advertised", filter(|f| f.services = vec![BATTERY_SERVICE]), Some(&sensor), false),
+            ("service without properties", filter(|f| f.services = vec![XIAOMI_SERVICE]), None, false),
+            ("manufacturer advertised", filter(|f| f.manufacturer_ids = vec![0x004c, 0x038f]), Some(&sensor), true),
+            ("manufacturer not advertised", filter(|f| f.manufacturer_ids = vec![0x004c]), Some(&sensor), false),
+            ("allowed", filter(|f| f.allow = vec![glob("A4:C1:38:*").unwrap()]), Some(&sensor), true),
+            ("not allowed", filter(|f| f.allow = vec![glob("E7:*").unwrap()]), Some(&sensor), false),
+            ("denied", filter(|f| f.deny = vec![glob("*:56").unwrap()]), Some(&sensor), false),
+            (
+                "deny wins over allow",
+                filter(|f| {
+                    f.allow = vec![glob("A4:*").unwrap()];
+                    f.deny = vec![glob("*:56").unwrap()];
+                }),
+                Some(&sensor),
+                false,
+            ),
+        ];
+        for (name, filter, properties, expected) in cases {
+            assert_eq!(filter.matches(address, properties), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn services_match_service_data() {
+        let mut properties = properties(None, None, None, None);
+        properties.service_data.insert(XIAOMI_SERVICE, vec![0x50]);
+        let filter = DeviceFilter { services: vec![XIAOMI_SERVICE], ..Default::default() };
+        assert!(filter.matches("A4:C1:38:12:34:56", Some(&properties)));
+        assert!(!filter.is_empty());
+        assert!(DeviceFilter::default().is_empty());
+    }
+}
@@ -0,0 +1,205 @@
+//! Sensor values decoded from advertisements or notifications, independent of their format.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A single value carried by an advertisement.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Measurement {
+    /// Degrees Celsius
+    Temperature(f32),
+    /// Relative humidity in percent
+    Humidity(f32),
+    /// Battery level in percent
+    Battery(u8),
+    /// Battery voltage in millivolts
+    BatteryVoltage(u16),
+    /// Volts
+    Voltage(f32),
+    /// Hectopascal
+    Pressure(f32),
+    /// Lux
+    Illuminance(f32),
+    /// Whether motion is detected
+    Motion(bool),
+    /// Whether the door is open
+    Door(bool),
+    Button(ButtonEvent),
+}
+
+/// Button event reported by a BTHome device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ButtonEvent {
+    None,
+    Press,
+    DoublePress,
+    TriplePress,
+    LongPress,
+    LongDoublePress,
+    LongTriplePress,
+    HoldPress,
+}
+
+impl ButtonEvent {
+    pub fn from_bthome(value: u8) -> Option<Self> {
+        match value {
+            0x00 => Some(ButtonEvent::None),
+            0x01 => Some(ButtonEvent::Press),
+            0x02 => Some(ButtonEvent::DoublePress),
+            0x03 => Some(ButtonEvent::TriplePress),
+            0x04 => Some(ButtonEvent::LongPress),
+            0x05 => Some(ButtonEvent::LongDoublePress),
+            0x06 => Some(ButtonEvent::LongTriplePress),
+            0x80 => Some(ButtonEvent::HoldPress),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ButtonEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ButtonEvent::None => "none",
+            ButtonEvent::Press => "press",
+            ButtonEvent::DoublePress => "double press",
+            ButtonEvent::TriplePress => "triple press",
+            ButtonEvent::LongPress => "long press",
+            ButtonEvent::LongDoublePress => "long double press",
+            ButtonEvent::LongTriplePress => "long triple press",
+            ButtonEvent::HoldPress => "hold press",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Format a reading was decoded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReadingSource {
+    MiBeacon,
+    /// ATC1441 custom firmware
+    Atc,
+    /// pvvx custom firmware
+    Pvvx,
+    BTHome,
+    /// Notified by an MJ_HT_V1 over a connection
+    MjHtV1,
+}
+
+impl fmt::Display for ReadingSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadingSource::MiBeacon => write!(f, "MiBeacon"),
+            ReadingSource::Atc => write!(f, "ATC"),
+            ReadingSource::Pvvx => write!(f, "pvvx"),
+            ReadingSource::BTHome => write!(f, "BTHome"),
+            ReadingSource::MjHtV1 => write!(f, "MJ_HT_V1"),
+        }
+    }
+}
+
+/// Latest known values of a sensor. Formats that split values over several frames
+/// (MiBeacon sends temperature, humidity and battery separately) are merged here.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SensorData {
+    pub source: ReadingSource,
+    pub updated: DateTime<Utc>,
+    pub temperature: Option<f32>,
+    pub humidity: Option<f32>,
+    pub battery: Option<u8>,
+    pub battery_voltage: Option<u16>,
+    pub voltage: Option<f32>,
+    pub pressure: Option<f32>,
+    pub illuminance: Option<f32>,
+    pub motion: Option<bool>,
+    pub door: Option<bool>,
+    /// Last button event
+    pub button: Option<ButtonEvent>,
+}
+
+impl SensorData {
+    pub fn new(source: ReadingSource) -> Self {
+        SensorData {
+            source,
+            updated: Utc::now(),
+            temperature: None,
+            humidity: None,
+            battery: None,
+            battery_voltage: None,
+            voltage: None,
+            pressure: None,
+            illuminance: None,
+            motion: None,
+            door: None,
+            button: None,
+        }
+    }
+
+    pub fn apply(&mut self, measurement: Measurement) {
+        match measurement {
+            Measurement::Temperature(value) => self.temperature = Some(value),
+            Measurement::Humidity(value) => self.humidity = Some(value),
+            Measurement::Battery(value) => self.battery = Some(value),
+            Measurement::BatteryVoltage(value) => self.battery_voltage = Some(value),
+            Measurement::Voltage(value) => self.voltage = Some(value),
+            Measurement::Pressure(value) => self.pressure = Some(value),
+            Measurement::Illuminance(value) => self.illuminance = Some(value),
+            Measurement::Motion(value) => self.motion = Some(value),
+            Measurement::Door(value) => self.door = Some(value),
+            Measurement::Button(value) => self.button = Some(value),
+        }
+    }
+
+    /// Takes over every value present in `newer`, keeping the ones it lacks.
+    pub fn merge(&mut self, newer: &SensorData) {
+        self.source = newer.source;
+        self.updated = newer.updated;
+        self.temperature = newer.temperature.or(self.temperature);
+        self.humidity = newer.humidity.or(self.humidity);
+        self.battery = newer.battery.or(self.battery);
+        self.battery_voltage = newer.battery_voltage.or(self.battery_voltage);
+        self.voltage = newer.voltage.or(self.voltage);
+        self.pressure = newer.pressure.or(self.pressure);
+        self.illuminance = newer.illuminance.or(self.illuminance);
+        self.motion = newer.motion.or(self.motion);
+        self.door = newer.door.or(self.door);
+        self.button = newer.button.or(self.button);
+    }
+}
+
+impl fmt::Display for SensorData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}]", self.source)?;
+        if let Some(temperature) = self.temperature {
+            write!(f, " Temperature: {:.1} °C", temperature)?;
+        }
+        if let Some(humidity) = self.humidity {
+            write!(f, " Humidity: {:.1} %", humidity)?;
+        }
+        if let Some(battery) = self.battery {
+            write!(f, " Battery: {} %", battery)?;
+        }
+        if let Some(voltage) = self.battery_voltage {
+            write!(f, " ({} mV)", voltage)?;
+        }
+        if let Some(voltage) = self.voltage {
+            write!(f, " Voltage: {:.3} V", voltage)?;
+        }
+        if let Some(pressure) = self.pressure {
+            write!(f, " Pressure: {:.2} hPa", pressure)?;
+        }
+        if let Some(illuminance) = self.illuminance {
+            write!(f, " Illuminance: {:.2} lx", illuminance)?;
+        }
+        if let Some(motion) = self.motion {
+            write!(f, " Motion: {}", if motion { "detected" } else { "clear" })?;
+        }
+        if let Some(door) = self.door {
+            write!(f, " Door: {}", if door { "open" } else { "closed" })?;
+        }
+        if let Some(button) = self.button {
+            write!(f, " Button: {}", button)?;
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,25 @@
+use btleplug::api::WriteType;
+use clap::ValueEnum;
+use crate::assigned_numbers;
+use crate::bluetooth_manager::{AdapterEntry, PollResult};
+use crate::connection_manager::ConnectionInfo;
+use crate::device_info::BluetoothDevice;
 use crate::device_storage::DeviceStorage;
+use crate::error::BluetoothError;
+use crate::mj_ht_v1::MjHtV1Reading;
+use crate::presence::PresenceEvent;
+use crate::value_format::ValueFormat;
+use std::path::PathBuf;
 
 pub struct UserInterface;
 
+/// Labels entered for a device; `None` keeps the current value, an empty string clears it.
+pub struct Labels {
+    pub alias: Option<String>,
+    pub location: Option<String>,
+    pub tags: Vec<String>,
+}
+
 impl UserInterface {
     pub fn new() -> Self {
         UserInterface {}
@@ -20,7 +38,16 @@ impl UserInterface {
         println!("10. Disconnect from device");
         println!("11. Discover services");
         println!("12. Read characteristic");
+        println!("13. List sensor readings from advertisements");
+        println!("14. List thermometers (MJ_HT_V1, ATC, pvvx)");
+        println!("15. Write characteristic");
+        println!("16. Set alias, location and tags");
+        println!("17. List Bluetooth adapters");
+        println!("18. List connections");
+        println!("19. Read all MJ_HT_V1 sensors");
         println!("20. Exit");
+        println!("21. Watch arrivals and departures");
+        println!("22. Export GATT table to JSON or YAML");
     }
 
     pub fn get_user_choice(&self) -> u8 {
@@ -50,39 +77,259 @@ impl UserInterface {
         input.trim().parse().expect("Please enter a valid number")
     }
 
+    pub fn get_max_connections(&self) -> usize {
+        println!("Enter the maximum number of sensors to connect to at once:");
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input).expect("Failed to read line");
+        input.trim().parse().expect("Please enter a valid number")
+    }
+
     pub fn display_devices(&self, storage: &DeviceStorage) {
         for (id, device) in storage.list_devices() {
-            println!("ID: {}, MAC: {}, Name: {}, RSSI: {}", id, device.mac_address, device.name, device.rssi);
+            println!("ID: {}, MAC: {}, Name: {}, RSSI: {}, Last seen: {}", id, device.mac_address, device.name, device.rssi,
+                     device.last_seen.format("%Y-%m-%d %H:%M:%S"));
+            let present = if storage.is_present(id) { ", present" } else { "" };
+            println!("    First seen: {}{}", device.first_seen.format("%Y-%m-%d %H:%M:%S"), present);
+            if let Some(alias) = &device.alias {
+                println!("    Alias: {}", alias);
+            }
+            if let Some(location) = &device.location {
+                println!("    Location: {}", location);
+            }
+            if !device.tags.is_empty() {
+                println!("    Tags: {}", device.tags.iter().cloned().collect::<Vec<_>>().join(", "));
+            }
+            if !device.manufacturer_ids.is_empty() {
+                let manufacturers: Vec<String> = device
+                    .manufacturer_ids
+                    .iter()
+                    .map(|&id| match assigned_numbers::company_name(id) {
+                        Some(name) => format!("{} ({:#06x})", name, id),
+                        None => format!("{:#06x}", id),
+                    })
+                    .collect();
+                println!("    Manufacturer: {}", manufacturers.join(", "));
+            }
+            if device.adapters.len() > 1 {
+                let sightings: Vec<String> = device
+                    .adapters
+                    .iter()
+                    .map(|(adapter, sighting)| format!("{} {} dBm at {}", adapter, sighting.rssi, sighting.last_seen.format("%H:%M:%S")))
+                    .collect();
+                println!("    Seen by: {}", sightings.join(", "));
+            }
+            self.display_signal(storage, id);
+            if let Some(sensor) = &device.sensor {
+                println!("    {}", sensor);
+            }
+            for (key, value) in &device.metadata {
+                println!("    {}: {}", key, value);
+            }
+        }
+    }
+
+    fn display_signal(&self, storage: &DeviceStorage, id: u32) {
+        if let Some(signal) = storage.signal(id) {
+            println!("    Signal: {}", signal);
+        }
+    }
+
+    /// Prints what the user can do about a failed operation, if anything.
+    pub fn display_hint(&self, error: &BluetoothError) {
+        if let Some(hint) = error.hint() {
+            println!("Hint: {}", hint);
+        }
+    }
+
+    pub fn display_adapters(&self, adapters: &[AdapterEntry]) {
+        for adapter in adapters {
+            let marker = if adapter.selected { "*" } else { " " };
+            println!("{} {}: {}", marker, adapter.index, adapter.info);
+        }
+    }
+
+    pub fn display_connections(&self, connections: &[ConnectionInfo]) {
+        if connections.is_empty() {
+            println!("No connections opened yet");
+        }
+        for connection in connections {
+            let kept = if connection.pinned { ", kept open" } else { "" };
+            println!(
+                "{}: {}{}, {} in use, idle for {}s",
+                connection.mac_address, connection.state, kept, connection.users, connection.idle.as_secs()
+            );
         }
     }
 
     // Display only MJ_HT_V1 devices
     pub fn display_mj_ht_v1_devices(&self, storage: &DeviceStorage) {
         for (id, device) in storage.list_mj_ht_v1_devices() {
-            println!("ID: {}, MAC: {}, Name: {}, RSSI: {}", id, device.mac_address, device.name, device.rssi);
+            println!("ID: {}, MAC: {}, Name: {}, RSSI: {}", id, device.mac_address, labelled_name(device), device.rssi);
+            self.display_signal(storage, id);
+            if let Some(sensor) = &device.sensor {
+                println!("    {}", sensor);
+            }
+        }
+    }
+
+    // Display MJ_HT_V1 sensors and ATC / pvvx thermometers
+    pub fn display_thermometer_devices(&self, storage: &DeviceStorage) {
+        for (id, device) in storage.list_thermometer_devices() {
+            println!("ID: {}, MAC: {}, Name: {}, RSSI: {}", id, device.mac_address, labelled_name(device), device.rssi);
+            self.display_signal(storage, id);
+            if let Some(sensor) = &device.sensor {
+                println!("    {}", sensor);
+            }
+        }
+    }
+
+    // Display devices broadcasting sensor values
+    pub fn display_sensor_devices(&self, storage: &DeviceStorage) {
+        for (id, device) in storage.list_sensor_devices() {
+            if let Some(sensor) = &device.sensor {
+                println!("ID: {}, MAC: {}, Name: {}, RSSI: {}, {}", id, device.mac_address, labelled_name(device), device.rssi, sensor);
+            }
+        }
+    }
+
+    pub fn display_mj_ht_v1_reading(&self, reading: &MjHtV1Reading) {
+        println!("{} Temperature: {:.1} °C, Humidity: {:.1} %", reading.timestamp.format("%Y-%m-%d %H:%M:%S"), reading.temperature, reading.humidity);
+    }
+
+    /// One line per polled sensor with its reading or the reason it failed.
+    pub fn display_poll_results(&self, results: &[PollResult]) {
+        println!("{:>4}  {:<17}  {:<20}  {:<6}  {:>6}  Reading / error", "ID", "MAC", "Name", "Status", "Time");
+        for poll in results {
+            let (status, outcome) = match &poll.result {
+                Ok(reading) => ("ok", format!("{:.1} °C, {:.1} %", reading.temperature, reading.humidity)),
+                Err(e) => ("failed", e.to_string()),
+            };
+            println!(
+                "{:>4}  {:<17}  {:<20}  {:<6}  {:>5.1}s  {}",
+                poll.id, poll.mac_address, poll.name, status, poll.elapsed.as_secs_f32(), outcome
+            );
         }
+        let succeeded = results.iter().filter(|poll| poll.result.is_ok()).count();
+        println!("{} of {} sensors read", succeeded, results.len());
+    }
+
+    pub fn display_presence_event(&self, event: &PresenceEvent) {
+        println!("{}", event);
     }
 
-    pub fn get_device_id(&self) -> u32 {
-        println!("Enter the internal ID of the device:");
+    /// Waits in the background for the user to press Enter.
+    pub fn spawn_wait_for_enter(&self) -> tokio::task::JoinHandle<()> {
+        println!("Press Enter to stop...");
+        tokio::task::spawn_blocking(|| {
+            let mut input = String::new();
+            let _ = std::io::stdin().read_line(&mut input);
+        })
+    }
+
+    /// Asks for a device by internal ID, alias or MAC address.
+    pub fn get_device_id(&self, storage: &DeviceStorage) -> Option<u32> {
+        println!("Enter the internal ID, alias or MAC address of the device:");
         let mut input = String::new();
         std::io::stdin().read_line(&mut input).expect("Failed to read line");
-        input.trim().parse().expect("Please enter a valid number")
+        let device_id = storage.find_device(input.trim());
+        if device_id.is_none() {
+            println!("Device '{}' not found", input.trim());
+        }
+        device_id
     }
 
-    // Adjusted method to return a Result<String, std::io::Error>
+    /// Asks for alias, location and tags; empty input keeps the current value.
+    pub fn get_labels(&self) -> Result<Labels, std::io::Error> {
+        let read = |prompt: &str| -> Result<String, std::io::Error> {
+            println!("{}", prompt);
+            let mut input = String::new();
+            std::io::stdin().read_line(&mut input)?;
+            Ok(input.trim().to_string())
+        };
+        let alias = read("Enter the alias (empty keeps it, '-' clears it):")?;
+        let location = read("Enter the location (empty keeps it, '-' clears it):")?;
+        let tags = read("Enter tags separated by commas, '-tag' removes one (empty keeps them):")?;
+
+        let label = |value: String| match value.as_str() {
+            "" => None,
+            "-" => Some(String::new()),
+            _ => Some(value),
+        };
+        let tags = tags.split(',').map(|tag| tag.trim().to_string()).filter(|tag| !tag.is_empty()).collect();
+        Ok(Labels { alias: label(alias), location: label(location), tags })
+    }
+
+    /// Asks for a service by UUID, short UUID or name; returns the lowercase hyphenated UUID.
     pub fn get_service_uuid(&self) -> Result<String, std::io::Error> {
-        println!("Enter the service UUID:");
+        println!("Enter the service UUID or name (e.g. 180f or Battery Service):");
         let mut input = String::new();
         std::io::stdin().read_line(&mut input)?;
-        Ok(input.trim().to_string())
+        assigned_numbers::parse_service_uuid(&input)
+            .map(|uuid| uuid.to_string())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
     }
-    
-    // This method is already correctly returning a String, but adjust for error handling
+
+    /// Asks for a characteristic by UUID, short UUID or name; returns the lowercase hyphenated UUID.
     pub fn get_characteristic_uuid(&self) -> Result<String, std::io::Error> {
-        println!("Enter the characteristic UUID:");
+        println!("Enter the characteristic UUID or name (e.g. 2a19 or Battery Level):");
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        assigned_numbers::parse_characteristic_uuid(&input)
+            .map(|uuid| uuid.to_string())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+    }
+
+    /// Asks how the value is typed in; empty input means hex.
+    pub fn get_value_format(&self) -> Result<ValueFormat, std::io::Error> {
+        let names: Vec<String> = ValueFormat::ALL.iter().map(|format| format.to_string()).collect();
+        println!("Enter the value format ({}) [hex]:", names.join(", "));
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        match input.trim() {
+            "" => Ok(ValueFormat::Hex),
+            name => ValueFormat::from_str(name, true).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e)),
+        }
+    }
+
+    pub fn get_value(&self) -> Result<String, std::io::Error> {
+        println!("Enter the value:");
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        Ok(input.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    /// Asks whether the device should acknowledge the write; empty input means yes.
+    pub fn get_write_type(&self) -> Result<WriteType, std::io::Error> {
+        println!("Wait for a write response? (y/n) [y]:");
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        Ok(match input.trim().to_lowercase().as_str() {
+            "n" | "no" => WriteType::WithoutResponse,
+            _ => WriteType::WithResponse,
+        })
+    }
+
+    /// Asks for the file to export to; the extension picks JSON or YAML.
+    pub fn get_export_path(&self) -> Result<PathBuf, std::io::Error> {
+        println!("Enter the file to write (.json, .yaml or .yml):");
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        Ok(PathBuf::from(input.trim()))
+    }
+
+    /// Asks whether readable values go into the export; empty input means no.
+    pub fn get_read_values(&self) -> Result<bool, std::io::Error> {
+        println!("Read the values of readable characteristics? (y/n) [n]:");
         let mut input = String::new();
         std::io::stdin().read_line(&mut input)?;
-        Ok(input.trim().to_string())
+        Ok(matches!(input.trim().to_lowercase().as_str(), "y" | "yes"))
+    }
+}
+
+/// The advertised name followed by the alias, if any: `MJ_HT_V1 (kitchen)`.
+fn labelled_name(device: &BluetoothDevice) -> String {
+    match &device.alias {
+        Some(alias) => format!("{} ({})", device.name, alias),
+        None => device.name.clone(),
     }
 }
@@ -0,0 +1,182 @@
+//! Encoding of user input into characteristic values.
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::fmt;
+
+/// How a value typed by the user is turned into bytes.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueFormat {
+    /// Hex digits, e.g. `01ff`
+    #[default]
+    Hex,
+    /// UTF-8 text, sent as given (spaces included) without terminator
+    Utf8,
+    U8,
+    U16le,
+    U16be,
+    U32le,
+    U32be,
+    I8,
+    I16le,
+    I16be,
+    I32le,
+    I32be,
+}
+
+impl ValueFormat {
+    pub const ALL: [ValueFormat; 12] = [
+        ValueFormat::Hex,
+        ValueFormat::Utf8,
+        ValueFormat::U8,
+        ValueFormat::U16le,
+        ValueFormat::U16be,
+        ValueFormat::U32le,
+        ValueFormat::U32be,
+        ValueFormat::I8,
+        ValueFormat::I16le,
+        ValueFormat::I16be,
+        ValueFormat::I32le,
+        ValueFormat::I32be,
+    ];
+
+    /// Encodes `input`, checking integers fit the width and signedness of the format.
+    /// Surrounding whitespace is ignored, except in text.
+    pub fn encode(self, input: &str) -> Result<Vec<u8>, String> {
+        let (width, signed, little_endian) = match self {
+            ValueFormat::Hex => {
+                let input = input.trim();
+                let digits: String = input.strip_prefix("0x").or_else(|| input.strip_prefix("0X")).unwrap_or(input).split_whitespace().collect();
+                return hex::decode(&digits).map_err(|e| format!("Invalid hex value '{}': {}", input, e));
+            }
+            ValueFormat::Utf8 => return Ok(input.as_bytes().to_vec()),
+            ValueFormat::U8 => (1, false, true),
+            ValueFormat::U16le => (2, false, true),
+            ValueFormat::U16be => (2, false, false),
+            ValueFormat::U32le => (4, false, true),
+            ValueFormat::U32be => (4, false, false),
+            ValueFormat::I8 => (1, true, true),
+            ValueFormat::I16le => (2, true, true),
+            ValueFormat::I16be => (2, true, false),
+            ValueFormat::I32le => (4, true, true),
+            ValueFormat::I32be => (4, true, false),
+        };
+
+        let input = input.trim();
+        let number = parse_integer(input).ok_or_else(|| format!("Invalid integer '{}'", input))?;
+        let bits = width * 8;
+        let (min, max) = if signed {
+            (-(1i64 << (bits - 1)), (1i64 << (bits - 1)) - 1)
+        } else {
+            (0, (1i64 << bits) - 1)
+        };
+        if number < min || number > max {
+            return Err(format!("{} does not fit {} (range {}..={})", number, self, min, max));
+        }
+
+        let bytes = number.to_le_bytes()[..width].to_vec();
+        Ok(if little_endian { bytes } else { bytes.into_iter().rev().collect() })
+    }
+}
+
+impl fmt::Display for ValueFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default();
+        write!(f, "{}", name)
+    }
+}
+
+/// Decimal, or hex with a `0x` prefix, with at most one sign in front.
+fn parse_integer(input: &str) -> Option<i64> {
+    let (negative, rest) = match input.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, input.strip_prefix('+').unwrap_or(input)),
+    };
+    let (radix, digits) = match rest.strip_prefix("0x").or_else(|| rest.strip_prefix("0X")) {
+        Some(hex) => (16, hex),
+        None => (10, rest),
+    };
+    // `from_str_radix` would accept another sign of its own
+    if digits.starts_with(['+', '-']) {
+        return None;
+    }
+    let magnitude = i64::from_str_radix(digits, radix).ok()?;
+    Some(if negative { -magnitude } else { magnitude })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_integers() {
+        let cases: Vec<(ValueFormat, &str, &[u8])> = vec![
+            (ValueFormat::U8, "0", &[0x00]),
+            (ValueFormat::U8, "255", &[0xff]),
+            (ValueFormat::U8, " 0x2a ", &[0x2a]),
+            (ValueFormat::U16le, "0x1234", &[0x34, 0x12]),
+            (ValueFormat::U16be, "0x1234", &[0x12, 0x34]),
+            (ValueFormat::U16le, "65535", &[0xff, 0xff]),
+            (ValueFormat::U32le, "0x12345678", &[0x78, 0x56, 0x34, 0x12]),
+            (ValueFormat::U32be, "4294967295", &[0xff, 0xff, 0xff, 0xff]),
+            (ValueFormat::I8, "-1", &[0xff]),
+            (ValueFormat::I8, "-128", &[0x80]),
+            (ValueFormat::I8, "127", &[0x7f]),
+            (ValueFormat::I16le, "-2", &[0xfe, 0xff]),
+            (ValueFormat::I16be, "-2", &[0xff, 0xfe]),
+            (ValueFormat::I16le, "-0x8000", &[0x00, 0x80]),
+            (ValueFormat::I16le, "+0X10", &[0x10, 0x00]),
+            (ValueFormat::I32le, "-1000", &[0x18, 0xfc, 0xff, 0xff]),
+            (ValueFormat::I32be, "2147483647", &[0x7f, 0xff, 0xff, 0xff]),
+        ];
+        for (format, input, expected) in cases {
+            assert_eq!(format.encode(input).as_deref(), Ok(expected), "{} {:?}", format, input);
+        }
+    }
+
+    #[test]
+    fn rejects_integers_out_of_range() {
+        let cases = [
+            (ValueFormat::U8, "256"),
+            (ValueFormat::U8, "-1"),
+            (ValueFormat::U16le, "65536"),
+            (ValueFormat::U32be, "0x100000000"),
+            (ValueFormat::I8, "128"),
+            (ValueFormat::I8, "-129"),
+            (ValueFormat::I16be, "32768"),
+            (ValueFormat::I32le, "-2147483649"),
+            (ValueFormat::U8, "twelve"),
+            (ValueFormat::U8, ""),
+            (ValueFormat::I16le, "1.5"),
+            (ValueFormat::I8, "--5"),
+            (ValueFormat::I8, "-0x-5"),
+            (ValueFormat::U8, "0x+5"),
+            (ValueFormat::U8, "+-5"),
+            (ValueFormat::U8, "-"),
+            (ValueFormat::U8, "0x"),
+        ];
+        for (format, input) in cases {
+            assert!(format.encode(input).is_err(), "{} {:?}", format, input);
+        }
+    }
+
+    #[test]
+    fn encodes_hex() {
+        assert_eq!(ValueFormat::Hex.encode("01ff"), Ok(vec![0x01, 0xff]));
+        assert_eq!(ValueFormat::Hex.encode(" 0x01ff "), Ok(vec![0x01, 0xff]));
+        assert_eq!(ValueFormat::Hex.encode("0X01FF"), Ok(vec![0x01, 0xff]));
+        assert_eq!(ValueFormat::Hex.encode("01 02 03"), Ok(vec![0x01, 0x02, 0x03]));
+        assert_eq!(ValueFormat::Hex.encode(""), Ok(vec![]));
+        // Only one prefix is stripped
+        assert!(ValueFormat::Hex.encode("0x0x01").is_err());
+        assert!(ValueFormat::Hex.encode("0f0").is_err());
+        assert!(ValueFormat::Hex.encode("zz").is_err());
+    }
+
+    #[test]
+    fn text_keeps_its_spaces() {
+        assert_eq!(ValueFormat::Utf8.encode(" hi there "), Ok(b" hi there ".to_vec()));
+        assert_eq!(ValueFormat::Utf8.encode("°C"), Ok(vec![0xc2, 0xb0, 0x43]));
+    }
+}